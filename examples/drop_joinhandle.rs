@@ -10,10 +10,10 @@ fn main() {
     let rt = Runtime::new();
     rt.block_on(async {
         let (tx, rx) = mpsc::unbounded::channel();
-        woi::spawn(async {
-            let tx = tx.clone();
+        let tx1 = tx.clone();
+        woi::spawn(async move {
             println!("Sending message from task 1");
-            tx.send("task 1: fly.io").unwrap()
+            tx1.send("task 1: fly.io").unwrap()
         });
 
         woi::spawn(async move {
@@ -11,10 +11,10 @@ fn main() {
     rt.block_on(async {
         let (tx, rx) = mpsc::unbounded::channel();
 
-        let h1 = woi::spawn(async {
-            let tx = tx.clone();
+        let tx1 = tx.clone();
+        let h1 = woi::spawn(async move {
             println!("Sending message from handle 1");
-            tx.send("hello").unwrap()
+            tx1.send("hello").unwrap()
         });
 
         let h2 = woi::spawn(async move {
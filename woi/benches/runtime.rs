@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::hint::black_box;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use woi::Runtime;
+
+const ITERS: u32 = 100_000;
+
+fn main() {
+    bench("spawn", spawn_only);
+    bench("spawn + complete", spawn_complete);
+    bench("yield (context switch)", yield_task);
+    bench("waker clone + drop", waker_clone_drop);
+}
+
+// Runs `f` once to warm up, then once more timed, and prints the
+// time taken per iteration
+fn bench(name: &str, f: fn(u32) -> Duration) {
+    f(ITERS / 10);
+    let elapsed = f(ITERS);
+    println!(
+        "{:<24} {:>8.1} ns/iter",
+        name,
+        elapsed.as_nanos() as f64 / ITERS as f64
+    );
+}
+
+// Spawns tasks without running them, timing only the spawns. Each one
+// allocates a task and writes its header, scheduler and future at the
+// offsets of the task's layout
+fn spawn_only(iters: u32) -> Duration {
+    let rt = Runtime::new();
+    rt.block_on(async {
+        let start = Instant::now();
+        for i in 0..iters {
+            black_box(woi::spawn(async move { black_box(i) }));
+        }
+        start.elapsed()
+    })
+}
+
+// Spawns tasks that complete on their first poll and awaits each of them
+fn spawn_complete(iters: u32) -> Duration {
+    let rt = Runtime::new();
+    rt.block_on(async {
+        let start = Instant::now();
+        for i in 0..iters {
+            let handle = woi::spawn(async move { black_box(i) });
+            black_box(handle.await.unwrap());
+        }
+        start.elapsed()
+    })
+}
+
+// A single task that yields back to the scheduler `iters` times
+fn yield_task(iters: u32) -> Duration {
+    let rt = Runtime::new();
+    rt.block_on(async {
+        let start = Instant::now();
+        woi::spawn(async move {
+            for _ in 0..iters {
+                YieldNow(false).await;
+            }
+        })
+        .await
+        .unwrap();
+        start.elapsed()
+    })
+}
+
+// Clones and drops the waker of a spawned task
+fn waker_clone_drop(iters: u32) -> Duration {
+    let rt = Runtime::new();
+    rt.block_on(async {
+        woi::spawn(futures::future::poll_fn(move |cx| {
+            let start = Instant::now();
+            for _ in 0..iters {
+                drop(black_box(cx.waker().clone()));
+            }
+            Poll::Ready(start.elapsed())
+        }))
+        .await
+        .unwrap()
+    })
+}
+
+// Wakes itself and returns pending the first time it is polled
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
@@ -13,6 +13,28 @@ impl<T> fmt::Display for SendError<T> {
     }
 }
 
+// ===== Send Timeout Error =====
+
+/// Error returned by `send_timeout`. Either way, the message is handed back
+#[derive(Debug)]
+pub enum SendTimeoutError<T> {
+    /// There was no space in the channel before the timeout
+    Timeout(T),
+    /// The receiver was dropped
+    Closed(T),
+}
+
+impl<T: fmt::Debug> Error for SendTimeoutError<T> {}
+
+impl<T> fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            SendTimeoutError::Timeout(_) => write!(f, "timed out waiting on send operation"),
+            SendTimeoutError::Closed(_) => write!(f, "sending on a closed channel"),
+        }
+    }
+}
+
 // ===== Try Send Error =====
 
 // Currently the same as the Send Error. This is because bounded channels
@@ -49,3 +71,17 @@ impl fmt::Display for TryRecvError {
         }
     }
 }
+
+// ===== Recv Error =====
+
+/// The sending half of a oneshot channel was dropped without sending a value
+#[derive(Debug)]
+pub struct RecvError;
+
+impl Error for RecvError {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "receiving on a closed channel")
+    }
+}
@@ -1,72 +1,126 @@
 use core::ptr;
 
-use super::semaphore::Waiter;
+/// A node of a [`LinkedList`]. It keeps the pointers to its neighbours
+/// itself, so linking it in doesn't allocate
+pub(crate) trait Link {
+    fn next(&mut self) -> &mut *mut Self;
 
-pub(crate) struct LinkedList {
-    head: *mut Waiter,
-    tail: *mut Waiter,
+    fn prev(&mut self) -> &mut *mut Self;
+}
+
+pub(crate) struct LinkedList<T> {
+    head: *mut T,
+    tail: *mut T,
 }
 
 #[allow(unused)]
-impl LinkedList {
-    pub fn new() -> LinkedList {
+impl<T: Link> LinkedList<T> {
+    pub fn new() -> LinkedList<T> {
         LinkedList {
             head: ptr::null_mut(),
             tail: ptr::null_mut(),
         }
     }
 
-    pub fn push_front(&mut self, value: *mut Waiter) {
+    pub fn push_front(&mut self, value: *mut T) {
+        if self.head.is_null() {
+            self.tail = value;
+        } else {
+            unsafe {
+                *(*self.head).prev() = value;
+            }
+        }
         unsafe {
-            (*value).next = self.head;
+            *(*value).next() = self.head;
         }
         self.head = value;
     }
 
-    pub fn push_back(&mut self, waiter: *mut Waiter) {
+    pub fn push_back(&mut self, value: *mut T) {
         if self.head.is_null() {
-            self.head = waiter;
-            self.tail = waiter;
+            self.head = value;
+            self.tail = value;
         } else {
             unsafe {
-                (*self.tail).next = waiter;
+                *(*self.tail).next() = value;
+                *(*value).prev() = self.tail;
             }
-            self.tail = waiter
+            self.tail = value
         }
     }
 
-    pub fn pop_front(&mut self) -> Option<&Waiter> {
+    pub fn pop_front(&mut self) -> Option<&mut T> {
         if self.head.is_null() {
             return None;
         }
 
         if self.head == self.tail {
-            let waiter = unsafe { &mut *self.head };
+            let value = unsafe { &mut *self.head };
             self.head = ptr::null_mut();
             self.tail = ptr::null_mut();
-            Some(waiter)
+            Some(value)
         } else {
-            let waiter = unsafe { &mut *self.head };
-            self.head = waiter.next;
-            waiter.next = ptr::null_mut();
-            Some(waiter)
+            let value = unsafe { &mut *self.head };
+            self.head = *value.next();
+            *value.next() = ptr::null_mut();
+            unsafe {
+                *(*self.head).prev() = ptr::null_mut();
+            }
+            Some(value)
+        }
+    }
+
+    /// Unlinks a value from anywhere in the list. The value must be in the
+    /// list
+    pub fn remove(&mut self, value: *mut T) {
+        unsafe {
+            let prev = *(*value).prev();
+            let next = *(*value).next();
+
+            if prev.is_null() {
+                self.head = next;
+            } else {
+                *(*prev).next() = next;
+            }
+
+            if next.is_null() {
+                self.tail = prev;
+            } else {
+                *(*next).prev() = prev;
+            }
+
+            *(*value).prev() = ptr::null_mut();
+            *(*value).next() = ptr::null_mut();
         }
     }
 
-    pub fn pop_back(&mut self) -> Option<&Waiter> {
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// The value at the back of the list, or null if it's empty
+    pub fn back(&self) -> *mut T {
+        self.tail
+    }
+
+    pub fn pop_back(&mut self) -> Option<&mut T> {
         if self.tail.is_null() {
             return None;
         }
 
         if self.head == self.tail {
-            let waiter = unsafe { &mut *self.head };
+            let value = unsafe { &mut *self.head };
             self.head = ptr::null_mut();
             self.tail = ptr::null_mut();
-            Some(waiter)
+            Some(value)
         } else {
-            let waiter = unsafe { &mut *self.tail };
-            self.tail = waiter.prev;
-            Some(waiter)
+            let value = unsafe { &mut *self.tail };
+            self.tail = *value.prev();
+            *value.prev() = ptr::null_mut();
+            unsafe {
+                *(*self.tail).next() = ptr::null_mut();
+            }
+            Some(value)
         }
     }
 }
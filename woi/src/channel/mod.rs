@@ -1,7 +1,12 @@
 mod linked_list;
 mod semaphore;
 
+mod notify;
+pub use notify::{Notified, Notify};
+
 pub mod error;
 
 pub mod mpsc;
 pub use mpsc::{bounded, unbounded};
+
+pub mod oneshot;
@@ -2,19 +2,26 @@
 //! asynchronous tasks.
 
 use std::rc::Rc;
+use std::time::Duration;
 
 use futures::future::poll_fn;
 
 use super::channel::Channel;
-use crate::channel::error::{SendError, TryRecvError};
+use crate::channel::error::{SendError, SendTimeoutError, TryRecvError};
 
 pub fn channel<T>(size: usize) -> (Sender<T>, Receiver<T>) {
     let chan = Rc::new(Channel::new(size));
     (Sender::new(chan.clone()), Receiver::new(chan))
 }
 
+/// A reserved slot in the channel, for sending one message
+///
+/// Dropping the permit without sending gives the slot back
 pub struct Permit<T> {
     chan: Rc<Channel<T>>,
+    // Once a message is sent, the slot is only given back when the message
+    // is received
+    sent: bool,
 }
 
 pub struct Sender<T> {
@@ -44,12 +51,33 @@ impl<T> Sender<T> {
             Ok(_) => {
                 let permit = Permit {
                     chan: self.chan.clone(),
+                    sent: false,
                 };
                 Ok(permit)
             }
             Err(_) => Err(SendError(())),
         }
     }
+
+    /// Sends a message, waiting up to `duration` for space in the channel
+    ///
+    /// The message is handed back in the error if the channel is still full
+    /// once `duration` has passed, or if the receiver is dropped. It's only
+    /// moved into the channel once there is space, so no message is lost if
+    /// this future is dropped early
+    pub async fn send_timeout(
+        &self,
+        message: T,
+        duration: Duration,
+    ) -> Result<(), SendTimeoutError<T>> {
+        match crate::time::timeout(duration, self.reserve()).await {
+            Ok(Ok(permit)) => permit
+                .send(message)
+                .map_err(|SendError(message)| SendTimeoutError::Closed(message)),
+            Ok(Err(_)) => Err(SendTimeoutError::Closed(message)),
+            Err(_) => Err(SendTimeoutError::Timeout(message)),
+        }
+    }
 }
 
 impl<T> Clone for Sender<T> {
@@ -85,6 +113,27 @@ impl<T> Receiver<T> {
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
         self.chan.try_recv()
     }
+
+    /// Receives up to `limit` messages into `buf` at once, returning how
+    /// many were received
+    ///
+    /// Only takes the messages that are already in the channel, waiting if
+    /// there are none. Returns 0 once the channel is closed and empty, or if
+    /// `limit` is 0
+    pub async fn recv_many(&self, buf: &mut Vec<T>, limit: usize) -> usize {
+        poll_fn(|cx| self.chan.recv_many(cx, buf, limit)).await
+    }
+
+    /// Receives messages until every sender has been dropped and returns
+    /// them in the order they were sent. If the channel is already closed
+    /// and empty, the returned `Vec` is empty
+    pub async fn collect(&self) -> Vec<T> {
+        let mut messages = Vec::new();
+        while let Some(message) = self.recv().await {
+            messages.push(message);
+        }
+        messages
+    }
 }
 
 impl<T> Drop for Receiver<T> {
@@ -97,13 +146,93 @@ impl<T> Drop for Receiver<T> {
 // ===== impl Permit =====
 
 impl<T> Permit<T> {
-    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
-        self.chan.send(message)
+    pub fn send(mut self, message: T) -> Result<(), SendError<T>> {
+        let res = self.chan.send(message);
+        self.sent = res.is_ok();
+        res
     }
 }
 
 impl<T> Drop for Permit<T> {
     fn drop(&mut self) {
-        self.chan.semaphore().release()
+        if !self.sent {
+            self.chan.semaphore().release()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::poll_once;
+    use crate::Runtime;
+
+    #[test]
+    fn recv_many_releases_permits() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = channel(4);
+            let mut buf = Vec::new();
+            for i in 0..4 {
+                tx.send(i).await.unwrap();
+            }
+            // Full, so another send has to wait
+            assert!(poll_once(tx.send(4)).is_pending());
+
+            // Each message taken gives its permit back, however many are
+            // taken at once
+            assert_eq!(rx.recv_many(&mut buf, 3).await, 3);
+            for i in 4..7 {
+                assert!(poll_once(tx.send(i)).is_ready());
+            }
+            assert!(poll_once(tx.send(7)).is_pending());
+
+            assert_eq!(rx.recv_many(&mut buf, 10).await, 4);
+            assert_eq!(buf, vec![0, 1, 2, 3, 4, 5, 6]);
+        });
+    }
+
+    #[test]
+    fn collect_until_senders_drop() {
+        let rt = Runtime::new();
+        let messages = rt.block_on(async {
+            // More messages than fit, so the sender waits for `collect` to
+            // make room
+            let (tx, rx) = channel(2);
+            crate::spawn(async move {
+                for i in 0..5 {
+                    tx.send(i).await.unwrap();
+                }
+            });
+            rx.collect().await
+        });
+        assert_eq!(messages, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn send_timeout_on_full_channel() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = channel(1);
+            tx.send(1).await.unwrap();
+
+            let err = tx
+                .send_timeout(2, Duration::from_millis(10))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, SendTimeoutError::Timeout(2)));
+
+            // Receiving frees up the slot again
+            assert_eq!(rx.recv().await, Some(1));
+            tx.send_timeout(3, Duration::from_millis(10)).await.unwrap();
+            assert_eq!(rx.recv().await, Some(3));
+
+            drop(rx);
+            let err = tx
+                .send_timeout(4, Duration::from_millis(10))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, SendTimeoutError::Closed(4)));
+        });
     }
 }
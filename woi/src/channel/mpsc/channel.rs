@@ -3,13 +3,19 @@ use std::collections::VecDeque;
 use std::task::{Context, Poll, Waker};
 
 use crate::channel::error::{SendError, TryRecvError};
-use crate::channel::semaphore::{Acquire, Semaphore};
+use crate::channel::semaphore::Semaphore;
+use futures::ready;
+
+use crate::task::{self, coop, WakeSource};
 
 pub struct Channel<T> {
     // Inner state of the channel
     inner: RefCell<Inner<T>>,
     // Controls access to the channel
     semaphore: Semaphore,
+    // Whether sending takes up a permit. Receiving a message from a bounded
+    // channel gives the permit back
+    bounded: bool,
 }
 
 struct Inner<T> {
@@ -31,8 +37,19 @@ enum State {
 
 impl<T> Channel<T> {
     pub fn new(size: usize) -> Channel<T> {
+        Self::with_capacity(size, true)
+    }
+
+    /// Creates a channel that senders don't need permits for. `capacity` is
+    /// only the initial capacity of the queue
+    pub fn unbounded(capacity: usize) -> Channel<T> {
+        Self::with_capacity(capacity, false)
+    }
+
+    fn with_capacity(size: usize, bounded: bool) -> Channel<T> {
         Channel {
             semaphore: Semaphore::new(size),
+            bounded,
             inner: RefCell::new(Inner {
                 queue: VecDeque::with_capacity(size),
                 tx_count: 1,
@@ -46,13 +63,22 @@ impl<T> Channel<T> {
     pub fn wake_rx(&self) {
         let mut inner = self.inner.borrow_mut();
         if let Some(waker) = inner.rx_waker.take() {
-            waker.wake();
+            task::wake(waker, WakeSource::Channel);
         }
     }
 
     pub fn close(&self) {
         let mut inner = self.inner.borrow_mut();
         inner.state = State::Closed;
+        // Wake a waiting receiver so it sees the channel is closed
+        // instead of waiting on a message that will never arrive
+        let rx_waker = inner.rx_waker.take();
+        drop(inner);
+        if let Some(waker) = rx_waker {
+            task::wake(waker, WakeSource::Channel);
+        }
+        // Senders waiting on capacity fail instead of waiting forever
+        self.semaphore.close();
     }
 
     pub fn incr_tx_count(&self) {
@@ -79,7 +105,7 @@ impl<T> Channel<T> {
             State::Open => {
                 inner.queue.push_back(message);
                 if let Some(rx_waker) = &inner.rx_waker {
-                    rx_waker.wake_by_ref();
+                    task::wake_by_ref(rx_waker, WakeSource::Channel);
                 }
                 Ok(())
             }
@@ -87,13 +113,29 @@ impl<T> Channel<T> {
         }
     }
 
+    // Frees up the slot a received message took up
+    fn release_slot(&self) {
+        if self.bounded {
+            self.semaphore.release();
+        }
+    }
+
     pub fn recv(&self, cx: &mut Context) -> Poll<Option<T>> {
+        // A receiver that's always ready would otherwise never let the task
+        // yield
+        ready!(coop::poll_proceed(cx));
+
         let mut inner = self.inner.borrow_mut();
         match inner.queue.pop_front() {
             // If there is a message, regardless if the channel is closed,
             // we read the message. This allows us to read any outstanding
             // messages in the event the channel is closed
-            Some(message) => Poll::Ready(Some(message)),
+            Some(message) => {
+                drop(inner);
+                self.release_slot();
+                coop::spend();
+                Poll::Ready(Some(message))
+            }
             // If the channel is still open, then we know it's just
             // empty temporarily and could be populated in future. We
             // register the rx waker to be woken when a new task is pushed
@@ -119,10 +161,45 @@ impl<T> Channel<T> {
         }
     }
 
+    /// Moves up to `limit` messages into `buf`, returning how many were
+    /// moved. Waits for at least one unless the channel is closed, in which
+    /// case 0 is returned
+    pub fn recv_many(&self, cx: &mut Context, buf: &mut Vec<T>, limit: usize) -> Poll<usize> {
+        if limit == 0 {
+            return Poll::Ready(0);
+        }
+
+        ready!(coop::poll_proceed(cx));
+
+        let mut inner = self.inner.borrow_mut();
+        let count = limit.min(inner.queue.len());
+        if count > 0 {
+            buf.extend(inner.queue.drain(..count));
+            drop(inner);
+            for _ in 0..count {
+                self.release_slot();
+            }
+            coop::spend();
+            return Poll::Ready(count);
+        }
+
+        match inner.state {
+            State::Open => {
+                inner.rx_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Closed => Poll::Ready(0),
+        }
+    }
+
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
         let mut inner = self.inner.borrow_mut();
         match inner.queue.pop_front() {
-            Some(message) => Ok(message),
+            Some(message) => {
+                drop(inner);
+                self.release_slot();
+                Ok(message)
+            }
             None => match inner.state {
                 State::Open => Err(TryRecvError::Empty),
                 State::Closed => Err(TryRecvError::Disconnected),
@@ -9,7 +9,7 @@ use super::channel::Channel;
 use crate::channel::error::{SendError, TryRecvError};
 
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
-    let chan = Rc::new(Channel::new(128));
+    let chan = Rc::new(Channel::unbounded(128));
     (Sender::new(chan.clone()), Receiver::new(chan))
 }
 
@@ -68,6 +68,27 @@ impl<T> Receiver<T> {
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
         self.chan.try_recv()
     }
+
+    /// Receives up to `limit` messages into `buf` at once, returning how
+    /// many were received
+    ///
+    /// Only takes the messages that are already in the channel, waiting if
+    /// there are none. Returns 0 once the channel is closed and empty, or if
+    /// `limit` is 0
+    pub async fn recv_many(&self, buf: &mut Vec<T>, limit: usize) -> usize {
+        poll_fn(|cx| self.chan.recv_many(cx, buf, limit)).await
+    }
+
+    /// Receives messages until every sender has been dropped and returns
+    /// them in the order they were sent. If the channel is already closed
+    /// and empty, the returned `Vec` is empty
+    pub async fn collect(&self) -> Vec<T> {
+        let mut messages = Vec::new();
+        while let Some(message) = self.recv().await {
+            messages.push(message);
+        }
+        messages
+    }
 }
 
 impl<T> Drop for Receiver<T> {
@@ -75,4 +96,70 @@ impl<T> Drop for Receiver<T> {
         tracing::debug!("Dropping receiver");
         self.chan.close();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn recv_many_drains_burst() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = channel();
+            let mut buf = Vec::new();
+
+            // Waits for the burst, then takes it in one go
+            let sender = crate::spawn(async move {
+                for i in 0..5 {
+                    tx.send(i).unwrap();
+                }
+                tx
+            });
+            assert_eq!(rx.recv_many(&mut buf, 10).await, 5);
+            assert_eq!(buf, vec![0, 1, 2, 3, 4]);
+
+            // Stops at the limit
+            let tx = sender.await.unwrap();
+            for i in 5..8 {
+                tx.send(i).unwrap();
+            }
+            assert_eq!(rx.recv_many(&mut buf, 2).await, 2);
+            assert_eq!(rx.recv_many(&mut buf, 10).await, 1);
+            assert_eq!(buf, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+
+            drop(tx);
+            assert_eq!(rx.recv_many(&mut buf, 10).await, 0);
+        });
+    }
+
+    #[test]
+    fn collect_until_senders_drop() {
+        let rt = Runtime::new();
+        let messages = rt.block_on(async {
+            let (tx, rx) = channel();
+            let tx2 = tx.clone();
+            tx.send(1).unwrap();
+            tx.send(2).unwrap();
+            drop(tx);
+
+            crate::spawn(async move {
+                tx2.send(3).unwrap();
+            });
+            rx.collect().await
+        });
+        assert_eq!(messages, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn collect_closed_empty_channel() {
+        let rt = Runtime::new();
+        let messages = rt.block_on(async {
+            let (tx, rx) = channel::<i32>();
+            drop(tx);
+            rx.collect().await
+        });
+        assert!(messages.is_empty());
+    }
+}
@@ -0,0 +1,377 @@
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::marker::PhantomPinned;
+use core::pin::Pin;
+use core::ptr;
+use core::task::{Context, Poll, Waker};
+
+use super::linked_list::{Link, LinkedList};
+use crate::task::{self, WakeSource};
+
+/// Notifies tasks waiting for an event
+///
+/// A task waits by awaiting [`Notify::notified`]. [`notify_one`] wakes the
+/// task that has been waiting longest, and [`notify_waiters`] wakes every
+/// task that is waiting when it's called.
+///
+/// If no task is waiting, `notify_one` stores a permit instead and the next
+/// call to `notified` completes straight away. There is at most one permit,
+/// however many times `notify_one` is called. `notify_waiters` never stores
+/// one.
+///
+/// Waiters live in the futures that wait, so neither waiting nor notifying
+/// allocates
+///
+/// ```
+/// use std::rc::Rc;
+/// use woi::channel::Notify;
+///
+/// let rt = woi::Runtime::new();
+/// rt.block_on(async {
+///     let notify = Rc::new(Notify::new());
+///     let waiter = {
+///         let notify = notify.clone();
+///         woi::spawn(async move { notify.notified().await })
+///     };
+///
+///     notify.notify_one();
+///     waiter.await.unwrap();
+/// });
+/// ```
+///
+/// [`notify_one`]: Notify::notify_one
+/// [`notify_waiters`]: Notify::notify_waiters
+pub struct Notify {
+    waiters: RefCell<LinkedList<Waiter>>,
+    /// Stored by `notify_one` while no one is waiting
+    permit: Cell<bool>,
+}
+
+/// Future returned by [`Notify::notified`]
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Waiter,
+}
+
+// The waiter is linked into the waitlist by address, so it must not move
+// once it has been queued. `PhantomPinned` makes it (and `Notified`)
+// `!Unpin` so that can't happen once the future has been polled
+struct Waiter {
+    waker: Option<Waker>,
+    next: *mut Waiter,
+    prev: *mut Waiter,
+    // Whether the waiter is in the waitlist
+    queued: bool,
+    // How the waiter was notified, if it has been
+    notified: Option<Notification>,
+    _pin: PhantomPinned,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Notification {
+    One,
+    All,
+}
+
+// ===== impl Notify =====
+
+impl Notify {
+    pub fn new() -> Notify {
+        Notify {
+            waiters: RefCell::new(LinkedList::new()),
+            permit: Cell::new(false),
+        }
+    }
+
+    /// Waits for a notification
+    ///
+    /// The future joins the waitlist when it's first polled, so only
+    /// `notify_waiters` calls made after that wake it. A permit stored by
+    /// `notify_one` is taken on that first poll too
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: Waiter::new(),
+        }
+    }
+
+    /// Wakes the task that has been waiting longest, or stores a permit for
+    /// the next one if no task is waiting
+    pub fn notify_one(&self) {
+        let waker = {
+            let mut waiters = self.waiters.borrow_mut();
+            match waiters.pop_front() {
+                Some(waiter) => {
+                    waiter.queued = false;
+                    waiter.notified = Some(Notification::One);
+                    waiter.waker.take()
+                }
+                None => {
+                    tracing::debug!("Notified with no waiters. Storing permit");
+                    self.permit.set(true);
+                    return;
+                }
+            }
+        };
+
+        // Wake outside of the borrow, in case the waker polls the waiter
+        if let Some(waker) = waker {
+            task::wake(waker, WakeSource::Channel);
+        }
+    }
+
+    /// Wakes every task that is waiting. Tasks that start waiting after
+    /// this aren't woken, and no permit is stored
+    pub fn notify_waiters(&self) {
+        // Waiters are woken one at a time, outside of the borrow, rather
+        // than collected first, which would allocate. The last one waiting
+        // now is remembered, so waiters that join while the others are
+        // woken are left waiting
+        let last = self.waiters.borrow().back();
+        if last.is_null() {
+            return;
+        }
+
+        let mut woken = 0;
+        loop {
+            let (waker, done) = {
+                let mut waiters = self.waiters.borrow_mut();
+                match waiters.pop_front() {
+                    Some(waiter) => {
+                        waiter.queued = false;
+                        waiter.notified = Some(Notification::All);
+                        (waiter.waker.take(), ptr::eq(waiter, last))
+                    }
+                    None => (None, true),
+                }
+            };
+            if let Some(waker) = waker {
+                woken += 1;
+                task::wake(waker, WakeSource::Channel);
+            }
+            if done {
+                break;
+            }
+        }
+        tracing::debug!("Notified waiters. Woken: {}", woken);
+    }
+
+    fn poll_notified(&self, cx: &mut Context, waiter: &mut Waiter) -> Poll<()> {
+        if waiter.notified.take().is_some() {
+            return Poll::Ready(());
+        }
+
+        if waiter.queued {
+            // Still waiting. Keep the waker current in case we've moved task
+            let waker = cx.waker();
+            if !waiter.waker.as_ref().is_some_and(|w| w.will_wake(waker)) {
+                waiter.waker = Some(waker.clone());
+            }
+            return Poll::Pending;
+        }
+
+        if self.permit.replace(false) {
+            return Poll::Ready(());
+        }
+
+        waiter.waker = Some(cx.waker().clone());
+        waiter.queued = true;
+        self.waiters.borrow_mut().push_back(waiter as *mut Waiter);
+        Poll::Pending
+    }
+}
+
+// ===== impl Waiter =====
+
+impl Waiter {
+    fn new() -> Waiter {
+        Waiter {
+            waker: None,
+            next: ptr::null_mut(),
+            prev: ptr::null_mut(),
+            queued: false,
+            notified: None,
+            _pin: PhantomPinned,
+        }
+    }
+}
+
+impl Link for Waiter {
+    fn next(&mut self) -> &mut *mut Waiter {
+        &mut self.next
+    }
+
+    fn prev(&mut self) -> &mut *mut Waiter {
+        &mut self.prev
+    }
+}
+
+// ===== impl Notified =====
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Safety: the waiter is never moved out of the future
+        let this = unsafe { self.get_unchecked_mut() };
+        this.notify.poll_notified(cx, &mut this.waiter)
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        if self.waiter.queued {
+            // Dropped while waiting, so leave the waitlist
+            let waiter = &mut self.waiter as *mut Waiter;
+            self.notify.waiters.borrow_mut().remove(waiter);
+        } else if self.waiter.notified == Some(Notification::One) {
+            // Dropped after `notify_one` picked it, without seeing it. Pass
+            // the notification on, so it isn't lost
+            self.notify.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::poll_once;
+    use crate::test_util::allocations;
+    use crate::Runtime;
+    use std::rc::Rc;
+
+    #[test]
+    fn notify_one_wakes_waiters_in_order() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let notify = Rc::new(Notify::new());
+            let woken = Rc::new(RefCell::new(Vec::new()));
+
+            let handles: Vec<_> = (0..3)
+                .map(|i| {
+                    let (notify, woken) = (notify.clone(), woken.clone());
+                    crate::spawn(async move {
+                        notify.notified().await;
+                        woken.borrow_mut().push(i);
+                    })
+                })
+                .collect();
+
+            // Let every task join the waitlist
+            crate::spawn(async {}).await.unwrap();
+            notify.notify_one();
+            notify.notify_one();
+            crate::spawn(async {}).await.unwrap();
+            assert_eq!(*woken.borrow(), vec![0, 1]);
+
+            notify.notify_one();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            assert_eq!(*woken.borrow(), vec![0, 1, 2]);
+        });
+    }
+
+    #[test]
+    fn notify_one_stores_a_single_permit() {
+        let notify = Notify::new();
+        notify.notify_one();
+        notify.notify_one();
+
+        assert!(poll_once(notify.notified()).is_ready());
+        assert!(poll_once(notify.notified()).is_pending());
+    }
+
+    #[test]
+    fn notify_waiters_wakes_only_current_waiters() {
+        let notify = Notify::new();
+        let first = notify.notified();
+        let second = notify.notified();
+        crate::pin!(first, second);
+        assert!(poll_once(first.as_mut()).is_pending());
+        assert!(poll_once(second.as_mut()).is_pending());
+
+        notify.notify_waiters();
+        assert!(poll_once(first.as_mut()).is_ready());
+        assert!(poll_once(second.as_mut()).is_ready());
+        // Nothing was stored for later waiters
+        assert!(poll_once(notify.notified()).is_pending());
+    }
+
+    #[test]
+    fn dropped_waiter_passes_notification_on() {
+        let notify = Notify::new();
+        let second = notify.notified();
+        crate::pin!(second);
+        {
+            let first = notify.notified();
+            crate::pin!(first);
+            assert!(poll_once(first.as_mut()).is_pending());
+            assert!(poll_once(second.as_mut()).is_pending());
+            // Picks the first, which is dropped without seeing it
+            notify.notify_one();
+        }
+        assert!(poll_once(second.as_mut()).is_ready());
+    }
+
+    #[test]
+    fn notify_fast_path_does_not_allocate() {
+        let notify = Notify::new();
+        let stored = allocations(|| {
+            notify.notify_one();
+            assert!(poll_once(notify.notified()).is_ready());
+        });
+        assert_eq!(stored, 0);
+
+        let notified = notify.notified();
+        crate::pin!(notified);
+        let woken = allocations(|| {
+            assert!(poll_once(notified.as_mut()).is_pending());
+            notify.notify_one();
+            assert!(poll_once(notified.as_mut()).is_ready());
+        });
+        assert_eq!(woken, 0);
+    }
+
+    #[test]
+    fn notifying_a_task_does_not_allocate() {
+        // Room on the queue up front, so queueing the task doesn't grow it
+        let rt = crate::runtime::Builder::new()
+            .global_queue_capacity(16)
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let notify = Rc::new(Notify::new());
+            let waiter = {
+                let notify = notify.clone();
+                crate::spawn(async move {
+                    let notified = notify.notified();
+                    crate::pin!(notified);
+                    let mut allocated = None;
+                    core::future::poll_fn(|cx| {
+                        if notified.as_mut().poll(cx).is_ready() {
+                            return Poll::Ready(());
+                        }
+                        // Woken while it's being polled, which only sets the
+                        // task's NOTIFIED bit. It's queued again once this
+                        // poll returns
+                        allocated = Some(allocations(|| notify.notify_one()));
+                        Poll::Pending
+                    })
+                    .await;
+                    allocated
+                })
+            };
+            assert_eq!(waiter.await.unwrap(), Some(0));
+
+            let waiter = {
+                let notify = notify.clone();
+                crate::spawn(async move { notify.notified().await })
+            };
+            crate::spawn(async {}).await.unwrap();
+            // Hands the waiter's waker to the task, which is queued
+            assert_eq!(allocations(|| notify.notify_one()), 0);
+            waiter.await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,133 @@
+//! A channel for sending a single value between asynchronous tasks.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use crate::channel::error::{RecvError, SendError};
+use crate::task::{self, WakeSource};
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Rc::new(RefCell::new(Inner {
+        value: None,
+        rx_waker: None,
+        tx_dropped: false,
+        rx_dropped: false,
+    }));
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+pub struct Sender<T> {
+    inner: Rc<RefCell<Inner<T>>>,
+}
+
+/// Receives the value sent by the [`Sender`]. Awaiting it resolves to the
+/// value, or an error if the sender was dropped without sending one
+pub struct Receiver<T> {
+    inner: Rc<RefCell<Inner<T>>>,
+}
+
+struct Inner<T> {
+    // The value sent
+    value: Option<T>,
+    // Waker notified when a value is sent or the sender is dropped
+    rx_waker: Option<Waker>,
+    tx_dropped: bool,
+    rx_dropped: bool,
+}
+
+// ==== impl Sender =====
+
+impl<T> Sender<T> {
+    /// Sends `value` to the receiver. Fails, returning the value, if the
+    /// receiver has been dropped
+    pub fn send(self, value: T) -> Result<(), SendError<T>> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.rx_dropped {
+            return Err(SendError(value));
+        }
+        inner.value = Some(value);
+        Ok(())
+        // Dropping the sender wakes the receiver
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        tracing::debug!("Dropping oneshot sender");
+        let mut inner = self.inner.borrow_mut();
+        inner.tx_dropped = true;
+        let rx_waker = inner.rx_waker.take();
+        drop(inner);
+        if let Some(waker) = rx_waker {
+            task::wake(waker, WakeSource::Channel);
+        }
+    }
+}
+
+// ===== impl Receiver =====
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(value) = inner.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if inner.tx_dropped {
+            return Poll::Ready(Err(RecvError));
+        }
+        inner.rx_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        tracing::debug!("Dropping oneshot receiver");
+        self.inner.borrow_mut().rx_dropped = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn send_before_recv() {
+        let rt = Runtime::new();
+        let value = rt.block_on(async {
+            let (tx, rx) = channel();
+            tx.send(3).unwrap();
+            rx.await
+        });
+        assert_eq!(value.unwrap(), 3);
+    }
+
+    #[test]
+    fn sender_dropped_without_sending() {
+        let rt = Runtime::new();
+        let res = rt.block_on(async {
+            let (tx, rx) = channel::<u8>();
+            crate::spawn(async move { drop(tx) });
+            rx.await
+        });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn send_to_dropped_receiver() {
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(tx.send(1).unwrap_err().0, 1);
+    }
+}
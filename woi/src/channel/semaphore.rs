@@ -1,22 +1,39 @@
 use core::cell::{Cell, RefCell};
 use core::future::Future;
+use core::marker::PhantomPinned;
 use core::pin::Pin;
 use core::ptr;
 use core::task::{Context, Poll, Waker};
 
-use super::linked_list::LinkedList;
+use super::linked_list::{Link, LinkedList};
+use crate::task::{self, WakeSource};
 
+/// Counts the permits controlling access to a resource
+///
+/// Waiters are granted permits in the order they started waiting. A permit
+/// made available while there are waiters is handed straight to the first
+/// one, so a new caller can't take it from under them.
+///
+/// A semaphore holds at most [`Semaphore::MAX_PERMITS`] available permits.
+/// Once closed, acquiring a permit fails with an [`AcquireError`]
 pub struct Semaphore {
     permits: Cell<usize>,
-    waiters: RefCell<LinkedList>,
+    waiters: RefCell<LinkedList<Waiter>>,
+    closed: Cell<bool>,
 }
 
-// We have to make this unpin so that we ensure that the waiter isn't
-// moved in memory. I need to read up on this though
+// The waiter is linked into the semaphore's waitlist by address, so it must
+// not move once it has been queued. `PhantomPinned` makes it (and `Acquire`)
+// `!Unpin` so that can't happen once the future has been polled
 pub struct Waiter {
     pub(crate) waker: Option<Waker>,
     pub(crate) next: *mut Waiter,
     pub(crate) prev: *mut Waiter,
+    // Whether the waiter is in the waitlist
+    queued: bool,
+    // Whether a permit has been handed to the waiter
+    granted: bool,
+    _pin: PhantomPinned,
 }
 
 /// Future to acquire a permit for sending messages to the channel
@@ -25,42 +42,148 @@ pub struct Acquire<'a> {
     waiter: Waiter,
 }
 
+/// Error returned when acquiring a permit from a closed semaphore
+#[derive(Debug)]
 pub struct AcquireError;
 
 // ===== impl Semaphore =====
 
 impl Semaphore {
+    /// The maximum number of permits a semaphore can hold
+    pub const MAX_PERMITS: usize = usize::MAX >> 3;
+
+    /// Creates a semaphore with the given number of permits
+    ///
+    /// Panics if `permits` exceeds [`Semaphore::MAX_PERMITS`]
     pub fn new(permits: usize) -> Semaphore {
+        assert!(
+            permits <= Self::MAX_PERMITS,
+            "a semaphore may not have more than {} permits",
+            Self::MAX_PERMITS
+        );
         Semaphore {
             permits: Cell::new(permits),
             waiters: RefCell::new(LinkedList::new()),
+            closed: Cell::new(false),
+        }
+    }
+
+    /// Closes the semaphore
+    ///
+    /// Every queued waiter is woken and, like any later call to acquire,
+    /// fails with an [`AcquireError`]. Permits that have already been
+    /// acquired are unaffected
+    pub fn close(&self) {
+        self.closed.set(true);
+
+        let mut waiters = self.waiters.borrow_mut();
+        let mut wakers = Vec::new();
+        while let Some(waiter) = waiters.pop_front() {
+            waiter.queued = false;
+            if let Some(waker) = waiter.waker.take() {
+                wakers.push(waker);
+            }
+        }
+        drop(waiters);
+        tracing::debug!("Closed semaphore. Woken waiters: {}", wakers.len());
+
+        for waker in wakers {
+            task::wake(waker, WakeSource::Channel);
         }
     }
 
+    pub fn is_closed(&self) -> bool {
+        self.closed.get()
+    }
+
+    /// Returns the number of permits available to acquire
+    pub fn available_permits(&self) -> usize {
+        self.permits.get()
+    }
+
     pub(crate) fn release(&self) {
-        self.permits.set(self.permits.get() + 1);
-        tracing::debug!("Released permit. Available: {}", self.permits.get());
+        self.add_permits(1)
+    }
+
+    /// Adds `n` permits to the semaphore
+    ///
+    /// Permits go to queued waiters first, in the order they started
+    /// waiting, and each waiter granted one is woken. Whatever is left over
+    /// is available to acquire.
+    ///
+    /// Panics if `n` and the available permits add up to more than
+    /// [`Semaphore::MAX_PERMITS`]
+    pub fn add_permits(&self, n: usize) {
+        assert!(
+            n <= Self::MAX_PERMITS - self.permits.get(),
+            "a semaphore may not have more than {} permits",
+            Self::MAX_PERMITS
+        );
 
         let mut waiters = self.waiters.borrow_mut();
-        if let Some(waiter) = waiters.pop_front() {
-            // TODO: Drop the waker?
-            if let Some(waker) = &waiter.waker {
-                waker.wake_by_ref()
+        let mut remaining = n;
+        let mut wakers = Vec::new();
+
+        while remaining > 0 {
+            match waiters.pop_front() {
+                Some(waiter) => {
+                    waiter.queued = false;
+                    waiter.granted = true;
+                    remaining -= 1;
+                    if let Some(waker) = waiter.waker.take() {
+                        wakers.push(waker);
+                    }
+                }
+                None => break,
             }
         }
+        drop(waiters);
+
+        self.permits.set(self.permits.get() + remaining);
+        tracing::debug!(
+            "Added {} permits. Granted: {}. Available: {}",
+            n,
+            wakers.len(),
+            self.permits.get()
+        );
+
+        // Wake outside of the borrow, in case a waker polls the semaphore
+        for waker in wakers {
+            task::wake(waker, WakeSource::Channel);
+        }
     }
 
     /// Acquire a permit that gives access to the data
     ///
-    /// If there are no permits left, a waker gets put into the semaphore
-    /// waitlist and we wait until one is available
+    /// If there are no permits left, or others are already waiting for one,
+    /// the waiter gets put at the back of the semaphore waitlist and we wait
+    /// until a permit is handed to it
     pub(crate) fn poll_acquire(
         &self,
         cx: &mut Context,
         waiter: &mut Waiter,
     ) -> Poll<Result<(), AcquireError>> {
+        if waiter.granted {
+            waiter.granted = false;
+            return Poll::Ready(Ok(()));
+        }
+
+        if self.closed.get() {
+            return Poll::Ready(Err(AcquireError));
+        }
+
+        if waiter.queued {
+            // Still waiting. Keep the waker current in case we've moved task
+            let waker = cx.waker();
+            if !waiter.waker.as_ref().is_some_and(|w| w.will_wake(waker)) {
+                waiter.waker = Some(waker.clone());
+            }
+            return Poll::Pending;
+        }
+
+        let mut waiters = self.waiters.borrow_mut();
         let permits = self.permits.get();
-        if permits > 0 {
+        if permits > 0 && waiters.is_empty() {
             self.permits.set(permits - 1);
             tracing::debug!("Acquired permit. Available: {}", self.permits.get());
             return Poll::Ready(Ok(()));
@@ -68,8 +191,8 @@ impl Semaphore {
 
         tracing::debug!("No permits available!");
         waiter.waker = Some(cx.waker().clone());
-        let waiter_ptr = waiter as *const _ as *mut Waiter;
-        self.waiters.borrow_mut().push_back(waiter_ptr);
+        waiter.queued = true;
+        waiters.push_back(waiter as *mut Waiter);
 
         Poll::Pending
     }
@@ -87,14 +210,27 @@ impl Waiter {
             waker: None,
             next: ptr::null_mut(),
             prev: ptr::null_mut(),
+            queued: false,
+            granted: false,
+            _pin: PhantomPinned,
         }
     }
 }
 
+impl Link for Waiter {
+    fn next(&mut self) -> &mut *mut Waiter {
+        &mut self.next
+    }
+
+    fn prev(&mut self) -> &mut *mut Waiter {
+        &mut self.prev
+    }
+}
+
 // ===== impl Acquire =====
 
 impl<'a> Acquire<'a> {
-    pub fn new(semaphore: &'a Semaphore) -> Acquire {
+    pub fn new(semaphore: &'a Semaphore) -> Acquire<'a> {
         Acquire {
             semaphore,
             waiter: Waiter::new(),
@@ -106,7 +242,121 @@ impl Future for Acquire<'_> {
     type Output = Result<(), AcquireError>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.get_mut();
+        // Safety: the waiter is never moved out of the future
+        let this = unsafe { self.get_unchecked_mut() };
         this.semaphore.poll_acquire(cx, &mut this.waiter)
     }
 }
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        if self.waiter.queued {
+            // Dropped while waiting, so leave the waitlist
+            let waiter = &mut self.waiter as *mut Waiter;
+            self.semaphore.waiters.borrow_mut().remove(waiter);
+        } else if self.waiter.granted {
+            // Dropped after being handed a permit it never took, so pass the
+            // permit on
+            self.semaphore.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::rc::Rc;
+
+    #[test]
+    fn add_permits_grants_waiters_in_order() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let semaphore = Rc::new(Semaphore::new(0));
+            let granted = Rc::new(RefCell::new(Vec::new()));
+
+            let handles: Vec<_> = (0..3)
+                .map(|i| {
+                    let (semaphore, granted) = (semaphore.clone(), granted.clone());
+                    crate::spawn(async move {
+                        let _ = semaphore.acquire().await;
+                        granted.borrow_mut().push(i);
+                    })
+                })
+                .collect();
+
+            // Let every task queue up on the semaphore
+            crate::spawn(async {}).await.unwrap();
+            assert!(granted.borrow().is_empty());
+
+            semaphore.add_permits(2);
+            // The permits went to the first two waiters, so a newcomer can't
+            // take one ahead of the third
+            let newcomer = semaphore.acquire();
+            crate::pin!(newcomer);
+            assert!(crate::task::poll_once(newcomer.as_mut()).is_pending());
+            assert_eq!(semaphore.available_permits(), 0);
+
+            crate::spawn(async {}).await.unwrap();
+            assert_eq!(*granted.borrow(), vec![0, 1]);
+
+            semaphore.add_permits(3);
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            assert_eq!(*granted.borrow(), vec![0, 1, 2]);
+            // One permit went to the newcomer, the last is left over
+            assert!(crate::task::poll_once(newcomer.as_mut()).is_ready());
+            assert_eq!(semaphore.available_permits(), 1);
+        });
+    }
+
+    #[test]
+    fn close_fails_parked_waiters() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let semaphore = Rc::new(Semaphore::new(1));
+            // Held across the close
+            semaphore.acquire().await.unwrap();
+
+            let handles: Vec<_> = (0..3)
+                .map(|_| {
+                    let semaphore = semaphore.clone();
+                    crate::spawn(async move { semaphore.acquire().await })
+                })
+                .collect();
+
+            // Let every task park on the semaphore
+            crate::spawn(async {}).await.unwrap();
+            semaphore.close();
+
+            for handle in handles {
+                assert!(handle.await.unwrap().is_err());
+            }
+            assert!(semaphore.acquire().await.is_err());
+        });
+    }
+
+    #[test]
+    fn dropped_waiter_leaves_waitlist() {
+        let semaphore = Semaphore::new(0);
+        {
+            let first = semaphore.acquire();
+            crate::pin!(first);
+            assert!(crate::task::poll_once(first.as_mut()).is_pending());
+        }
+
+        let second = semaphore.acquire();
+        crate::pin!(second);
+        assert!(crate::task::poll_once(second.as_mut()).is_pending());
+        semaphore.add_permits(1);
+        assert!(crate::task::poll_once(second.as_mut()).is_ready());
+    }
+
+    #[test]
+    #[should_panic(expected = "permits")]
+    fn add_permits_past_max_panics() {
+        let semaphore = Semaphore::new(Semaphore::MAX_PERMITS);
+        semaphore.add_permits(1);
+    }
+}
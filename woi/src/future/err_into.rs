@@ -0,0 +1,35 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Future returned by [`FutureExt::err_into`]
+///
+/// [`FutureExt::err_into`]: super::FutureExt::err_into
+pub struct ErrInto<F, E> {
+    future: F,
+    _marker: PhantomData<fn() -> E>,
+}
+
+impl<F, E> ErrInto<F, E> {
+    pub(super) fn new(future: F) -> ErrInto<F, E> {
+        ErrInto {
+            future,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<F, T, E1, E2> Future for ErrInto<F, E2>
+where
+    F: Future<Output = Result<T, E1>>,
+    E1: Into<E2>,
+{
+    type Output = Result<T, E2>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the future is pinned along with `ErrInto` and is never moved
+        let future = unsafe { self.map_unchecked_mut(|this| &mut this.future) };
+        future.poll(cx).map(|output| output.map_err(Into::into))
+    }
+}
@@ -0,0 +1,100 @@
+mod err_into;
+pub use err_into::ErrInto;
+
+mod select;
+pub(crate) use select::Rng;
+pub use select::Select;
+#[doc(hidden)]
+pub use select::{Chain, Race};
+
+mod try_join_all;
+pub use try_join_all::{try_join_all, TryJoinAll};
+
+use std::future::Future;
+use std::time::Duration;
+
+use crate::time::Timeout;
+
+// The output of `Select`
+pub use futures::future::Either;
+
+/// Extension trait adding combinators to futures
+///
+/// Its methods are named to match the free functions they wrap. If another
+/// extension trait in scope has a method with the same name, call it as
+/// `woi::future::FutureExt::timeout(fut, dur)`
+pub trait FutureExt: Future {
+    /// Requires the future to complete within `duration`. See
+    /// [`time::timeout`](crate::time::timeout)
+    fn timeout(self, duration: Duration) -> Timeout<Self>
+    where
+        Self: Sized,
+    {
+        crate::time::timeout(duration, self)
+    }
+
+    /// Converts the error of a future that outputs a `Result` into `E`,
+    /// using its `Into` implementation. Useful when the error would
+    /// otherwise have to be converted before `?`, e.g. in a `select!` arm
+    /// or when passing the future on
+    fn err_into<E>(self) -> ErrInto<Self, E>
+    where
+        Self: Sized,
+    {
+        ErrInto::new(self)
+    }
+}
+
+impl<F: Future + ?Sized> FutureExt for F {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn err_into_converts_io_error() {
+        use std::io;
+
+        #[derive(Debug)]
+        enum AppError {
+            Io(io::ErrorKind),
+        }
+
+        impl From<io::Error> for AppError {
+            fn from(err: io::Error) -> AppError {
+                AppError::Io(err.kind())
+            }
+        }
+
+        async fn read() -> io::Result<u8> {
+            Err(io::Error::new(io::ErrorKind::NotFound, "missing"))
+        }
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            // Both arms have to produce the same type
+            let res = crate::select! {
+                res = read().err_into() => res,
+                res = std::future::pending::<Result<u8, AppError>>() => res,
+            };
+            assert!(matches!(res, Err(AppError::Io(io::ErrorKind::NotFound))));
+
+            let ok = async { Ok::<_, io::Error>(1) }.err_into::<AppError>();
+            assert!(matches!(ok.await, Ok(1)));
+        });
+    }
+
+    #[test]
+    fn timeout_method_form() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            assert_eq!(async { 1 }.timeout(Duration::from_millis(100)).await, Ok(1));
+
+            let res = crate::time::sleep(Duration::from_secs(10))
+                .timeout(Duration::from_millis(10))
+                .await;
+            assert!(res.is_err());
+        });
+    }
+}
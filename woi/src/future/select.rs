@@ -0,0 +1,206 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future::Either;
+
+use crate::runtime::context;
+
+thread_local! {
+    // Used outside of runtimes and by runtimes built without a seed
+    static RNG: Rng = Rng::from_entropy();
+}
+
+/// Generator picking which future a `Select` polls first. It's a xorshift
+/// generator, since it only has to spread polls between the futures, not
+/// be unpredictable
+pub(crate) struct Rng {
+    state: Cell<u64>,
+}
+
+impl Rng {
+    /// Creates a generator that makes the same picks every time it's given
+    /// the same seed
+    pub(crate) fn new(seed: u64) -> Rng {
+        // A xorshift generator never leaves zero
+        let state = match seed {
+            0 => 0x9e37_79b9_7f4a_7c15,
+            seed => seed,
+        };
+        Rng {
+            state: Cell::new(state),
+        }
+    }
+
+    fn from_entropy() -> Rng {
+        // Each `RandomState` gets its own random keys
+        Rng::new(RandomState::new().build_hasher().finish())
+    }
+
+    /// Picks a number below `n`
+    fn below(&self, n: usize) -> usize {
+        let mut x = self.state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state.set(x);
+        // The bias of the modulo is negligible for the few arms of a select
+        (x % n as u64) as usize
+    }
+}
+
+// Picks a number below `n` with the generator of the current runtime if it
+// was given a seed
+fn random_below(n: usize) -> usize {
+    match context::try_rng() {
+        Some(rng) => rng.below(n),
+        None => RNG.with(|rng| rng.below(n)),
+    }
+}
+
+/// Future that races two or more futures, used by the [`select!`] macro
+///
+/// Each poll picks one of the futures at random to poll first, then polls
+/// the others in order from there, wrapping round to `left`. So a future
+/// that is always ready can't keep the others from ever winning. A biased
+/// `Select` polls `left` first every time instead, then the rest in order
+///
+/// A race of more than two futures passes the rest as a [`Chain`] on the
+/// right. The pick is made over all of them, so each is as likely as the
+/// others to be polled first. The output nests an [`Either`] per future,
+/// in the order they were given
+///
+/// The picks come from the runtime's generator if it was built with
+/// [`Builder::rng_seed`], which makes them the same on every run. Otherwise
+/// they come from a generator seeded at random for each thread
+///
+/// [`select!`]: crate::select
+/// [`Builder::rng_seed`]: crate::runtime::Builder::rng_seed
+pub struct Select<A, B> {
+    arms: Chain<A, B>,
+    biased: bool,
+}
+
+/// The futures after the first of a [`Select`], raced as if each had been
+/// passed to the `Select` itself. Only [`select!`](crate::select) needs to
+/// build one
+#[doc(hidden)]
+pub struct Chain<A, B> {
+    arm: A,
+    rest: B,
+}
+
+/// The futures a [`Select`] races: a single future, or a [`Chain`] of them
+#[doc(hidden)]
+pub trait Race {
+    type Output;
+
+    /// Number of futures raced
+    const ARMS: usize;
+
+    /// Polls the futures numbered `from` to `to`, exclusive, in order, and
+    /// returns the output of the first that is ready
+    fn poll_arms(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        from: usize,
+        to: usize,
+    ) -> Poll<Self::Output>;
+}
+
+impl<F: Future> Race for F {
+    type Output = F::Output;
+
+    const ARMS: usize = 1;
+
+    fn poll_arms(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        from: usize,
+        to: usize,
+    ) -> Poll<F::Output> {
+        if from == 0 && to > 0 {
+            return self.poll(cx);
+        }
+        Poll::Pending
+    }
+}
+
+impl<A: Future, B: Race> Chain<A, B> {
+    pub fn new(arm: A, rest: B) -> Chain<A, B> {
+        Chain { arm, rest }
+    }
+}
+
+impl<A: Future, B: Race> Race for Chain<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    const ARMS: usize = 1 + B::ARMS;
+
+    fn poll_arms(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        from: usize,
+        to: usize,
+    ) -> Poll<Self::Output> {
+        // Safety: both are pinned along with the chain and are never moved
+        let this = unsafe { self.get_unchecked_mut() };
+        if from == 0 && to > 0 {
+            let arm = unsafe { Pin::new_unchecked(&mut this.arm) };
+            if let Poll::Ready(output) = arm.poll(cx) {
+                return Poll::Ready(Either::Left(output));
+            }
+        }
+        if to > 1 {
+            // The rest are numbered from one past `arm`
+            let rest = unsafe { Pin::new_unchecked(&mut this.rest) };
+            let from = from.saturating_sub(1);
+            if let Poll::Ready(output) = rest.poll_arms(cx, from, to - 1) {
+                return Poll::Ready(Either::Right(output));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<A: Future, B: Race> Select<A, B> {
+    pub fn new(left: A, right: B) -> Select<A, B> {
+        Select {
+            arms: Chain::new(left, right),
+            biased: false,
+        }
+    }
+
+    /// Creates a `Select` that always polls `left` first, then the rest in
+    /// order, so the first of them that is ready wins
+    pub fn biased(left: A, right: B) -> Select<A, B> {
+        Select {
+            arms: Chain::new(left, right),
+            biased: true,
+        }
+    }
+}
+
+impl<A: Future, B: Race> Future for Select<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the futures are pinned along with `Select` and are never
+        // moved
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut arms = unsafe { Pin::new_unchecked(&mut this.arms) };
+
+        let count = <Chain<A, B> as Race>::ARMS;
+        let start = match this.biased {
+            true => 0,
+            false => random_below(count),
+        };
+        if let Poll::Ready(output) = arms.as_mut().poll_arms(cx, start, count) {
+            return Poll::Ready(output);
+        }
+        arms.poll_arms(cx, 0, start)
+    }
+}
@@ -0,0 +1,154 @@
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future::TryFuture;
+
+/// Future returned by [`try_join_all`]
+pub struct TryJoinAll<F: TryFuture> {
+    elems: Pin<Box<[Elem<F>]>>,
+}
+
+/// A single future tracked by [`TryJoinAll`]. Once the future is done, its
+/// output is stored in place until every other future has finished
+enum Elem<F: TryFuture> {
+    Pending(F),
+    Done(F::Ok),
+    Taken,
+}
+
+/// Polls a collection of fallible futures concurrently
+///
+/// Resolves to a `Vec` of the `Ok` values, in the same order as the input, once
+/// every future has completed successfully. If any future resolves to an `Err`,
+/// the error is returned immediately and all the remaining futures are dropped
+pub fn try_join_all<I>(iter: I) -> TryJoinAll<I::Item>
+where
+    I: IntoIterator,
+    I::Item: TryFuture,
+{
+    let elems: Box<[_]> = iter.into_iter().map(Elem::Pending).collect();
+    TryJoinAll {
+        elems: elems.into(),
+    }
+}
+
+// ===== impl TryJoinAll =====
+
+impl<F: TryFuture> Future for TryJoinAll<F> {
+    type Output = Result<Vec<F::Ok>, F::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut all_done = true;
+        let mut error = None;
+
+        for elem in iter_pin_mut(self.elems.as_mut()) {
+            match elem.try_poll(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => {
+                    error = Some(e);
+                    break;
+                }
+                Poll::Pending => all_done = false,
+            }
+        }
+
+        if let Some(e) = error {
+            // Short-circuit. Replacing the collection drops every future
+            // that is still pending
+            self.elems = Box::pin([]);
+            return Poll::Ready(Err(e));
+        }
+
+        if !all_done {
+            return Poll::Pending;
+        }
+
+        let mut elems = mem::replace(&mut self.elems, Box::pin([]));
+        let output = iter_pin_mut(elems.as_mut())
+            .map(|elem| elem.take_output())
+            .collect();
+        Poll::Ready(Ok(output))
+    }
+}
+
+// Projects a pinned slice into its pinned elements
+fn iter_pin_mut<T>(slice: Pin<&mut [T]>) -> impl Iterator<Item = Pin<&mut T>> {
+    // Safety: the elements are structurally pinned since the slice is pinned
+    unsafe { slice.get_unchecked_mut() }
+        .iter_mut()
+        .map(|elem| unsafe { Pin::new_unchecked(elem) })
+}
+
+// ===== impl Elem =====
+
+impl<F: TryFuture> Elem<F> {
+    fn try_poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), F::Error>> {
+        // Safety: the future is never moved out of the pinned slot. It is
+        // only ever dropped in place by overwriting the slot
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = match this {
+            Elem::Pending(future) => unsafe { Pin::new_unchecked(future) },
+            Elem::Done(_) => return Poll::Ready(Ok(())),
+            Elem::Taken => panic!("TryJoinAll polled after completion"),
+        };
+
+        match future.try_poll(cx) {
+            Poll::Ready(Ok(output)) => {
+                *this = Elem::Done(output);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn take_output(self: Pin<&mut Self>) -> F::Ok {
+        // Safety: only the output is moved out, which is not pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        match mem::replace(this, Elem::Taken) {
+            Elem::Done(output) => output,
+            _ => unreachable!("output taken before the future was done"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::DropFlag;
+    use crate::Runtime;
+    use futures::future;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn try_join_all_ok() {
+        let rt = Runtime::new();
+        let futures = (0..5).map(|i| future::ready(Ok::<_, ()>(i)));
+        let output = rt.block_on(try_join_all(futures));
+        assert_eq!(output, Ok(vec![0, 1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn try_join_all_first_error() {
+        let dropped = Rc::new(Cell::new(false));
+        let flag = DropFlag(dropped.clone());
+
+        let futures: Vec<future::LocalBoxFuture<'_, Result<i32, &str>>> = vec![
+            Box::pin(async move {
+                let _flag = flag;
+                future::pending().await
+            }),
+            Box::pin(future::ready(Err("boom"))),
+            Box::pin(future::ready(Ok(3))),
+        ];
+
+        let rt = Runtime::new();
+        let output = rt.block_on(try_join_all(futures));
+        assert_eq!(output, Err("boom"));
+        // The pending future is dropped as soon as the error is returned
+        assert!(dropped.get());
+    }
+}
@@ -0,0 +1,205 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+
+use futures::future::poll_fn;
+
+use super::io_source::Direction;
+use super::pollable::Pollable;
+
+/// Registers any file descriptor with the reactor, so its readiness can be
+/// awaited
+///
+/// This is the escape hatch for fds that woi has no type for, like those
+/// handed out by other libraries. The fd must be in nonblocking mode. Await
+/// [`readable`](AsyncFd::readable) or [`writable`](AsyncFd::writable), then
+/// perform the syscall through the returned guard
+///
+/// Dropping the `AsyncFd` deregisters the fd and drops the inner value
+pub struct AsyncFd<T: AsRawFd> {
+    inner: Pollable<T>,
+}
+
+/// Returned by [`AsyncFd::readable`] and [`AsyncFd::writable`], once the fd
+/// is ready in that direction
+pub struct AsyncFdReadyGuard<'a, T: AsRawFd> {
+    fd: &'a AsyncFd<T>,
+    direction: Direction,
+}
+
+/// The IO attempted through [`AsyncFdReadyGuard::try_io`] would have blocked.
+/// Readiness has been cleared, so await it again before retrying
+#[derive(Debug)]
+pub struct TryIoError(());
+
+impl Error for TryIoError {}
+
+impl fmt::Display for TryIoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation would block")
+    }
+}
+
+impl<T: AsRawFd> AsyncFd<T> {
+    /// Registers `inner` with the reactor of the current runtime, for both
+    /// reading and writing
+    pub fn new(inner: T) -> io::Result<AsyncFd<T>> {
+        let inner = Pollable::new(inner)?;
+        Ok(AsyncFd { inner })
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// Deregisters the fd from the reactor and hands back the inner value
+    pub fn into_inner(self) -> io::Result<T> {
+        self.inner.into_inner()
+    }
+
+    /// Waits for the fd to be readable
+    pub async fn readable(&self) -> io::Result<AsyncFdReadyGuard<'_, T>> {
+        poll_fn(|cx| self.inner.poll_readable(cx)).await?;
+        Ok(AsyncFdReadyGuard {
+            fd: self,
+            direction: Direction::Read,
+        })
+    }
+
+    /// Waits for the fd to be writable
+    pub async fn writable(&self) -> io::Result<AsyncFdReadyGuard<'_, T>> {
+        poll_fn(|cx| self.inner.poll_writable(cx)).await?;
+        Ok(AsyncFdReadyGuard {
+            fd: self,
+            direction: Direction::Write,
+        })
+    }
+}
+
+impl<T: AsRawFd> AsRawFd for AsyncFd<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.get_ref().as_raw_fd()
+    }
+}
+
+impl<'a, T: AsRawFd> AsyncFdReadyGuard<'a, T> {
+    pub fn get_ref(&self) -> &'a AsyncFd<T> {
+        self.fd
+    }
+
+    pub fn get_inner(&self) -> &'a T {
+        self.fd.get_ref()
+    }
+
+    /// Clears the readiness the guard was returned for. Call it once the fd
+    /// returns `WouldBlock`, so awaiting readiness again waits for the next
+    /// event
+    ///
+    /// The reactor is edge-triggered: it only reports the fd again once it
+    /// becomes ready again. Until readiness is cleared, `readable` and
+    /// `writable` return straight away, even if the fd would block
+    pub fn clear_ready(&mut self) {
+        self.fd.inner.clear_readiness(self.direction);
+    }
+
+    /// Performs the IO in `f`. If it fails with `WouldBlock`, readiness is
+    /// cleared, see [`clear_ready`](AsyncFdReadyGuard::clear_ready), and
+    /// `Err(TryIoError)` returned
+    pub fn try_io<R>(
+        &mut self,
+        f: impl FnOnce(&'a T) -> io::Result<R>,
+    ) -> Result<io::Result<R>, TryIoError> {
+        match f(self.get_inner()) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.clear_ready();
+                Err(TryIoError(()))
+            }
+            res => Ok(res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::prelude::FromRawFd;
+    use std::time::Duration;
+
+    // A pipe, with a nonblocking read end
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        let res = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+        assert_eq!(res, 0, "{}", io::Error::last_os_error());
+        let res = unsafe { libc::fcntl(fds[0], libc::F_SETFL, libc::O_NONBLOCK) };
+        assert_eq!(res, 0, "{}", io::Error::last_os_error());
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn read_pipe_once_readable() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (reader, mut writer) = pipe();
+            let reader = AsyncFd::new(reader).unwrap();
+
+            let write = crate::spawn(async move {
+                crate::time::sleep(Duration::from_millis(10)).await;
+                writer.write_all(b"hello").unwrap();
+                writer
+            });
+
+            let mut buf = [0; 16];
+            let n = loop {
+                let mut guard = reader.readable().await.unwrap();
+                match guard.try_io(|mut file| file.read(&mut buf)) {
+                    Ok(res) => break res.unwrap(),
+                    Err(TryIoError(())) => continue,
+                }
+            };
+            assert_eq!(&buf[..n], b"hello");
+
+            // Held on to, or the next read would see EOF instead of blocking
+            let _writer = write.await.unwrap();
+            // The pipe is empty, so the next read would block
+            let mut guard = reader.readable().await.unwrap();
+            assert!(guard.try_io(|mut file| file.read(&mut buf)).is_err());
+        });
+    }
+
+    #[test]
+    fn cleared_readiness_waits_for_next_write() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (reader, mut writer) = pipe();
+            let reader = AsyncFd::new(reader).unwrap();
+            let mut buf = [0; 16];
+
+            writer.write_all(b"a").unwrap();
+            let guard = reader.readable().await.unwrap();
+            let mut file = guard.get_inner();
+            assert_eq!(file.read(&mut buf).unwrap(), 1);
+            let err = file.read(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+            // Still ready, until cleared
+            let mut guard = reader.readable().await.unwrap();
+            guard.clear_ready();
+            let mut readable = Box::pin(reader.readable());
+            assert!(futures::poll!(readable.as_mut()).is_pending());
+
+            writer.write_all(b"b").unwrap();
+            let mut guard = readable.await.unwrap();
+            let n = guard.try_io(|mut file| file.read(&mut buf)).unwrap();
+            assert_eq!(n.unwrap(), 1);
+            assert_eq!(&buf[..1], b"b");
+        });
+    }
+}
@@ -0,0 +1,130 @@
+use std::io;
+use std::time::Duration;
+
+use super::reactor::{Handle, Reactor};
+use crate::runtime::context::{self, DriverEnterGuard};
+
+/// Drives the IO reactor independently of the task scheduler
+///
+/// This lets a host event loop integrate woi's IO resources without running
+/// a [`Runtime`]. Turning the driver waits for IO events and wakes the tasks
+/// waiting on them. Where a woken task goes is decided by the scheduler it was
+/// spawned with (through its `Schedule` impl), so the driver never needs to
+/// know about it
+///
+/// [`Runtime`]: crate::Runtime
+pub struct Driver {
+    reactor: Reactor,
+}
+
+/// Guard returned by [`Driver::enter`]. Once dropped, IO resources are no
+/// longer registered with the driver
+pub struct DriverGuard {
+    _guard: DriverEnterGuard,
+}
+
+impl Driver {
+    pub fn new() -> io::Result<Driver> {
+        let reactor = Reactor::new()?;
+        Ok(Driver { reactor })
+    }
+
+    /// Creates a driver that only accepts the IO resources and timers that
+    /// are enabled, reading and writing through io_uring if asked to
+    pub(crate) fn with_subsystems(
+        io_enabled: bool,
+        time_enabled: bool,
+        io_uring: bool,
+    ) -> io::Result<Driver> {
+        let reactor = Reactor::with_subsystems(io_enabled, time_enabled, io_uring)?;
+        Ok(Driver { reactor })
+    }
+
+    /// Waits up to `timeout` for IO events and wakes the tasks waiting on
+    /// them. A timeout of `None` blocks until there is at least one event.
+    /// Returns the number of events processed
+    pub fn turn(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
+        self.reactor.react(timeout)
+    }
+
+    /// Registers IO resources created on this thread with this driver, until
+    /// the returned guard is dropped. When a runtime is running, its own
+    /// driver is used instead
+    pub fn enter(&self) -> DriverGuard {
+        DriverGuard {
+            _guard: context::enter_driver(self.handle()),
+        }
+    }
+
+    pub(crate) fn handle(&self) -> Handle {
+        self.reactor.handle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::AsyncReadExt;
+    use crate::net::TcpStream;
+    use crate::task::join::JoinHandle;
+    use crate::task::raw::{RawTask, Schedule};
+    use crate::task::Task;
+    use futures::task::noop_waker_ref;
+    use std::cell::RefCell;
+    use std::future::Future;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+
+    // A scheduler of the host's own, unrelated to the woi runtime
+    struct HostScheduler(Rc<RefCell<Vec<Task>>>);
+
+    impl Schedule for HostScheduler {
+        fn schedule(&self, task: Task) {
+            self.0.borrow_mut().push(task)
+        }
+    }
+
+    #[test]
+    fn turn_wakes_socket_task() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut driver = Driver::new().unwrap();
+        let _guard = driver.enter();
+
+        let queue = Rc::new(RefCell::new(Vec::new()));
+        let future = async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            buf
+        };
+        let raw = RawTask::new(future, HostScheduler(queue.clone()));
+        let mut join_handle = JoinHandle::<[u8; 5]>::new(raw);
+
+        let cx = &mut Context::from_waker(noop_waker_ref());
+        assert!(Pin::new(&mut join_handle).poll(cx).is_pending());
+
+        // Connects and then waits on the read
+        Task { raw }.run();
+        assert!(queue.borrow().is_empty());
+
+        let (mut peer, _) = listener.accept().unwrap();
+        peer.write_all(b"hello").unwrap();
+
+        let n = driver.turn(Some(Duration::from_secs(1))).unwrap();
+        assert!(n >= 1);
+
+        // The driver woke the task, which went to the host's scheduler
+        let task = queue.borrow_mut().pop().expect("task was not woken");
+        task.run();
+
+        match Pin::new(&mut join_handle).poll(cx) {
+            Poll::Ready(Ok(buf)) => assert_eq!(&buf, b"hello"),
+            _ => panic!("task did not complete"),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::io;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+
+/// Safe wrapper around an eventfd. It's nonblocking and close on exec
+///
+/// Writing to it from any thread makes it readable, which is how other
+/// threads get the attention of a runtime parked on the reactor
+pub(crate) struct EventFd {
+    fd: RawFd,
+}
+
+#[cfg(test)]
+thread_local! {
+    // Number of eventfds this thread can create before creating one fails,
+    // as it would once the process is out of file descriptors
+    static LIMIT: std::cell::Cell<Option<usize>> = const { std::cell::Cell::new(None) };
+}
+
+/// Makes `EventFd::new` on this thread fail once it has created `limit`
+/// more eventfds. `None` lifts the limit
+#[cfg(test)]
+pub(crate) fn set_limit(limit: Option<usize>) {
+    LIMIT.with(|l| l.set(limit));
+}
+
+impl EventFd {
+    pub fn new() -> io::Result<EventFd> {
+        #[cfg(test)]
+        if let Some(limit) = LIMIT.with(|l| l.get()) {
+            if limit == 0 {
+                return Err(io::Error::from_raw_os_error(libc::EMFILE));
+            }
+            set_limit(Some(limit - 1));
+        }
+        let flags = libc::EFD_NONBLOCK | libc::EFD_CLOEXEC;
+        let fd = cvt(unsafe { libc::eventfd(0, flags) })?;
+        Ok(EventFd { fd })
+    }
+
+    /// Makes the eventfd readable
+    pub fn notify(&self) -> io::Result<()> {
+        let value: u64 = 1;
+        let size = std::mem::size_of::<u64>();
+        let n = unsafe { libc::write(self.fd, &value as *const u64 as *const libc::c_void, size) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Blocks the thread until the eventfd is readable, then resets it.
+    /// Returns straight away if it was written to since it was last reset
+    pub fn wait(&self) -> io::Result<()> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            match cvt(unsafe { libc::poll(&mut pollfd, 1, -1) }) {
+                Ok(_) => return self.drain(),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Resets the counter, so the eventfd is no longer readable
+    pub fn drain(&self) -> io::Result<()> {
+        let mut value: u64 = 0;
+        let size = std::mem::size_of::<u64>();
+        let n = unsafe { libc::read(self.fd, &mut value as *mut u64 as *mut libc::c_void, size) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            // Already drained
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        let _ = cvt(unsafe { libc::close(self.fd) });
+    }
+}
+
+// Converts C error codes into a Rust Result type
+fn cvt(result: i32) -> io::Result<i32> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
@@ -0,0 +1,115 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use super::AsyncRead;
+use crate::time::{sleep, Sleep};
+
+/// Reader returned by [`idle_timeout`]
+pub struct IdleTimeout<R> {
+    io: R,
+    timeout: Duration,
+    sleep: Sleep,
+}
+
+/// Wraps a reader so that reads fail once it has been idle for `timeout`
+///
+/// Every read that makes progress pushes the deadline out by `timeout`. If no
+/// read completes before the deadline, the pending read returns an error of
+/// kind [`io::ErrorKind::TimedOut`]. The deadline is moved by resetting one
+/// timer, so no new timer is created per read
+pub fn idle_timeout<R: AsyncRead + Unpin>(io: R, timeout: Duration) -> IdleTimeout<R> {
+    IdleTimeout {
+        io,
+        timeout,
+        sleep: sleep(timeout),
+    }
+}
+
+impl<R> IdleTimeout<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.io
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.io
+    }
+
+    pub fn into_inner(self) -> R {
+        self.io
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for IdleTimeout<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        match Pin::new(&mut this.io).poll_read(cx, buf) {
+            Poll::Ready(res) => {
+                let deadline = Instant::now() + this.timeout;
+                Pin::new(&mut this.sleep).reset(deadline);
+                Poll::Ready(res)
+            }
+            Poll::Pending => match Pin::new(&mut this.sleep).poll(cx) {
+                Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "idle timeout elapsed",
+                ))),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::AsyncReadExt;
+    use crate::net::TcpStream;
+    use crate::Runtime;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    #[test]
+    fn times_out_only_after_idle_gap() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let rt = Runtime::new();
+        rt.block_on(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let (peer, _) = listener.accept().unwrap();
+
+            let mut writer = peer.try_clone().unwrap();
+            crate::spawn(async move {
+                for _ in 0..3 {
+                    sleep(Duration::from_millis(30)).await;
+                    writer.write_all(b"ping").unwrap();
+                }
+            });
+
+            let timeout = Duration::from_millis(60);
+            let mut reader = idle_timeout(stream, timeout);
+            let mut buf = [0; 4];
+
+            // Data arrives more often than the timeout so none of these time out
+            for _ in 0..3 {
+                reader.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"ping");
+            }
+
+            let idle_since = Instant::now();
+            let err = reader.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert!(idle_since.elapsed() >= Duration::from_millis(50));
+
+            drop(peer);
+        });
+    }
+}
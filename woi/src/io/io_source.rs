@@ -1,10 +1,11 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::io;
 use std::os::unix::prelude::RawFd;
 use std::task::{Context, Poll, Waker};
 
 use super::epoll::{Event, Token};
 use super::readiness::Readiness;
+use crate::task::{self, WakeSource};
 
 #[derive(Clone, Default)]
 pub(crate) struct IoSource {
@@ -15,6 +16,8 @@ pub(crate) struct IoSource {
     /// Holds state on an io resource's readiness for
     /// reading and writing
     pub(crate) inner: RefCell<Inner>,
+    /// What wakes from this source are tagged as
+    pub(crate) wake_source: Cell<WakeSource>,
 }
 
 #[derive(Clone, Default)]
@@ -35,10 +38,13 @@ pub(crate) enum Direction {
 }
 
 impl IoSource {
-    /// Set the readiness of the task (readable, writable or both)
+    /// Set the readiness of the task (readable, writable or both). The
+    /// readiness from the event is added to the existing readiness so an
+    /// event for one direction doesn't clobber the other. Readiness is only
+    /// removed through [`clear_readiness`](IoSource::clear_readiness)
     pub fn set_readiness(&self, event: &Event) {
         let mut inner = self.inner.borrow_mut();
-        inner.readiness = Readiness::from_event(event)
+        inner.readiness |= Readiness::from_event(event)
     }
 
     /// Unset the bit indicating readiness for a specific [`Direction`]
@@ -71,8 +77,9 @@ impl IoSource {
             }
         }
 
+        let source = self.wake_source.get();
         for waker in wakers {
-            waker.wake()
+            task::wake(waker, source)
         }
     }
 
@@ -104,7 +111,11 @@ impl IoSource {
             Direction::Write => &mut inner.writer,
         };
 
+        // The source stays registered in epoll for its whole lifetime, so
+        // polling only has to store the waker. Skip that too if the same
+        // waker is already registered
         match slot {
+            Some(existing) if existing.will_wake(cx.waker()) => {}
             Some(existing) => *existing = cx.waker().clone(),
             None => *slot = Some(cx.waker().clone()),
         }
@@ -143,3 +154,92 @@ impl IoSource {
         inner.readiness & Readiness::WRITABLE == Readiness::WRITABLE
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::epoll::Interest;
+    use crate::io::reactor::Reactor;
+    use crate::test_util::counting_waker;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn read_and_write_wakers_are_independent() {
+        let (socket, mut peer) = UnixStream::pair().unwrap();
+        socket.set_nonblocking(true).unwrap();
+
+        let mut reactor = Reactor::new().unwrap();
+        let interest = Interest::READABLE | Interest::WRITABLE;
+        let source = reactor
+            .handle()
+            .inner
+            .register(socket.as_raw_fd(), interest)
+            .unwrap();
+
+        let (reader, reader_waker) = counting_waker();
+        let (writer, writer_waker) = counting_waker();
+        let reader_cx = &mut Context::from_waker(&reader_waker);
+        let writer_cx = &mut Context::from_waker(&writer_waker);
+        assert!(source.poll_readable(reader_cx).is_pending());
+        assert!(source.poll_writable(writer_cx).is_pending());
+
+        // The socket is writable straight away but has nothing to read
+        reactor.react(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(writer.count(), 1);
+        assert_eq!(reader.count(), 0);
+        assert!(source.poll_writable(writer_cx).is_ready());
+
+        peer.write_all(b"hello").unwrap();
+        reactor.react(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(reader.count(), 1);
+        assert_eq!(writer.count(), 1);
+    }
+
+    #[test]
+    fn same_waker_is_not_registered_again() {
+        use std::task::{RawWaker, RawWakerVTable};
+
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, no_op, no_op, no_op);
+
+        fn clone(_: *const ()) -> RawWaker {
+            CLONES.fetch_add(1, Ordering::SeqCst);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        fn no_op(_: *const ()) {}
+
+        let waker = unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) };
+        let cx = &mut Context::from_waker(&waker);
+
+        let source = IoSource::default();
+        for _ in 0..3 {
+            assert!(source.poll_readable(cx).is_pending());
+        }
+        assert_eq!(CLONES.load(Ordering::SeqCst), 1);
+
+        // A different waker replaces the registered one
+        let (other, other_waker) = counting_waker();
+        assert!(source
+            .poll_readable(&mut Context::from_waker(&other_waker))
+            .is_pending());
+        source.wake(&Event::new(Interest::READABLE, source.token));
+        assert_eq!(other.count(), 1);
+    }
+
+    #[test]
+    fn readiness_for_one_direction_keeps_the_other() {
+        let source = IoSource::default();
+        source.set_readiness(&Event::new(Interest::WRITABLE, source.token));
+        source.set_readiness(&Event::new(Interest::READABLE, source.token));
+        assert!(source.readable());
+        assert!(source.writable());
+
+        source.clear_readiness(Direction::Read);
+        assert!(!source.readable());
+        assert!(source.writable());
+    }
+}
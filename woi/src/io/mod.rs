@@ -1,8 +1,53 @@
+mod async_fd;
+pub use async_fd::{AsyncFd, AsyncFdReadyGuard, TryIoError};
+
+mod driver;
+pub use driver::{Driver, DriverGuard};
+
+mod idle_timeout;
+pub use idle_timeout::{idle_timeout, IdleTimeout};
+
 pub(crate) mod epoll;
+pub(crate) mod eventfd;
 pub(crate) mod io_source;
 pub(crate) mod pollable;
 pub(crate) mod reactor;
 pub(crate) mod readiness;
+#[cfg(feature = "io-uring")]
+pub(crate) mod uring;
 
 pub(crate) use futures::io::{AsyncRead, AsyncWrite};
+// `AsyncReadExt::read_exact` reads until the buffer is full, retrying
+// partial reads, and fails with `UnexpectedEof` if the reader ends first
 pub use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::Command;
+    use crate::Runtime;
+    use std::process::Stdio;
+
+    #[test]
+    fn read_exact_from_pipe() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            // Written in two parts, so filling the buffer takes two reads
+            let mut child = Command::new("sh")
+                .args(["-c", "printf ab; sleep 0.05; printf cdef"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let mut stdout = child.stdout.take().unwrap();
+
+            let mut buf = [0; 4];
+            stdout.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"abcd");
+
+            // Only two bytes are left before EOF
+            let err = stdout.read_exact(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+            child.wait().await.unwrap();
+        });
+    }
+}
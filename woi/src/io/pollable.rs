@@ -1,5 +1,7 @@
-use std::io::{self, Read, Write};
+use std::io::{self, IoSlice, Read, Write};
+use std::mem::ManuallyDrop;
 use std::os::unix::prelude::AsRawFd;
+use std::ptr;
 use std::rc::Rc;
 use std::task::{Context, Poll};
 
@@ -8,6 +10,9 @@ use futures::ready;
 use super::epoll::Interest;
 use super::io_source::{Direction, IoSource};
 use super::reactor::Handle;
+#[cfg(feature = "io-uring")]
+use super::uring::Ops;
+use crate::task::WakeSource;
 
 /// Bridges the event queue and IO resources
 pub(crate) struct Pollable<T> {
@@ -17,6 +22,10 @@ pub(crate) struct Pollable<T> {
     source: Rc<IoSource>,
     /// Handle to the reactor
     handle: Handle,
+    /// Reads and writes in flight, when the reactor does IO through
+    /// io_uring
+    #[cfg(feature = "io-uring")]
+    ops: Ops,
 }
 
 impl<T> Pollable<T> {
@@ -27,6 +36,38 @@ impl<T> Pollable<T> {
     pub fn get_mut(&mut self) -> &mut T {
         &mut self.io
     }
+
+    /// Deregisters the resource from the reactor and hands it back
+    ///
+    /// Any waker registered for readiness is dropped along with the
+    /// registration, so nothing is woken for this resource afterwards
+    pub fn into_inner(self) -> io::Result<T> {
+        let mut this = ManuallyDrop::new(self);
+        this.abandon_ops();
+        let result = this.handle.inner().deregister(this.source.token);
+        // Safety: `this` is never dropped, so each field is read out once
+        let (io, source, handle) = unsafe {
+            (
+                ptr::read(&this.io),
+                ptr::read(&this.source),
+                ptr::read(&this.handle),
+            )
+        };
+        #[cfg(feature = "io-uring")]
+        drop(unsafe { ptr::read(&this.ops) });
+        drop(source);
+        drop(handle);
+        result.map(|()| io)
+    }
+
+    // Gives up on the reads and writes in flight through io_uring, which
+    // would otherwise outlive the resource
+    fn abandon_ops(&mut self) {
+        #[cfg(feature = "io-uring")]
+        if let Some(uring) = &self.handle.inner.uring {
+            self.ops.abandon(uring);
+        }
+    }
 }
 
 // impl<T> Unpin for Pollable<T> {}
@@ -39,6 +80,20 @@ impl<T> Pollable<T> {
     pub fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         self.source.poll_writable(cx)
     }
+
+    /// Whether the resource was readable when the reactor last saw it
+    pub fn readable(&self) -> bool {
+        self.source.readable()
+    }
+
+    pub fn clear_readiness(&self, direction: Direction) {
+        self.source.clear_readiness(direction)
+    }
+
+    /// Sets what wakes from this resource are tagged as. Defaults to IO
+    pub fn set_wake_source(&self, source: WakeSource) {
+        self.source.wake_source.set(source)
+    }
 }
 
 impl<T: AsRawFd> Pollable<T> {
@@ -48,14 +103,29 @@ impl<T: AsRawFd> Pollable<T> {
     }
 
     pub fn new_with_interest(io: T, interest: Interest) -> io::Result<Self> {
-        let handle = Handle::current();
+        Self::new_with_handle(io, interest, Handle::current_io())
+    }
+
+    /// Registers `io` with the reactor of `handle`
+    pub fn new_with_handle(io: T, interest: Interest, handle: Handle) -> io::Result<Self> {
         let source = handle.inner.register(io.as_raw_fd(), interest)?;
-        Ok(Pollable { io, source, handle })
+        Ok(Pollable {
+            io,
+            source,
+            handle,
+            #[cfg(feature = "io-uring")]
+            ops: Ops::default(),
+        })
     }
 }
 
 impl<T: Read> Pollable<T> {
     pub fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        #[cfg(feature = "io-uring")]
+        if let Some(uring) = &self.handle.inner.uring {
+            return self.ops.poll_read(uring, &self.source, cx, buf);
+        }
+
         loop {
             ready!(self.poll_readable(cx))?;
 
@@ -73,6 +143,11 @@ impl<T: Read> Pollable<T> {
 
 impl<T: Write> Pollable<T> {
     pub fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        #[cfg(feature = "io-uring")]
+        if let Some(uring) = &self.handle.inner.uring {
+            return self.ops.poll_write(uring, &self.source, cx, buf);
+        }
+
         loop {
             ready!(self.poll_writable(cx))?;
 
@@ -86,11 +161,102 @@ impl<T: Write> Pollable<T> {
             }
         }
     }
+
+    pub fn poll_write_vectored(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        // Writing through the ring goes one buffer at a time, so a write in
+        // flight isn't overtaken by a `writev`
+        #[cfg(feature = "io-uring")]
+        if self.handle.inner.uring.is_some() {
+            let buf = bufs.iter().find(|b| !b.is_empty()).map_or(&[][..], |b| b);
+            return self.poll_write(cx, buf);
+        }
+
+        loop {
+            ready!(self.poll_writable(cx))?;
+
+            match self.get_mut().write_vectored(bufs) {
+                Ok(n) => return Poll::Ready(Ok(n)),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Clear readiness for the specific direction
+                    self.source.clear_readiness(Direction::Write)
+                }
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
 }
 
 impl<T> Drop for Pollable<T> {
     fn drop(&mut self) {
+        self.abandon_ops();
         let inner = self.handle.inner();
         let _ = inner.deregister(self.source.token);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Driver;
+    use crate::test_util::counting_waker;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    fn socket_pair() -> (UnixStream, UnixStream) {
+        let (socket, peer) = UnixStream::pair().unwrap();
+        socket.set_nonblocking(true).unwrap();
+        (socket, peer)
+    }
+
+    #[test]
+    fn reused_fd_wakes_new_owner_only() {
+        let mut driver = Driver::new().unwrap();
+        let _guard = driver.enter();
+
+        let (socket, peer) = socket_pair();
+        let old_fd = socket.as_raw_fd();
+        let old = Pollable::new(socket).unwrap();
+        let old_token = old.source.token;
+
+        let (old_counter, old_waker) = counting_waker();
+        let cx = &mut Context::from_waker(&old_waker);
+        assert!(old.poll_readable(cx).is_pending());
+
+        // Dropping deregisters the fd before it is closed
+        drop(old);
+        drop(peer);
+
+        // Other tests may grab the freed fd first, so keep trying until a
+        // new socket reuses it
+        let mut spare = Vec::new();
+        let (socket, mut peer) = loop {
+            let (socket, peer) = socket_pair();
+            if socket.as_raw_fd() == old_fd {
+                break (socket, peer);
+            }
+            assert!(spare.len() < 64, "fd {} was never reused", old_fd);
+            spare.push((socket, peer));
+        };
+        drop(spare);
+
+        let new = Pollable::new(socket).unwrap();
+        // The slab slot is reused too
+        assert_eq!(new.source.token, old_token);
+
+        let (new_counter, new_waker) = counting_waker();
+        let cx = &mut Context::from_waker(&new_waker);
+        assert!(new.poll_readable(cx).is_pending());
+
+        peer.write_all(b"ping").unwrap();
+        driver.turn(Some(Duration::from_millis(100))).unwrap();
+
+        assert_eq!(new_counter.count(), 1);
+        assert_eq!(old_counter.count(), 0);
+        assert!(new.poll_readable(cx).is_ready());
+    }
+}
@@ -1,13 +1,16 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::io;
 use std::os::unix::prelude::RawFd;
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use slab::Slab;
 
 use super::epoll::{Epoll, Events, Interest, Token};
 use super::io_source::IoSource;
+#[cfg(feature = "io-uring")]
+use super::uring::{self, Uring};
+use crate::task::WakeSource;
 
 /// The reactor
 ///
@@ -32,15 +35,60 @@ pub(crate) struct Inner {
     pub poll: Epoll,
     /// Collection of IO resources registered in the event queue
     pub sources: RefCell<Slab<Rc<IoSource>>>,
+    /// Deadlines of the pending sleeps using this reactor
+    pub deadlines: RefCell<Slab<Instant>>,
+    /// Whether IO resources can be registered with this reactor
+    pub io_enabled: bool,
+    /// Whether timers can be registered with this reactor
+    pub time_enabled: bool,
+    /// Ring for completion-based IO, if the runtime asked for one and it
+    /// could be set up. Without it, IO is readiness-based through epoll
+    #[cfg(feature = "io-uring")]
+    pub uring: Option<Uring>,
 }
 
 impl Reactor {
     pub fn new() -> io::Result<Reactor> {
+        Self::with_subsystems(true, true, false)
+    }
+
+    /// Creates a reactor that only accepts the IO resources and timers that
+    /// are enabled. With `io_uring`, IO resources read and write through an
+    /// io_uring if one can be set up, and fall back to epoll if not. It's
+    /// ignored without the `io-uring` feature
+    #[cfg_attr(not(feature = "io-uring"), allow(unused_variables))]
+    pub fn with_subsystems(
+        io_enabled: bool,
+        time_enabled: bool,
+        io_uring: bool,
+    ) -> io::Result<Reactor> {
+        let poll = Epoll::new()?;
+
+        #[cfg(feature = "io-uring")]
+        let uring = match io_uring {
+            true => match Uring::new().and_then(|uring| {
+                poll.add(uring.fd(), Interest::READABLE, uring::TOKEN)?;
+                Ok(uring)
+            }) {
+                Ok(uring) => Some(uring),
+                Err(e) => {
+                    tracing::debug!("Failed to set up io_uring, falling back to epoll: {}", e);
+                    None
+                }
+            },
+            false => None,
+        };
+
         Ok(Reactor {
             events: Events::with_capacity(1024),
             inner: Rc::new(Inner {
-                poll: Epoll::new()?,
+                poll,
                 sources: RefCell::new(Slab::new()),
+                deadlines: RefCell::new(Slab::new()),
+                io_enabled,
+                time_enabled,
+                #[cfg(feature = "io-uring")]
+                uring,
             }),
         })
     }
@@ -51,8 +99,8 @@ impl Reactor {
         }
     }
 
-    // Process new events
-    pub fn react(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+    // Process new events. Returns the number of events processed
+    pub fn react(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
         self.inner.poll.poll(&mut self.events, timeout)?;
 
         for event in self.events.iter() {
@@ -63,13 +111,20 @@ impl Reactor {
             );
 
             let token = event.token();
+            #[cfg(feature = "io-uring")]
+            if token == uring::TOKEN {
+                if let Some(uring) = &self.inner.uring {
+                    uring.complete();
+                }
+                continue;
+            }
             if let Some(io_source) = self.inner.sources.borrow().get(token.0) {
                 io_source.set_readiness(event);
                 io_source.wake(event)
             }
         }
 
-        Ok(())
+        Ok(self.events.len())
     }
 }
 
@@ -80,6 +135,30 @@ impl Handle {
         crate::runtime::context::io()
     }
 
+    /// The current handle, for registering an IO resource
+    ///
+    /// Panics if IO is disabled on the current runtime
+    pub fn current_io() -> Self {
+        let handle = Self::current();
+        assert!(
+            handle.inner.io_enabled,
+            "IO is disabled on this runtime. Call `enable_io` on the runtime builder to use IO resources"
+        );
+        handle
+    }
+
+    /// The current handle, for registering a timer
+    ///
+    /// Panics if time is disabled on the current runtime
+    pub fn current_time() -> Self {
+        let handle = Self::current();
+        assert!(
+            handle.inner.time_enabled,
+            "Time is disabled on this runtime. Call `enable_time` on the runtime builder to use timers"
+        );
+        handle
+    }
+
     pub fn inner(&self) -> Rc<Inner> {
         self.inner.clone()
     }
@@ -98,6 +177,7 @@ impl Inner {
         let io_source = Rc::new(IoSource {
             io,
             token,
+            wake_source: Cell::new(WakeSource::Io),
             ..Default::default()
         });
 
@@ -113,4 +193,27 @@ impl Inner {
         let source = self.sources.borrow_mut().remove(token.0);
         self.poll.delete(source.io)
     }
+
+    pub fn add_deadline(&self, deadline: Instant) -> usize {
+        self.deadlines.borrow_mut().insert(deadline)
+    }
+
+    pub fn remove_deadline(&self, key: usize) {
+        self.deadlines.borrow_mut().remove(key);
+    }
+
+    /// Number of operations completed through io_uring. Always zero
+    /// without it
+    pub fn io_uring_completions(&self) -> u64 {
+        #[cfg(feature = "io-uring")]
+        if let Some(uring) = &self.uring {
+            return uring.completions();
+        }
+        0
+    }
+
+    /// The earliest deadline of all pending sleeps
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.deadlines.borrow().iter().map(|(_, d)| *d).min()
+    }
 }
@@ -0,0 +1,715 @@
+//! Completion-based IO through io_uring, used by the reactor when the
+//! `io-uring` feature is on and the runtime opts in with
+//! [`Builder::io_uring`](crate::runtime::Builder::io_uring).
+//!
+//! epoll tells us when a resource is *ready* and we do the read or write
+//! ourselves. io_uring is handed the read or write and tells us when it
+//! *completed*. The two meet in the reactor: the ring's fd is registered in
+//! epoll under [`TOKEN`], so completions wake the reactor like any other
+//! event and the reactor reaps them.
+//!
+//! The kernel owns an operation's buffer until it completes. So the buffers
+//! live here, in the operation, rather than in the future waiting on it. A
+//! future dropped before its operation completes leaves the buffer behind
+//! until the kernel is done with it.
+//!
+//! The ring is set up by hand with the raw syscalls, since libc only has
+//! their numbers. Layouts follow `include/uapi/linux/io_uring.h`.
+
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::mem;
+use std::os::unix::prelude::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::task::{Context, Poll, Waker};
+
+use futures::ready;
+use slab::Slab;
+
+use super::epoll::Token;
+use super::io_source::{Direction, IoSource};
+use crate::task::{self, WakeSource};
+
+/// Token of the ring's fd in epoll. Slab keys never get this high, so it
+/// can't clash with an IO resource
+pub(crate) const TOKEN: Token = Token(usize::MAX);
+
+/// Number of submission queue entries. Operations are submitted as they're
+/// pushed, so the queue never holds more than one
+const ENTRIES: u32 = 64;
+
+/// `user_data` of cancellations, whose completions are ignored
+const CANCEL: u64 = u64::MAX;
+
+#[cfg(test)]
+thread_local! {
+    // Makes setting a ring up fail on this thread, as it does on kernels
+    // without io_uring
+    static FAIL_SETUP: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Makes `Uring::new` on this thread fail while `fail` is set
+#[cfg(test)]
+pub(crate) fn fail_setup(fail: bool) {
+    FAIL_SETUP.with(|f| f.set(fail));
+}
+
+/// An io_uring instance and the operations in flight on it
+pub(crate) struct Uring {
+    ring: Ring,
+    ops: RefCell<Slab<Op>>,
+    /// Number of operations completed
+    completions: Cell<u64>,
+}
+
+enum Op {
+    /// Submitted and not yet completed. Holds the buffer the kernel reads
+    /// into or writes from
+    InFlight { buf: Vec<u8>, waker: Option<Waker> },
+    /// Completed, waiting for its future to take the result
+    Done { result: i32, buf: Vec<u8> },
+    /// Its future is gone. Kept until the kernel is done with the buffer
+    Abandoned { buf: Vec<u8> },
+}
+
+/// The completion-based reads and writes of one IO resource
+#[derive(Default)]
+pub(crate) struct Ops {
+    /// Key of the read in flight
+    read: Option<usize>,
+    /// Bytes of a completed read that didn't fit the buffer they were read
+    /// for, and how many of them have been handed out
+    unread: Vec<u8>,
+    unread_pos: usize,
+    /// Set when the last read found nothing to read, see `Ops::poll_read`
+    read_blocked: bool,
+    /// Key of the write in flight
+    write: Option<usize>,
+    /// Set when the last write found no room, see `Ops::poll_write`
+    write_blocked: bool,
+}
+
+// ===== impl Uring =====
+
+impl Uring {
+    pub fn new() -> io::Result<Uring> {
+        #[cfg(test)]
+        if FAIL_SETUP.with(|f| f.get()) {
+            return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+        }
+        Ok(Uring {
+            ring: Ring::new(ENTRIES)?,
+            ops: RefCell::new(Slab::new()),
+            completions: Cell::new(0),
+        })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.ring.fd
+    }
+
+    pub fn completions(&self) -> u64 {
+        self.completions.get()
+    }
+
+    /// Submits a read of up to `len` bytes from `fd`. Returns the key to
+    /// poll the operation with
+    pub fn read(&self, fd: RawFd, len: usize) -> io::Result<usize> {
+        let mut buf = Vec::with_capacity(len);
+        let sqe = sys::Sqe {
+            opcode: sys::IORING_OP_READ,
+            fd,
+            // Read from the current position, for files. Sockets and pipes
+            // ignore it
+            off: u64::MAX,
+            addr: buf.as_mut_ptr() as u64,
+            len: len as u32,
+            ..Default::default()
+        };
+        self.submit(sqe, buf)
+    }
+
+    /// Submits a write of `data` to `fd`. The data is copied, so the caller
+    /// doesn't have to keep it alive
+    pub fn write(&self, fd: RawFd, data: &[u8]) -> io::Result<usize> {
+        let buf = data.to_vec();
+        let sqe = sys::Sqe {
+            opcode: sys::IORING_OP_WRITE,
+            fd,
+            off: u64::MAX,
+            addr: buf.as_ptr() as u64,
+            len: buf.len() as u32,
+            ..Default::default()
+        };
+        self.submit(sqe, buf)
+    }
+
+    fn submit(&self, mut sqe: sys::Sqe, buf: Vec<u8>) -> io::Result<usize> {
+        let mut ops = self.ops.borrow_mut();
+        let entry = ops.vacant_entry();
+        let key = entry.key();
+        sqe.user_data = key as u64;
+        // The buffer moves into the slab, but its heap allocation, which the
+        // kernel was given, doesn't move with it
+        self.ring.submit(sqe)?;
+        entry.insert(Op::InFlight { buf, waker: None });
+        Ok(key)
+    }
+
+    /// Polls the operation of `key`. Once it's done, returns the result the
+    /// kernel gave (bytes transferred or a negated errno) and the buffer,
+    /// and forgets the operation
+    pub fn poll_op(&self, key: usize, cx: &mut Context<'_>) -> Poll<(i32, Vec<u8>)> {
+        let mut ops = self.ops.borrow_mut();
+        match &mut ops[key] {
+            Op::InFlight { waker, .. } => {
+                match waker {
+                    Some(existing) if existing.will_wake(cx.waker()) => {}
+                    _ => *waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+            Op::Done { .. } => match ops.remove(key) {
+                Op::Done { result, buf } => Poll::Ready((result, buf)),
+                _ => unreachable!(),
+            },
+            Op::Abandoned { .. } => unreachable!("polled an abandoned operation"),
+        }
+    }
+
+    /// Gives up on the operation of `key`. If it's still in flight, the
+    /// kernel is asked to cancel it and its buffer is kept until it
+    /// completes
+    pub fn abandon(&self, key: usize) {
+        let mut ops = self.ops.borrow_mut();
+        match &mut ops[key] {
+            Op::InFlight { buf, .. } => {
+                let buf = mem::take(buf);
+                ops[key] = Op::Abandoned { buf };
+                drop(ops);
+                let sqe = sys::Sqe {
+                    opcode: sys::IORING_OP_ASYNC_CANCEL,
+                    fd: -1,
+                    addr: key as u64,
+                    user_data: CANCEL,
+                    ..Default::default()
+                };
+                // The operation completes on its own if this fails, it just
+                // might take longer
+                if let Err(e) = self.ring.submit(sqe) {
+                    tracing::debug!("io_uring: failed to cancel operation {}: {}", key, e);
+                }
+            }
+            _ => {
+                ops.remove(key);
+            }
+        }
+    }
+
+    /// Reaps the completed operations and wakes the tasks waiting on them.
+    /// Returns the number reaped
+    pub fn complete(&self) -> usize {
+        let mut wakers = Vec::new();
+        let mut reaped = 0;
+        {
+            let mut ops = self.ops.borrow_mut();
+            self.ring.reap(|cqe| {
+                if cqe.user_data == CANCEL {
+                    return;
+                }
+                reaped += 1;
+                let key = cqe.user_data as usize;
+                match mem::replace(&mut ops[key], Op::Abandoned { buf: Vec::new() }) {
+                    Op::InFlight { mut buf, waker } => {
+                        // Reads are submitted with an empty buffer, writes
+                        // with the data
+                        if cqe.res > 0 && buf.is_empty() {
+                            // Safety: the kernel read `res` bytes into the
+                            // buffer, which never exceeds its capacity
+                            unsafe { buf.set_len(cqe.res as usize) };
+                        }
+                        ops[key] = Op::Done {
+                            result: cqe.res,
+                            buf,
+                        };
+                        wakers.extend(waker);
+                    }
+                    Op::Abandoned { .. } => {
+                        ops.remove(key);
+                    }
+                    Op::Done { .. } => unreachable!("operation completed twice"),
+                }
+            });
+        }
+        self.completions.set(self.completions.get() + reaped as u64);
+        tracing::debug!("io_uring: reaped {} completions", reaped);
+        for waker in wakers {
+            task::wake(waker, WakeSource::Io);
+        }
+        reaped
+    }
+}
+
+impl Drop for Uring {
+    fn drop(&mut self) {
+        // The kernel may still write to the buffers of operations in flight
+        // after the ring is closed, so they're leaked rather than freed
+        for op in self.ops.get_mut().drain() {
+            match op {
+                Op::InFlight { buf, .. } | Op::Abandoned { buf } => mem::forget(buf),
+                Op::Done { .. } => {}
+            }
+        }
+    }
+}
+
+// ===== impl Ops =====
+
+impl Ops {
+    /// Reads into `buf` through the ring
+    ///
+    /// The read is submitted straight away, without waiting for `source` to
+    /// be readable, and the kernel completes it once there's data. Kernels
+    /// that honour `O_NONBLOCK` on io_uring reads complete it with `EAGAIN`
+    /// instead, in which case the resource's readiness is waited for before
+    /// the read is submitted again
+    pub fn poll_read(
+        &mut self,
+        uring: &Uring,
+        source: &IoSource,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.unread_pos < self.unread.len() {
+            let n = copy(&self.unread[self.unread_pos..], buf);
+            self.unread_pos += n;
+            return Poll::Ready(Ok(n));
+        }
+
+        loop {
+            let key = match self.read {
+                Some(key) => key,
+                None => {
+                    if self.read_blocked {
+                        ready!(source.poll_readable(cx))?;
+                        self.read_blocked = false;
+                    }
+                    let key = uring.read(source.io, buf.len())?;
+                    self.read = Some(key);
+                    key
+                }
+            };
+
+            let (result, data) = ready!(uring.poll_op(key, cx));
+            self.read = None;
+            if result == -libc::EAGAIN {
+                source.clear_readiness(Direction::Read);
+                self.read_blocked = true;
+                continue;
+            }
+            if result < 0 {
+                return Poll::Ready(Err(io::Error::from_raw_os_error(-result)));
+            }
+
+            // The buffer may not be the one the read was submitted for, if
+            // the last poll was with another. Keep what doesn't fit
+            let n = copy(&data, buf);
+            if n < data.len() {
+                self.unread = data;
+                self.unread_pos = n;
+            }
+            return Poll::Ready(Ok(n));
+        }
+    }
+
+    /// Writes `buf` through the ring
+    ///
+    /// As with `poll_read`, the write is submitted straight away. A write
+    /// that is pending completes with the bytes it was submitted with, so it
+    /// must be polled again with the same `buf` until it's ready
+    pub fn poll_write(
+        &mut self,
+        uring: &Uring,
+        source: &IoSource,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let key = match self.write {
+                Some(key) => key,
+                None => {
+                    if self.write_blocked {
+                        ready!(source.poll_writable(cx))?;
+                        self.write_blocked = false;
+                    }
+                    let key = uring.write(source.io, buf)?;
+                    self.write = Some(key);
+                    key
+                }
+            };
+
+            let (result, _) = ready!(uring.poll_op(key, cx));
+            self.write = None;
+            if result == -libc::EAGAIN {
+                source.clear_readiness(Direction::Write);
+                self.write_blocked = true;
+                continue;
+            }
+            if result < 0 {
+                return Poll::Ready(Err(io::Error::from_raw_os_error(-result)));
+            }
+            return Poll::Ready(Ok(result as usize));
+        }
+    }
+
+    /// Gives up on the operations in flight, before the resource is closed
+    pub fn abandon(&mut self, uring: &Uring) {
+        if let Some(key) = self.read.take() {
+            uring.abandon(key);
+        }
+        if let Some(key) = self.write.take() {
+            uring.abandon(key);
+        }
+    }
+}
+
+fn copy(src: &[u8], dst: &mut [u8]) -> usize {
+    let n = src.len().min(dst.len());
+    dst[..n].copy_from_slice(&src[..n]);
+    n
+}
+
+// ===== impl Ring =====
+
+/// The submission and completion queues, mapped from the kernel
+struct Ring {
+    fd: RawFd,
+    sq: Mmap,
+    cq: Mmap,
+    sqes: Mmap,
+    params: sys::Params,
+}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Ring> {
+        let mut params = sys::Params::default();
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries, &mut params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = fd as RawFd;
+
+        let maps = (|| {
+            let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+            let cq_len = params.cq_off.cqes as usize
+                + params.cq_entries as usize * mem::size_of::<sys::Cqe>();
+            let sqes_len = params.sq_entries as usize * mem::size_of::<sys::Sqe>();
+            Ok::<_, io::Error>((
+                Mmap::new(fd, sq_len, sys::IORING_OFF_SQ_RING)?,
+                Mmap::new(fd, cq_len, sys::IORING_OFF_CQ_RING)?,
+                Mmap::new(fd, sqes_len, sys::IORING_OFF_SQES)?,
+            ))
+        })();
+        match maps {
+            Ok((sq, cq, sqes)) => Ok(Ring {
+                fd,
+                sq,
+                cq,
+                sqes,
+                params,
+            }),
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                Err(e)
+            }
+        }
+    }
+
+    /// Pushes `sqe` onto the submission queue and submits it
+    fn submit(&self, sqe: sys::Sqe) -> io::Result<()> {
+        let off = &self.params.sq_off;
+        // Safety: the offsets come from the kernel and are within the map.
+        // Only this thread touches the tail, and the kernel only reads
+        // entries up to it
+        let tail = unsafe { &*self.sq.at::<AtomicU32>(off.tail) };
+        let old_tail = tail.load(Ordering::Relaxed);
+        unsafe {
+            let mask = *self.sq.at::<u32>(off.ring_mask);
+            let index = old_tail & mask;
+            ptr::write(self.sqes.at::<sys::Sqe>(0).add(index as usize), sqe);
+            ptr::write(self.sq.at::<u32>(off.array).add(index as usize), index);
+        }
+        tail.store(old_tail.wrapping_add(1), Ordering::Release);
+
+        loop {
+            let n = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd,
+                    1u32,
+                    0u32,
+                    0u32,
+                    ptr::null::<libc::sigset_t>(),
+                    0usize,
+                )
+            };
+            if n == 1 {
+                return Ok(());
+            }
+            let err = match n {
+                0 => io::Error::from_raw_os_error(libc::EAGAIN),
+                _ => io::Error::last_os_error(),
+            };
+            if err.kind() != io::ErrorKind::Interrupted {
+                // Nothing was submitted, so take the entry back off the
+                // queue. Otherwise the next submit would submit it, after
+                // its buffer is gone
+                tail.store(old_tail, Ordering::Release);
+                return Err(err);
+            }
+        }
+    }
+
+    /// Calls `f` with every completion queued, then hands their slots back
+    /// to the kernel
+    fn reap(&self, mut f: impl FnMut(sys::Cqe)) {
+        let off = &self.params.cq_off;
+        // Safety: as in `submit`. The kernel writes entries before it moves
+        // the tail past them, and only this thread moves the head
+        unsafe {
+            let head = &*self.cq.at::<AtomicU32>(off.head);
+            let tail = &*self.cq.at::<AtomicU32>(off.tail);
+            let mask = *self.cq.at::<u32>(off.ring_mask);
+            let cqes = self.cq.at::<sys::Cqe>(off.cqes);
+
+            let mut next = head.load(Ordering::Relaxed);
+            let end = tail.load(Ordering::Acquire);
+            while next != end {
+                f(ptr::read(cqes.add((next & mask) as usize)));
+                next = next.wrapping_add(1);
+            }
+            head.store(next, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        tracing::debug!("Drop: io_uring_fd={}", self.fd);
+        // The maps are unmapped after this, which the kernel is fine with
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// A shared map of part of the ring
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Mmap> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+
+    /// Pointer to the `T` at byte `offset` into the map
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { (self.ptr as *mut u8).add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// The kernel's io_uring types and constants
+mod sys {
+    pub const IORING_OP_READ: u8 = 22;
+    pub const IORING_OP_WRITE: u8 = 23;
+    pub const IORING_OP_ASYNC_CANCEL: u8 = 14;
+
+    pub const IORING_OFF_SQ_RING: i64 = 0;
+    pub const IORING_OFF_CQ_RING: i64 = 0x8000000;
+    pub const IORING_OFF_SQES: i64 = 0x10000000;
+
+    /// `struct io_uring_params`
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Params {
+        pub sq_entries: u32,
+        pub cq_entries: u32,
+        pub flags: u32,
+        pub sq_thread_cpu: u32,
+        pub sq_thread_idle: u32,
+        pub features: u32,
+        pub wq_fd: u32,
+        pub resv: [u32; 3],
+        pub sq_off: SqringOffsets,
+        pub cq_off: CqringOffsets,
+    }
+
+    /// `struct io_sqring_offsets`
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct SqringOffsets {
+        pub head: u32,
+        pub tail: u32,
+        pub ring_mask: u32,
+        pub ring_entries: u32,
+        pub flags: u32,
+        pub dropped: u32,
+        pub array: u32,
+        pub resv1: u32,
+        pub user_addr: u64,
+    }
+
+    /// `struct io_cqring_offsets`
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct CqringOffsets {
+        pub head: u32,
+        pub tail: u32,
+        pub ring_mask: u32,
+        pub ring_entries: u32,
+        pub overflow: u32,
+        pub cqes: u32,
+        pub flags: u32,
+        pub resv1: u32,
+        pub user_addr: u64,
+    }
+
+    /// `struct io_uring_sqe`, with the unions flattened to the fields used
+    /// here
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Sqe {
+        pub opcode: u8,
+        pub flags: u8,
+        pub ioprio: u16,
+        pub fd: i32,
+        pub off: u64,
+        pub addr: u64,
+        pub len: u32,
+        pub op_flags: u32,
+        pub user_data: u64,
+        pub buf_index: u16,
+        pub personality: u16,
+        pub splice_fd_in: i32,
+        pub addr3: u64,
+        pub pad: u64,
+    }
+
+    /// `struct io_uring_cqe`
+    #[repr(C)]
+    pub struct Cqe {
+        pub user_data: u64,
+        pub res: i32,
+        pub flags: u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::reactor::Reactor;
+    use crate::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::net::{TcpListener, TcpStream};
+    use crate::runtime::Builder;
+    use futures::task::noop_waker_ref;
+    use std::io::{Read, Write};
+    use std::net::SocketAddr;
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+
+    #[test]
+    fn layouts_match_the_kernel() {
+        assert_eq!(mem::size_of::<sys::Params>(), 120);
+        assert_eq!(mem::size_of::<sys::Sqe>(), 64);
+        assert_eq!(mem::size_of::<sys::Cqe>(), 16);
+    }
+
+    #[test]
+    fn read_completes_once_data_arrives() {
+        let reactor = Reactor::with_subsystems(true, true, true).unwrap();
+        let inner = reactor.handle().inner();
+        let uring = inner.uring.as_ref().unwrap();
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        let cx = &mut Context::from_waker(noop_waker_ref());
+
+        let key = uring.read(rx.as_raw_fd(), 8).unwrap();
+        assert!(uring.poll_op(key, cx).is_pending());
+
+        tx.write_all(b"ping").unwrap();
+        // The socket isn't nonblocking, so the kernel waits for the data
+        // rather than failing the read. Spin until it posts the completion
+        while uring.complete() == 0 {}
+        match uring.poll_op(key, cx) {
+            Poll::Ready((n, buf)) => {
+                assert_eq!(n, 4);
+                assert_eq!(buf, b"ping");
+            }
+            Poll::Pending => panic!("read didn't complete"),
+        }
+        assert_eq!(uring.completions(), 1);
+        assert!(uring.ops.borrow().is_empty());
+    }
+
+    #[test]
+    fn abandoned_read_is_cancelled() {
+        let reactor = Reactor::with_subsystems(true, true, true).unwrap();
+        let inner = reactor.handle().inner();
+        let uring = inner.uring.as_ref().unwrap();
+        let (mut tx, mut rx) = UnixStream::pair().unwrap();
+
+        let key = uring.read(rx.as_raw_fd(), 8).unwrap();
+        uring.abandon(key);
+        // Kept until the kernel reports the read cancelled
+        while uring.complete() == 0 {}
+        assert!(uring.ops.borrow().is_empty());
+
+        // The cancelled read took nothing
+        tx.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        rx.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn runtime_falls_back_to_epoll() {
+        fail_setup(true);
+        let rt = Builder::new().enable_all().io_uring().build().unwrap();
+        fail_setup(false);
+
+        rt.block_on(async {
+            let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+                .await
+                .unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (mut server, _) = listener.accept().await.unwrap();
+
+            client.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+        });
+        assert_eq!(rt.metrics().io_uring_completions, 0);
+    }
+}
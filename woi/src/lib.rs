@@ -1,14 +1,32 @@
+// Mirrors the lints allowed by `just check`
+#![allow(
+    clippy::module_inception,
+    clippy::new_ret_no_self,
+    clippy::zero_ptr,
+    clippy::new_without_default
+)]
+
+mod macros;
+
 pub mod channel;
+pub mod future;
 pub mod io;
 pub mod net;
+pub mod process;
+pub mod runtime;
+pub mod signal;
+pub mod stream;
+pub mod task;
 pub mod time;
 
-mod runtime;
+#[cfg(test)]
+pub(crate) mod test_util;
+
 pub use runtime::Runtime;
 
-mod task;
-pub use task::spawn;
+pub use task::{spawn, spawn_with, JoinError, JoinHandle};
 
 // Re-exports
+// `join!` drives its futures inline on the calling task, without spawning
+// them. A future that completes early isn't polled again
 pub use futures::join;
-pub use futures::pin_mut as pin;
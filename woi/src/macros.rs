@@ -0,0 +1,360 @@
+/// Pins values on the stack
+///
+/// Each binding is moved into the macro and shadowed by a `Pin<&mut T>`
+/// pointing to it. Since the original value can no longer be named, it
+/// can't be moved again, which is what makes pinning it sound. This lets a
+/// `!Unpin` future be polled by reference without boxing it
+///
+/// ```
+/// let future = async { 1 };
+/// woi::pin!(future);
+/// // `future` is now a `Pin<&mut impl Future<Output = i32>>`
+/// ```
+#[macro_export]
+macro_rules! pin {
+    ($($x:ident),* $(,)?) => { $(
+        // Move the value so no one else ever has access to it
+        let mut $x = $x;
+        // Shadow the original binding so it can't be accessed directly
+        #[allow(unused_mut)]
+        let mut $x = unsafe { ::core::pin::Pin::new_unchecked(&mut $x) };
+    )* }
+}
+
+/// Waits on several futures at once and runs the arm of the first to
+/// complete
+///
+/// Each arm is written `pattern = future => expression`. The futures are
+/// polled on the calling task, each once per poll of `select!`. As soon as
+/// one completes, the others are dropped and the output of the winner is
+/// bound to its pattern to evaluate its arm. The patterns must be
+/// irrefutable
+///
+/// Which future is polled first is picked at random on every poll, each as
+/// likely as the others, and the rest are polled in the order they're
+/// written from there. So in a loop, an arm that is always ready, like a
+/// receiver with a full channel, doesn't stop the others from ever running.
+/// Start the arms with `biased;` to always poll them in the order they're
+/// written instead
+///
+/// A future that should outlive one `select!`, e.g. a deadline for a whole
+/// loop, can be passed by reference as `&mut fut`. It's polled in place
+/// rather than moved into the `select!`, so it must be `Unpin`, like a
+/// [`JoinHandle`](crate::JoinHandle), or pinned first with [`pin!`]
+///
+/// ```
+/// use std::time::Duration;
+/// use woi::time::sleep;
+///
+/// let rt = woi::Runtime::new();
+/// let winner = rt.block_on(async {
+///     woi::select! {
+///         _ = sleep(Duration::from_millis(10)) => "short",
+///         _ = sleep(Duration::from_secs(10)) => "long",
+///     }
+/// });
+/// assert_eq!(winner, "short");
+///
+/// let ticks = rt.block_on(async {
+///     let deadline = sleep(Duration::from_millis(50));
+///     woi::pin!(deadline);
+///     let mut ticks = 0;
+///     loop {
+///         woi::select! {
+///             _ = &mut deadline => break ticks,
+///             _ = sleep(Duration::from_millis(10)) => ticks += 1,
+///         }
+///     }
+/// });
+/// assert!(ticks > 0);
+/// ```
+#[macro_export]
+macro_rules! select {
+    (biased; $($pat:pat = $fut:expr => $body:expr),+ $(,)?) => {{
+        let output = $crate::select!(@race biased; $($fut),+).await;
+        $crate::select!(@arms output; $($pat = $fut => $body,)+)
+    }};
+    ($($pat:pat = $fut:expr => $body:expr),+ $(,)?) => {{
+        // The race finishes, dropping the losers, before any arm is run
+        let output = $crate::select!(@race new; $($fut),+).await;
+        $crate::select!(@arms output; $($pat = $fut => $body,)+)
+    }};
+
+    // Races the futures in one `Select`, the arms after the first chained
+    // on its right. `$kind` is the constructor of `Select` to use
+    (@race $kind:ident; $fut:expr) => { $fut };
+    (@race $kind:ident; $fut:expr, $($rest:expr),+) => {
+        $crate::future::Select::$kind($fut, $crate::select!(@chain $($rest),+))
+    };
+    (@chain $fut:expr) => { $fut };
+    (@chain $fut:expr, $($rest:expr),+) => {
+        $crate::future::Chain::new($fut, $crate::select!(@chain $($rest),+))
+    };
+
+    // Unwraps the output one `Either` at a time to find the winning arm
+    (@arms $output:ident; $pat:pat = $fut:expr => $body:expr,) => {
+        match $output {
+            $pat => $body,
+        }
+    };
+    (@arms $output:ident; $pat:pat = $fut:expr => $body:expr, $($rest:tt)+) => {
+        match $output {
+            $crate::future::Either::Left($pat) => $body,
+            $crate::future::Either::Right(rest) => $crate::select!(@arms rest; $($rest)+),
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::marker::PhantomPinned;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::task::noop_waker_ref;
+
+    // Ready on its second poll. Not `Unpin`, so it must be pinned
+    // before it can be polled
+    struct SecondPoll {
+        polled: bool,
+        _pinned: PhantomPinned,
+    }
+
+    impl Future for SecondPoll {
+        type Output = u8;
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<u8> {
+            let this = unsafe { self.get_unchecked_mut() };
+            if this.polled {
+                Poll::Ready(3)
+            } else {
+                this.polled = true;
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn pin_unpin_future() {
+        let cx = &mut Context::from_waker(noop_waker_ref());
+        let future = SecondPoll {
+            polled: false,
+            _pinned: PhantomPinned,
+        };
+        let other = async { 4 };
+        crate::pin!(future, other);
+
+        assert!(future.as_mut().poll(cx).is_pending());
+        assert_eq!(future.as_mut().poll(cx), Poll::Ready(3));
+        assert_eq!(other.as_mut().poll(cx), Poll::Ready(4));
+    }
+
+    // Ready with `value` after being pending `pending` times. Counts its polls
+    struct ReadyAfter {
+        pending: usize,
+        value: &'static str,
+        polls: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl Future for ReadyAfter {
+        type Output = &'static str;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<&'static str> {
+            self.polls.set(self.polls.get() + 1);
+            if self.pending == 0 {
+                return Poll::Ready(self.value);
+            }
+            self.pending -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn join_drives_futures_inline() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let polls: Vec<_> = (0..3).map(|_| Rc::new(Cell::new(0))).collect();
+        let future = |i: usize, pending, value| ReadyAfter {
+            pending,
+            value,
+            polls: polls[i].clone(),
+        };
+
+        let rt = crate::Runtime::new();
+        let spawned = rt.metrics().local_spawn_count;
+        let output = rt.block_on(async {
+            crate::join!(future(0, 0, "a"), future(1, 2, "b"), future(2, 1, "c"))
+        });
+
+        assert_eq!(output, ("a", "b", "c"));
+        // Each future is polled until it's ready and never again, and none
+        // of them is spawned as a task
+        let polls: Vec<_> = polls.iter().map(|polls| polls.get()).collect();
+        assert_eq!(polls, vec![1, 3, 2]);
+        assert_eq!(rt.metrics().local_spawn_count, spawned);
+    }
+
+    #[test]
+    fn select_runs_first_ready_arm() {
+        use crate::test_util::DropFlag;
+        use crate::time::sleep;
+        use std::cell::Cell;
+        use std::rc::Rc;
+        use std::time::Duration;
+
+        let dropped = Rc::new(Cell::new(false));
+        let flag = DropFlag(dropped.clone());
+
+        let rt = crate::Runtime::new();
+        let winner = rt.block_on(async {
+            crate::select! {
+                long = async move {
+                    let _flag = flag;
+                    sleep(Duration::from_secs(10)).await;
+                    "long"
+                } => long,
+                () = sleep(Duration::from_millis(10)) => {
+                    // The loser is dropped before the arm runs
+                    assert!(dropped.get());
+                    "short"
+                },
+                n = std::future::pending::<u8>() => { let _ = n; "never" },
+            }
+        });
+        assert_eq!(winner, "short");
+    }
+
+    #[test]
+    fn full_channel_does_not_starve_timer() {
+        use crate::channel::unbounded;
+        use crate::time::sleep;
+        use std::time::Duration;
+
+        let rt = crate::Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = unbounded::channel();
+            tx.send(0).unwrap();
+
+            let timer = sleep(Duration::from_millis(10));
+            crate::pin!(timer);
+            let mut received = 0;
+            loop {
+                crate::select! {
+                    // Sending it back keeps the channel from ever emptying
+                    msg = rx.recv() => {
+                        tx.send(msg.unwrap()).unwrap();
+                        received += 1;
+                    },
+                    () = timer.as_mut() => break,
+                }
+            }
+            assert!(received > 0);
+        });
+    }
+
+    #[test]
+    fn biased_select_polls_in_order() {
+        let rt = crate::Runtime::new();
+        rt.block_on(async {
+            for _ in 0..100 {
+                let winner = crate::select! {
+                    biased;
+                    a = async { "first" } => a,
+                    b = async { "second" } => b,
+                };
+                assert_eq!(winner, "first");
+            }
+        });
+    }
+
+    #[test]
+    fn select_polls_futures_by_reference_across_iterations() {
+        use crate::time::sleep;
+        use std::time::Duration;
+
+        let rt = crate::Runtime::new();
+        rt.block_on(async {
+            // A handle is `Unpin`, so it can be borrowed as it is
+            let mut handle = crate::spawn(async {
+                sleep(Duration::from_millis(10)).await;
+                7
+            });
+            let mut ticks = 0;
+            let output = loop {
+                crate::select! {
+                    res = &mut handle => break res.unwrap(),
+                    () = sleep(Duration::from_millis(1)) => ticks += 1,
+                }
+            };
+            assert_eq!(output, 7);
+            assert!(ticks > 0);
+
+            // A sleep isn't, so it's pinned on the stack first. Had it been
+            // recreated every iteration, the ticks would keep it from firing
+            let deadline = sleep(Duration::from_millis(10));
+            crate::pin!(deadline);
+            let mut ticks = 0;
+            loop {
+                crate::select! {
+                    () = &mut deadline => break,
+                    () = sleep(Duration::from_millis(1)) => ticks += 1,
+                }
+            }
+            assert!(ticks > 0);
+        });
+    }
+
+    #[test]
+    fn seeded_select_repeats_its_order() {
+        fn winners(seed: u64) -> Vec<&'static str> {
+            let rt = crate::runtime::Builder::new()
+                .rng_seed(seed)
+                .build()
+                .unwrap();
+            rt.block_on(async {
+                let mut winners = Vec::new();
+                for _ in 0..64 {
+                    // Both are ready, so the one polled first wins
+                    winners.push(crate::select! {
+                        a = async { "a" } => a,
+                        b = async { "b" } => b,
+                    });
+                }
+                winners
+            })
+        }
+
+        let first = winners(7);
+        assert_eq!(first, winners(7));
+        assert!(first.contains(&"a") && first.contains(&"b"));
+        assert_ne!(first, winners(8));
+    }
+
+    #[test]
+    fn select_polls_each_arm_first_as_often() {
+        let rt = crate::runtime::Builder::new().rng_seed(3).build().unwrap();
+        let firsts = rt.block_on(async {
+            let mut firsts = [0; 4];
+            for _ in 0..4000 {
+                // All are ready, so the one polled first wins
+                let first = crate::select! {
+                    a = async { 0 } => a,
+                    b = async { 1 } => b,
+                    c = async { 2 } => c,
+                    d = async { 3 } => d,
+                };
+                firsts[first] += 1;
+            }
+            firsts
+        });
+
+        // A coin flip per nested pair would poll the first arm first half
+        // of the time, and the last two an eighth of the time each
+        for count in firsts {
+            assert!((800..1200).contains(&count), "{:?}", firsts);
+        }
+    }
+}
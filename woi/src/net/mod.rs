@@ -1,7 +1,7 @@
 mod addr;
 
 mod tcp;
-pub use tcp::TcpStream;
+pub use tcp::{Incoming, TcpListener, TcpStream};
 
 // Re-exports
 pub use std::net::{
@@ -1,26 +1,111 @@
-use std::io;
-use std::net::Shutdown;
+use std::io::{self, IoSlice};
+use std::mem;
+use std::net::{Shutdown, SocketAddr};
+use std::os::unix::prelude::AsRawFd;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::{ready, Stream};
 
 use super::addr::ToSocketAddrs;
+use crate::io::epoll::Interest;
+use crate::io::io_source::Direction;
 use crate::io::{pollable::Pollable, AsyncRead, AsyncWrite};
 
+pub struct TcpListener {
+    inner: Pollable<std::net::TcpListener>,
+}
+
+/// Stream of connections accepted by a [`TcpListener`]. Returned from
+/// [`TcpListener::incoming`]
+pub struct Incoming<'a> {
+    listener: &'a TcpListener,
+}
+
 pub struct TcpStream {
     inner: Pollable<std::net::TcpStream>,
 }
 
+// ===== impl TcpListener =====
+
+impl TcpListener {
+    pub async fn bind<A: ToSocketAddrs>(addrs: A) -> io::Result<TcpListener> {
+        let mut last_err = None;
+
+        for addr in addrs.to_socket_addrs().await? {
+            match std::net::TcpListener::bind(addr) {
+                Ok(listener) => {
+                    listener.set_nonblocking(true)?;
+                    let pollable = Pollable::new_with_interest(listener, Interest::READABLE)?;
+                    return Ok(TcpListener { inner: pollable });
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any of the addresses",
+            )
+        }))
+    }
+
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        futures::future::poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<(TcpStream, SocketAddr)>> {
+        loop {
+            ready!(self.inner.poll_readable(cx))?;
+
+            match self.inner.get_ref().accept() {
+                Ok((stream, addr)) => return Poll::Ready(Ok((TcpStream::from_std(stream)?, addr))),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.inner.clear_readiness(Direction::Read)
+                }
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    /// Returns a stream of the connections accepted by this listener.
+    /// Connections aborted before they could be accepted are skipped
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.get_ref().local_addr()
+    }
+}
+
+impl Stream for Incoming<'_> {
+    type Item = io::Result<TcpStream>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(self.listener.poll_accept(cx)) {
+                Ok((stream, _)) => return Poll::Ready(Some(Ok(stream))),
+                // The peer gave up before we got to it. This says nothing
+                // about the listener so carry on accepting
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+}
+
+// ===== impl TcpStream =====
+
 impl TcpStream {
     pub async fn connect<A: ToSocketAddrs>(addrs: A) -> io::Result<TcpStream> {
         let mut last_err = None;
 
         for addr in addrs.to_socket_addrs().await? {
             match std::net::TcpStream::connect(addr) {
-                Ok(stream) => {
-                    stream.set_nonblocking(true)?;
-                    let pollable = Pollable::new(stream)?;
-                    return Ok(TcpStream { inner: pollable });
-                }
+                Ok(stream) => return TcpStream::from_std(stream),
                 Err(e) => last_err = Some(e),
             }
         }
@@ -34,6 +119,99 @@ impl TcpStream {
     }
 }
 
+impl TcpStream {
+    /// Creates a `TcpStream` from a standard library stream
+    ///
+    /// The stream is put in nonblocking mode and registered with the reactor
+    /// of the current runtime
+    pub fn from_std(stream: std::net::TcpStream) -> io::Result<TcpStream> {
+        stream.set_nonblocking(true)?;
+        let pollable = Pollable::new(stream)?;
+        Ok(TcpStream { inner: pollable })
+    }
+
+    /// Turns the `TcpStream` back into a blocking standard library stream
+    ///
+    /// The stream is deregistered from the reactor first. Readiness the
+    /// reactor had seen for it is discarded, but data already received is
+    /// still in the socket and can be read from the returned stream
+    pub fn into_std(self) -> io::Result<std::net::TcpStream> {
+        let stream = self.inner.into_inner()?;
+        stream.set_nonblocking(false)?;
+        Ok(stream)
+    }
+}
+
+impl TcpStream {
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.inner.get_ref().set_nodelay(nodelay)
+    }
+
+    pub fn nodelay(&self) -> io::Result<bool> {
+        self.inner.get_ref().nodelay()
+    }
+
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.inner.get_ref().set_ttl(ttl)
+    }
+
+    pub fn ttl(&self) -> io::Result<u32> {
+        self.inner.get_ref().ttl()
+    }
+
+    /// Sets `SO_LINGER`. With `Some(duration)`, closing the socket blocks
+    /// for up to `duration` while unsent data is sent. `Some(Duration::ZERO)`
+    /// resets the connection on close instead
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        let linger = libc::linger {
+            l_onoff: linger.is_some() as libc::c_int,
+            l_linger: linger.map_or(0, |d| d.as_secs() as libc::c_int),
+        };
+        self.setsockopt(libc::SOL_SOCKET, libc::SO_LINGER, linger)
+    }
+
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        let linger: libc::linger = self.getsockopt(libc::SOL_SOCKET, libc::SO_LINGER)?;
+        Ok((linger.l_onoff != 0).then(|| Duration::from_secs(linger.l_linger as u64)))
+    }
+
+    fn setsockopt<T>(&self, level: libc::c_int, name: libc::c_int, value: T) -> io::Result<()> {
+        let fd = self.inner.get_ref().as_raw_fd();
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                &value as *const T as *const libc::c_void,
+                mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn getsockopt<T: Copy>(&self, level: libc::c_int, name: libc::c_int) -> io::Result<T> {
+        let fd = self.inner.get_ref().as_raw_fd();
+        let mut value = mem::MaybeUninit::<T>::uninit();
+        let mut len = mem::size_of::<T>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                fd,
+                level,
+                name,
+                value.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { value.assume_init() })
+    }
+}
+
 impl AsyncRead for TcpStream {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -53,6 +231,17 @@ impl AsyncWrite for TcpStream {
         self.inner.poll_write(cx, buf)
     }
 
+    // Writes as much of `bufs` as the socket accepts with a single `writev`.
+    // As with `poll_write`, the number of bytes written may be less than
+    // the total length of `bufs`
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_write_vectored(cx, bufs)
+    }
+
     fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
         Poll::Ready(Ok(()))
     }
@@ -61,3 +250,186 @@ impl AsyncWrite for TcpStream {
         Poll::Ready(self.inner.get_ref().shutdown(Shutdown::Write))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::Runtime;
+    use futures::StreamExt;
+
+    // Number of IO resources registered with the current runtime
+    fn registered_sources() -> usize {
+        crate::runtime::context::io().inner.sources.borrow().len()
+    }
+
+    #[test]
+    fn incoming_accepts_connections() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+                .await
+                .unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            for i in 0..3u8 {
+                crate::spawn(async move {
+                    let mut stream = TcpStream::connect(addr).await.unwrap();
+                    stream.write_all(&[i]).await.unwrap();
+                });
+            }
+
+            let mut received = Vec::new();
+            let mut incoming = listener.incoming();
+            while let Some(conn) = incoming.next().await {
+                let mut buf = [0; 1];
+                conn.unwrap().read_exact(&mut buf).await.unwrap();
+                received.push(buf[0]);
+                if received.len() == 3 {
+                    break;
+                }
+            }
+            received.sort();
+            assert_eq!(received, vec![0, 1, 2]);
+        });
+    }
+
+    #[test]
+    fn echo_round_trip_releases_registrations() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let before = registered_sources();
+            let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+                .await
+                .unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let server = crate::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                stream.write_all(&buf).await.unwrap();
+            });
+
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(b"hello").await.unwrap();
+            let mut echoed = [0; 5];
+            client.read_exact(&mut echoed).await.unwrap();
+            assert_eq!(&echoed, b"hello");
+
+            server.await.unwrap();
+            drop(client);
+            // The listener and both streams deregistered when dropped
+            assert_eq!(registered_sources(), before);
+        });
+    }
+
+    #[test]
+    fn std_round_trip() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::net::TcpStream::connect(addr).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        let rt = Runtime::new();
+        let mut client = rt.block_on(async {
+            let before = registered_sources();
+            let mut stream = TcpStream::from_std(client).unwrap();
+            assert_eq!(registered_sources(), before + 1);
+            stream.write_all(b"async").await.unwrap();
+
+            // Data arriving while the stream is registered is left in the
+            // socket. Reading it first leaves the reactor with readiness
+            // recorded for the stream
+            peer.write_all(b"ab").unwrap();
+            let mut buf = [0; 1];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"a");
+
+            let std = stream.into_std().unwrap();
+            assert_eq!(registered_sources(), before);
+            std
+        });
+
+        let mut buf = [0; 5];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"async");
+
+        // Blocking again, so the read waits for data
+        let mut buf = [0; 1];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"b");
+        client.write_all(b"sync").unwrap();
+        let mut buf = [0; 4];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"sync");
+
+        // And back into the runtime
+        rt.block_on(async {
+            let mut stream = TcpStream::from_std(client).unwrap();
+            peer.write_all(b"again").unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"again");
+        });
+    }
+
+    #[test]
+    fn socket_options_round_trip() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+
+            stream.set_nodelay(true).unwrap();
+            assert!(stream.nodelay().unwrap());
+            stream.set_nodelay(false).unwrap();
+            assert!(!stream.nodelay().unwrap());
+
+            stream.set_ttl(42).unwrap();
+            assert_eq!(stream.ttl().unwrap(), 42);
+
+            assert_eq!(stream.linger().unwrap(), None);
+            stream.set_linger(Some(Duration::from_secs(1))).unwrap();
+            assert_eq!(stream.linger().unwrap(), Some(Duration::from_secs(1)));
+
+            // Still registered with the reactor
+            stream.write_all(b"ok").await.unwrap();
+        });
+
+        let (mut peer, _) = listener.accept().unwrap();
+        let mut buf = [0; 2];
+        std::io::Read::read_exact(&mut peer, &mut buf).unwrap();
+        assert_eq!(&buf, b"ok");
+    }
+
+    #[test]
+    fn write_vectored_concatenates_slices() {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let rt = Runtime::new();
+        let written = rt.block_on(async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let bufs = [
+                IoSlice::new(b"GET / HTTP/1.1\r\n"),
+                IoSlice::new(b""),
+                IoSlice::new(b"Host: woi\r\n"),
+                IoSlice::new(b"\r\n"),
+            ];
+            stream.write_vectored(&bufs).await.unwrap()
+        });
+
+        let (mut peer, _) = listener.accept().unwrap();
+        let mut received = vec![0; written];
+        peer.read_exact(&mut received).unwrap();
+        assert_eq!(written, 29);
+        assert_eq!(received, b"GET / HTTP/1.1\r\nHost: woi\r\n\r\n");
+    }
+}
@@ -0,0 +1,324 @@
+//! Asynchronous child processes
+//!
+//! A child's exit is awaited through a pidfd registered with the reactor,
+//! so it needs Linux 5.3 or later.
+
+mod pidfd;
+
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::prelude::AsRawFd;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::{ExitStatus, Stdio};
+use std::task::{Context, Poll};
+
+use futures::future::poll_fn;
+use futures::ready;
+
+use self::pidfd::PidFd;
+use crate::io::epoll::Interest;
+use crate::io::pollable::Pollable;
+use crate::io::{AsyncRead, AsyncWrite};
+
+thread_local! {
+    // Children that were dropped before they exited. They are reaped the
+    // next time a child is spawned or waited on, so they don't linger as
+    // zombies
+    static ORPHANS: RefCell<Vec<std::process::Child>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Builds a child process. Mirrors [`std::process::Command`]
+pub struct Command {
+    inner: std::process::Command,
+}
+
+/// A spawned child process
+pub struct Child {
+    /// The child's stdin, if it was piped
+    pub stdin: Option<ChildStdin>,
+    /// The child's stdout, if it was piped
+    pub stdout: Option<ChildStdout>,
+    /// The child's stderr, if it was piped
+    pub stderr: Option<ChildStderr>,
+    /// `None` once the child has been reaped
+    child: Option<std::process::Child>,
+    status: Option<ExitStatus>,
+    pidfd: Pollable<PidFd>,
+}
+
+pub struct ChildStdin {
+    inner: Pollable<std::process::ChildStdin>,
+}
+
+pub struct ChildStdout {
+    inner: Pollable<std::process::ChildStdout>,
+}
+
+pub struct ChildStderr {
+    inner: Pollable<std::process::ChildStderr>,
+}
+
+// ===== impl Command =====
+
+impl Command {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Command {
+        Command {
+            inner: std::process::Command::new(program),
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Command {
+        self.inner.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.inner.args(args);
+        self
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, val: V) -> &mut Command {
+        self.inner.env(key, val);
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Command {
+        self.inner.current_dir(dir);
+        self
+    }
+
+    pub fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Command {
+        self.inner.stdin(cfg);
+        self
+    }
+
+    pub fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Command {
+        self.inner.stdout(cfg);
+        self
+    }
+
+    pub fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Command {
+        self.inner.stderr(cfg);
+        self
+    }
+
+    /// Spawns the child process. Piped stdio is registered with the
+    /// reactor, so this must be called from within a runtime
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        reap_orphans();
+
+        let mut child = self.inner.spawn()?;
+        let pidfd = match PidFd::open(child.id()) {
+            Ok(pidfd) => Pollable::new_with_interest(pidfd, Interest::READABLE)?,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+
+        let stdin = child.stdin.take().map(ChildStdin::new).transpose()?;
+        let stdout = child.stdout.take().map(ChildStdout::new).transpose()?;
+        let stderr = child.stderr.take().map(ChildStderr::new).transpose()?;
+
+        Ok(Child {
+            stdin,
+            stdout,
+            stderr,
+            child: Some(child),
+            status: None,
+            pidfd,
+        })
+    }
+}
+
+// ===== impl Child =====
+
+impl Child {
+    /// The OS-assigned process identifier of the child
+    pub fn id(&self) -> u32 {
+        self.pidfd.get_ref().pid()
+    }
+
+    /// Waits for the child to exit and reaps it. Stdin is closed first so
+    /// that a child reading from it doesn't wait forever
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        drop(self.stdin.take());
+        poll_fn(|cx| self.poll_wait(cx)).await
+    }
+
+    pub fn poll_wait(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<ExitStatus>> {
+        reap_orphans();
+
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Poll::Ready(Ok(status));
+            }
+            // The pidfd becomes readable once the child exits. It stays
+            // readable after, so the readiness is never cleared
+            ready!(self.pidfd.poll_readable(cx))?;
+            if let Some(status) = self.try_wait()? {
+                return Poll::Ready(Ok(status));
+            }
+        }
+    }
+
+    /// Returns the exit status if the child has exited, reaping it
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        if let Some(status) = self.status {
+            return Ok(Some(status));
+        }
+
+        let child = self.child.as_mut().expect("child already reaped");
+        let status = child.try_wait()?;
+        if status.is_some() {
+            self.status = status;
+            self.child = None;
+        }
+        Ok(status)
+    }
+
+    /// Sends `SIGKILL` to the child. Does nothing if it has been reaped
+    pub fn start_kill(&mut self) -> io::Result<()> {
+        match self.child.as_mut() {
+            Some(child) => child.kill(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            // Still running, hand it over to be reaped once it exits
+            if let Ok(None) = child.try_wait() {
+                let _ = ORPHANS.try_with(|orphans| orphans.borrow_mut().push(child));
+            }
+        }
+    }
+}
+
+// Reaps the orphaned children that have exited
+fn reap_orphans() {
+    let _ = ORPHANS.try_with(|orphans| {
+        orphans
+            .borrow_mut()
+            .retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+    });
+}
+
+// ===== impl ChildStdin/ChildStdout/ChildStderr =====
+
+// Sets the pipe as nonblocking and registers it with the reactor
+fn register<T: AsRawFd>(pipe: T, interest: Interest) -> io::Result<Pollable<T>> {
+    let fd = pipe.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Pollable::new_with_interest(pipe, interest)
+}
+
+impl ChildStdin {
+    fn new(stdin: std::process::ChildStdin) -> io::Result<ChildStdin> {
+        let inner = register(stdin, Interest::WRITABLE)?;
+        Ok(ChildStdin { inner })
+    }
+}
+
+impl ChildStdout {
+    fn new(stdout: std::process::ChildStdout) -> io::Result<ChildStdout> {
+        let inner = register(stdout, Interest::READABLE)?;
+        Ok(ChildStdout { inner })
+    }
+}
+
+impl ChildStderr {
+    fn new(stderr: std::process::ChildStderr) -> io::Result<ChildStderr> {
+        let inner = register(stderr, Interest::READABLE)?;
+        Ok(ChildStderr { inner })
+    }
+}
+
+impl AsyncWrite for ChildStdin {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for ChildStdout {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_read(cx, buf)
+    }
+}
+
+impl AsyncRead for ChildStderr {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_read(cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::AsyncReadExt;
+    use crate::Runtime;
+
+    #[test]
+    fn wait_for_exit_code() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let status = Command::new("true").spawn().unwrap().wait().await.unwrap();
+            assert!(status.success());
+
+            let status = Command::new("false").spawn().unwrap().wait().await.unwrap();
+            assert_eq!(status.code(), Some(1));
+        });
+    }
+
+    #[test]
+    fn read_piped_stdout() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let mut child = Command::new("echo")
+                .arg("hello")
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+
+            let mut output = String::new();
+            let mut stdout = child.stdout.take().unwrap();
+            stdout.read_to_string(&mut output).await.unwrap();
+            assert_eq!(output, "hello\n");
+            assert!(child.wait().await.unwrap().success());
+        });
+    }
+}
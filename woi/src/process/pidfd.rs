@@ -0,0 +1,39 @@
+use std::io;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+
+/// A pidfd referring to a child process. It becomes readable once the
+/// process exits
+pub(super) struct PidFd {
+    fd: RawFd,
+    pid: u32,
+}
+
+impl PidFd {
+    pub fn open(pid: u32) -> io::Result<PidFd> {
+        // The fd is close on exec
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PidFd {
+            fd: fd as RawFd,
+            pid,
+        })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+}
+
+impl AsRawFd for PidFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for PidFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
@@ -0,0 +1,307 @@
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::hooks::{PollHook, ScheduleHook, ThreadHook};
+use super::multi_thread;
+use super::runtime::Runtime;
+use crate::task::TaskId;
+
+/// Builds a [`Runtime`] with custom configuration
+///
+/// ```
+/// let rt = woi::runtime::Builder::new()
+///     .enable_all()
+///     .event_interval(31)
+///     .global_queue_capacity(1024)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct Builder {
+    /// Number of tasks run before the IO driver is checked for events
+    pub(super) event_interval: u32,
+    /// Initial capacity of the run queue
+    pub(super) queue_capacity: usize,
+    /// Most tasks `try_spawn` lets be queued at once
+    pub(super) queue_limit: Option<usize>,
+    /// Whether IO resources can be used on the runtime
+    pub(super) enable_io: bool,
+    /// Whether timers can be used on the runtime
+    pub(super) enable_time: bool,
+    /// Whether IO resources read and write through io_uring. Only ever set
+    /// with the `io-uring` feature
+    pub(super) io_uring: bool,
+    /// Run whenever a task is scheduled
+    pub(super) on_task_schedule: Option<ScheduleHook>,
+    /// Run whenever a task is taken off the queue to be polled
+    pub(super) on_task_poll: Option<PollHook>,
+    /// What to do when the future passed to `block_on` panics
+    pub(super) unhandled_panic: UnhandledPanic,
+    /// Seed of the generator `select!` picks the order of its arms with
+    pub(super) rng_seed: Option<u64>,
+    /// Number of workers of a multi-threaded runtime
+    pub(super) worker_threads: Option<usize>,
+    /// Number of tasks a worker runs between checks of the injector
+    pub(super) global_queue_interval: u32,
+    /// Whether the workers of a multi-threaded runtime are run by threads
+    /// calling `run_worker`, instead of threads the runtime starts
+    pub(super) external_worker_threads: bool,
+    /// Whether a multi-threaded runtime whose worker threads can't be
+    /// started runs its tasks on `block_on` instead of failing to build
+    pub(super) fallback_to_current_thread: bool,
+    /// Run on each thread of a multi-threaded runtime as it starts running
+    /// a worker
+    pub(super) on_thread_start: Option<ThreadHook>,
+    /// Run on each thread of a multi-threaded runtime as it stops running a
+    /// worker
+    pub(super) on_thread_stop: Option<ThreadHook>,
+}
+
+/// What a runtime does when the future passed to [`Runtime::block_on`]
+/// panics. Set with [`Builder::unhandled_panic`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnhandledPanic {
+    /// Let the panic unwind out of `block_on`, leaving queued tasks as they
+    /// are. This is the default
+    Ignore,
+    /// Shut the runtime down before the panic unwinds out of `block_on`.
+    /// Queued tasks, including those woken by the unwinding future dropping
+    /// its state, are aborted and their futures dropped
+    Shutdown,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            event_interval: 61,
+            queue_capacity: 0,
+            queue_limit: None,
+            enable_io: false,
+            enable_time: false,
+            io_uring: false,
+            on_task_schedule: None,
+            on_task_poll: None,
+            unhandled_panic: UnhandledPanic::Ignore,
+            rng_seed: None,
+            worker_threads: None,
+            global_queue_interval: 31,
+            external_worker_threads: false,
+            fallback_to_current_thread: false,
+            on_thread_start: None,
+            on_thread_stop: None,
+        }
+    }
+
+    /// Enables both IO resources and timers on the runtime
+    pub fn enable_all(&mut self) -> &mut Builder {
+        self.enable_io().enable_time()
+    }
+
+    /// Enables IO resources, e.g. sockets, signals and child processes, on
+    /// the runtime. Using one on a runtime without IO enabled panics
+    pub fn enable_io(&mut self) -> &mut Builder {
+        self.enable_io = true;
+        self
+    }
+
+    /// Enables timers, e.g. sleeps and timeouts, on the runtime. Using one on
+    /// a runtime without time enabled panics
+    pub fn enable_time(&mut self) -> &mut Builder {
+        self.enable_time = true;
+        self
+    }
+
+    /// Makes IO resources read and write through io_uring instead of
+    /// waiting on epoll for readiness. Their reads and writes are handed to
+    /// the kernel and the task is woken once they've completed
+    ///
+    /// If the kernel doesn't support io_uring, or a ring can't be set up,
+    /// the runtime falls back to epoll. [`RuntimeMetrics::io_uring_completions`]
+    /// stays at zero then
+    ///
+    /// Requires the `io-uring` feature. Only sockets and pipes read and write
+    /// through the ring; everything else, and the multi-threaded runtime,
+    /// still uses epoll
+    ///
+    /// [`RuntimeMetrics::io_uring_completions`]: crate::runtime::RuntimeMetrics::io_uring_completions
+    #[cfg(feature = "io-uring")]
+    pub fn io_uring(&mut self) -> &mut Builder {
+        self.io_uring = true;
+        self
+    }
+
+    /// Sets how many tasks are run before the IO driver is checked for new
+    /// events while there are still tasks queued. Lower values pick up IO
+    /// sooner, higher values spend less time checking when none is ready.
+    /// Defaults to 61
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero
+    pub fn event_interval(&mut self, interval: u32) -> &mut Builder {
+        assert!(interval > 0, "event interval must be greater than zero");
+        self.event_interval = interval;
+        self
+    }
+
+    /// Sets the initial capacity of the run queue. Sizing it for the number
+    /// of tasks expected to be queued at once avoids growing it during
+    /// bursts of spawns. Defaults to 0, so the queue grows as needed
+    pub fn global_queue_capacity(&mut self, capacity: usize) -> &mut Builder {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Limits how many tasks can be queued before [`try_spawn`] stops
+    /// spawning new ones, so a loop of spawns can't grow the queue without
+    /// bound. Defaults to no limit
+    ///
+    /// Only new tasks are held back. Tasks that are woken are always queued,
+    /// and so are tasks spawned with [`spawn`]
+    ///
+    /// [`try_spawn`]: crate::task::try_spawn
+    /// [`spawn`]: crate::spawn
+    pub fn queue_limit(&mut self, limit: usize) -> &mut Builder {
+        self.queue_limit = Some(limit);
+        self
+    }
+
+    /// Sets what the runtime does when the future passed to `block_on`
+    /// panics. Defaults to [`UnhandledPanic::Ignore`]
+    pub fn unhandled_panic(&mut self, behaviour: UnhandledPanic) -> &mut Builder {
+        self.unhandled_panic = behaviour;
+        self
+    }
+
+    /// Seeds the generator [`select!`] uses to pick the order it polls its
+    /// arms in. A runtime given the same seed makes the same picks, so a
+    /// test can replay the interleaving of a failing run. Without a seed,
+    /// the order is picked by a generator seeded at random
+    ///
+    /// [`select!`]: crate::select
+    pub fn rng_seed(&mut self, seed: u64) -> &mut Builder {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// Sets a callback that is run with the id of a task whenever it is
+    /// pushed onto the run queue, when it's spawned or woken
+    pub fn on_task_schedule<F>(&mut self, f: F) -> &mut Builder
+    where
+        F: Fn(TaskId) + 'static,
+    {
+        self.on_task_schedule = Some(Rc::new(f));
+        self
+    }
+
+    /// Sets a callback that is run whenever a task is taken off the run
+    /// queue, just before it's polled. It's passed the id of the task and
+    /// how long the task was queued for, e.g. to build a histogram of
+    /// queue latencies
+    ///
+    /// Queued tasks are only timestamped when this is set
+    pub fn on_task_poll<F>(&mut self, f: F) -> &mut Builder
+    where
+        F: Fn(TaskId, Duration) + 'static,
+    {
+        self.on_task_poll = Some(Rc::new(f));
+        self
+    }
+
+    /// Sets how many worker threads a runtime built with
+    /// [`build_multi_thread`](Self::build_multi_thread) runs tasks on.
+    /// Defaults to the number of CPUs
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero
+    pub fn worker_threads(&mut self, workers: usize) -> &mut Builder {
+        assert!(workers > 0, "worker threads must be greater than zero");
+        self.worker_threads = Some(workers);
+        self
+    }
+
+    /// Sets how many tasks a worker of a multi-threaded runtime runs before
+    /// it checks the queue of tasks spawned and woken from other threads,
+    /// even though its own queue isn't empty. Lower values get to those
+    /// tasks sooner, higher values keep workers on their own tasks for
+    /// longer. Defaults to 31
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero
+    pub fn global_queue_interval(&mut self, interval: u32) -> &mut Builder {
+        assert!(
+            interval > 0,
+            "global queue interval must be greater than zero"
+        );
+        self.global_queue_interval = interval;
+        self
+    }
+
+    /// Makes a multi-threaded runtime run its workers on threads that call
+    /// [`Handle::run_worker`] instead of starting threads of its own, for
+    /// apps that already manage a pool of threads. Until a thread runs a
+    /// worker, no task runs. See `run_worker` for how threads take up and
+    /// give back workers
+    ///
+    /// [`Handle::run_worker`]: multi_thread::Handle::run_worker
+    pub fn external_worker_threads(&mut self) -> &mut Builder {
+        self.external_worker_threads = true;
+        self
+    }
+
+    /// Makes a multi-threaded runtime that can't start its worker threads
+    /// fall back to running its tasks on the thread of `block_on`, instead
+    /// of failing to build. Threads started before one failed are stopped
+    /// either way
+    ///
+    /// Tasks of a runtime that fell back only run while `block_on` is
+    /// waiting on its future
+    pub fn fallback_to_current_thread(&mut self) -> &mut Builder {
+        self.fallback_to_current_thread = true;
+        self
+    }
+
+    /// Sets a callback that is run on each thread of a multi-threaded
+    /// runtime as it starts running a worker, before it runs any task.
+    /// That's once for each thread the runtime starts, and once for each
+    /// call of [`Handle::run_worker`]
+    ///
+    /// [`Handle::run_worker`]: multi_thread::Handle::run_worker
+    pub fn on_thread_start<F>(&mut self, f: F) -> &mut Builder
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_thread_start = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets a callback that is run on each thread of a multi-threaded
+    /// runtime as it stops running a worker, once the runtime has shut
+    /// down. See [`on_thread_start`](Self::on_thread_start)
+    pub fn on_thread_stop<F>(&mut self, f: F) -> &mut Builder
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_thread_stop = Some(Arc::new(f));
+        self
+    }
+
+    /// Creates the runtime. Fails if the IO driver can't be created
+    pub fn build(&self) -> io::Result<Runtime> {
+        Runtime::from_builder(self)
+    }
+
+    /// Creates a [multi-threaded runtime](multi_thread), which runs `Send`
+    /// tasks on [`worker_threads`](Self::worker_threads) threads. Fails if
+    /// a worker thread can't be started, after stopping the ones that were,
+    /// unless it [falls back](Self::fallback_to_current_thread)
+    ///
+    /// The workers don't drive IO or timers, so only the worker settings
+    /// apply to it
+    pub fn build_multi_thread(&self) -> io::Result<multi_thread::Runtime> {
+        multi_thread::Runtime::from_builder(self)
+    }
+}
@@ -1,32 +1,59 @@
 use std::cell::RefCell;
+use std::rc::Rc;
 
 use super::runtime::Handle;
 use super::runtime::Spawner;
+use crate::future::Rng;
 use crate::io::reactor::Handle as IoHandle;
+use crate::task::blocking::Completions;
 
 thread_local! {
-    static CONTEXT: RefCell<Option<Handle>> = RefCell::new(None)
+    static CONTEXT: RefCell<Option<Handle>> = const { RefCell::new(None) };
+    // Handle to a standalone IO driver. Only used when there is no
+    // runtime context entered
+    static DRIVER: RefCell<Option<IoHandle>> = const { RefCell::new(None) }
 }
 
-pub(crate) struct EnterGuard;
+pub(crate) struct EnterGuard {
+    prev: Option<Handle>,
+}
 
 impl Drop for EnterGuard {
     fn drop(&mut self) {
         tracing::debug!("Dropping enter guard");
-        CONTEXT.with(|ctx| {
-            ctx.borrow_mut().take();
-        })
+        let prev = self.prev.take();
+        let _ = CONTEXT.try_with(|ctx| *ctx.borrow_mut() = prev);
     }
 }
 
 /// Sets this [`Handle`] as the current [`Handle`]. Returns an
-/// [`EnterGuard`] which clears thread local storage once dropped
+/// [`EnterGuard`] which restores the previous [`Handle`] once dropped, e.g.
+/// when a runtime is dropped from within another runtime's context
 pub(super) fn enter(new: Handle) -> EnterGuard {
-    match CONTEXT.try_with(|ctx| {
-        ctx.borrow_mut().replace(new);
-        EnterGuard {}
-    }) {
-        Ok(enter_guard) => enter_guard,
+    match CONTEXT.try_with(|ctx| ctx.borrow_mut().replace(new)) {
+        Ok(prev) => EnterGuard { prev },
+        Err(_) => panic!("Thread local destroyed"),
+    }
+}
+
+pub(crate) struct DriverEnterGuard {
+    prev: Option<IoHandle>,
+}
+
+impl Drop for DriverEnterGuard {
+    fn drop(&mut self) {
+        tracing::debug!("Dropping driver enter guard");
+        let prev = self.prev.take();
+        let _ = DRIVER.try_with(|driver| *driver.borrow_mut() = prev);
+    }
+}
+
+/// Sets the handle of a standalone IO driver as the current IO handle.
+/// Returns a [`DriverEnterGuard`] which restores the previous handle
+/// once dropped
+pub(crate) fn enter_driver(new: IoHandle) -> DriverEnterGuard {
+    match DRIVER.try_with(|driver| driver.borrow_mut().replace(new)) {
+        Ok(prev) => DriverEnterGuard { prev },
         Err(_) => panic!("Thread local destroyed"),
     }
 }
@@ -36,8 +63,12 @@ pub(super) fn enter(new: Handle) -> EnterGuard {
 pub(crate) fn io() -> IoHandle {
     match CONTEXT.try_with(|ctx| {
         let ctx = ctx.borrow();
-        let handle = ctx.as_ref().expect("No reactor running");
-        handle.io.clone()
+        match ctx.as_ref() {
+            Some(handle) => handle.io.clone(),
+            None => DRIVER
+                .with(|driver| driver.borrow().clone())
+                .expect("No reactor running"),
+        }
     }) {
         Ok(io_handle) => io_handle,
         Err(_) => panic!("Thread local destroyed"),
@@ -49,9 +80,44 @@ pub(crate) fn spawner() -> Spawner {
         let ctx = ctx.borrow();
         ctx.as_ref()
             .map(|handle| handle.spawner.clone())
-            .expect("No reactor running")
+            .expect("No runtime running. Tasks can only be spawned from within `Runtime::block_on`")
     }) {
         Ok(spawner) => spawner,
         Err(_) => panic!("Thread local destroyed"),
     }
 }
+
+pub(crate) fn blocking() -> Rc<Completions> {
+    match CONTEXT.try_with(|ctx| {
+        let ctx = ctx.borrow();
+        ctx.as_ref()
+            .map(|handle| handle.blocking.clone())
+            .expect("No runtime running. Tasks can only be spawned from within `Runtime::block_on`")
+    }) {
+        Ok(blocking) => blocking,
+        Err(_) => panic!("Thread local destroyed"),
+    }
+}
+
+/// Returns the spawner of the current runtime, if one is running
+pub(crate) fn try_spawner() -> Option<Spawner> {
+    CONTEXT
+        .try_with(|ctx| {
+            let ctx = ctx.borrow();
+            ctx.as_ref().map(|handle| handle.spawner.clone())
+        })
+        .ok()
+        .flatten()
+}
+
+/// Returns the generator of the current runtime, if one is running and was
+/// given a seed
+pub(crate) fn try_rng() -> Option<Rc<Rng>> {
+    CONTEXT
+        .try_with(|ctx| {
+            let ctx = ctx.borrow();
+            ctx.as_ref().and_then(|handle| handle.rng.clone())
+        })
+        .ok()
+        .flatten()
+}
@@ -0,0 +1,54 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::task::TaskId;
+
+pub(super) type ScheduleHook = Rc<dyn Fn(TaskId)>;
+pub(super) type PollHook = Rc<dyn Fn(TaskId, Duration)>;
+pub(super) type ThreadHook = Arc<dyn Fn() + Send + Sync>;
+
+/// Callbacks run as tasks move through the run queue, set on the
+/// [`Builder`](super::Builder)
+///
+/// Without a poll hook the queue isn't timestamped, so runtimes without
+/// hooks don't pay for them beyond a check per task
+#[derive(Default)]
+pub(crate) struct Hooks {
+    on_schedule: Option<ScheduleHook>,
+    on_poll: Option<PollHook>,
+    /// When each queued task was scheduled, in queue order. Only kept while
+    /// there's a poll hook
+    scheduled_at: RefCell<VecDeque<Instant>>,
+}
+
+impl Hooks {
+    pub fn new(on_schedule: Option<ScheduleHook>, on_poll: Option<PollHook>) -> Hooks {
+        Hooks {
+            on_schedule,
+            on_poll,
+            scheduled_at: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Called after a task is pushed onto the run queue
+    pub fn scheduled(&self, id: TaskId) {
+        if self.on_poll.is_some() {
+            self.scheduled_at.borrow_mut().push_back(Instant::now());
+        }
+        if let Some(hook) = &self.on_schedule {
+            hook(id)
+        }
+    }
+
+    /// Called after a task is popped off the run queue, before it is polled
+    pub fn polled(&self, id: TaskId) {
+        if let Some(hook) = &self.on_poll {
+            let scheduled_at = self.scheduled_at.borrow_mut().pop_front();
+            let queued = scheduled_at.map_or(Duration::ZERO, |at| at.elapsed());
+            hook(id, queued)
+        }
+    }
+}
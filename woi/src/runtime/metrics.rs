@@ -0,0 +1,141 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Counters updated by the runtime as it runs. These are shared
+/// between the runtime and its spawners
+#[derive(Default)]
+pub(crate) struct Metrics {
+    local_spawn_count: Cell<u64>,
+    outside_spawn_count: Cell<u64>,
+    /// Number of tasks ever spawned
+    total_tasks_spawned: Cell<u64>,
+    /// Number of times a task has been polled
+    poll_count: Cell<u64>,
+    /// Total time spent polling tasks, in nanoseconds
+    poll_time_nanos: Cell<u64>,
+    io_driver_ready_count: Cell<u64>,
+    /// Most tasks the run queue has held at once
+    max_queue_depth: Cell<usize>,
+    /// Number of wakes from other threads. Shared with the wakers and
+    /// threads that wake the runtime through an eventfd
+    remote_wakes: Arc<AtomicU64>,
+}
+
+/// A snapshot of the runtime's metrics, taken with [`Runtime::metrics`]
+///
+/// [`Runtime::metrics`]: crate::Runtime::metrics
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeMetrics {
+    /// Number of tasks spawned from within the runtime's context, i.e. from
+    /// inside `block_on` or from another task
+    pub local_spawn_count: u64,
+    /// Number of tasks spawned while the runtime's context wasn't entered,
+    /// through the runtime or a [`Handle`]. These are tasks spawned before
+    /// or in between calls to `block_on`. A [`Handle`] can't be sent to
+    /// another thread, so none of them come from other threads
+    ///
+    /// [`Handle`]: crate::runtime::Handle
+    pub outside_spawn_count: u64,
+    /// Number of tasks spawned since the runtime was created, however they
+    /// were spawned, including by [`spawn_blocking`]. Completed tasks are
+    /// still counted, so it only ever goes up
+    ///
+    /// [`spawn_blocking`]: crate::task::spawn_blocking
+    pub total_tasks_spawned: u64,
+    /// Mean time taken to poll a task, over every poll since the runtime
+    /// was created. Zero if no task has been polled yet
+    pub mean_poll_time: Duration,
+    /// Number of readiness events the IO driver has processed
+    pub io_driver_ready_count: u64,
+    /// Number of reads and writes completed through io_uring. Zero unless
+    /// the runtime was built with [`Builder::io_uring`] and a ring could
+    /// be set up
+    ///
+    /// [`Builder::io_uring`]: crate::runtime::Builder
+    pub io_uring_completions: u64,
+    /// Capacity of the run queue. It only grows past the capacity set with
+    /// [`Builder::global_queue_capacity`] when more tasks are queued at once
+    ///
+    /// [`Builder::global_queue_capacity`]: crate::runtime::Builder::global_queue_capacity
+    pub run_queue_capacity: usize,
+    /// The most tasks the run queue has held at once. Useful for sizing the
+    /// queue and spotting bursts of spawns or wakes
+    pub max_queue_depth: usize,
+    /// Number of wakes that came from another thread: wakes of the
+    /// `block_on` future from another thread, and blocking closures
+    /// finishing. These go through an eventfd, which makes them more
+    /// expensive than wakes on the runtime's thread
+    pub remote_wakes: u64,
+    /// Number of blocking closures waiting for a thread of the blocking
+    /// pool. The pool is shared by every runtime in the process, and so
+    /// are this and the thread counts
+    pub blocking_queue_depth: usize,
+    /// Number of threads the blocking pool is running, idle or not
+    pub blocking_threads: usize,
+    /// Number of threads of the blocking pool waiting for a closure
+    pub idle_blocking_threads: usize,
+}
+
+impl Metrics {
+    pub fn incr_local_spawn_count(&self) {
+        self.local_spawn_count.set(self.local_spawn_count.get() + 1);
+    }
+
+    pub fn incr_outside_spawn_count(&self) {
+        self.outside_spawn_count
+            .set(self.outside_spawn_count.get() + 1);
+    }
+
+    pub fn incr_total_tasks_spawned(&self) {
+        self.total_tasks_spawned
+            .set(self.total_tasks_spawned.get() + 1);
+    }
+
+    pub fn record_poll(&self, elapsed: Duration) {
+        self.poll_count.set(self.poll_count.get() + 1);
+        self.poll_time_nanos
+            .set(self.poll_time_nanos.get() + elapsed.as_nanos() as u64);
+    }
+
+    pub fn incr_io_driver_ready_count(&self, events: usize) {
+        self.io_driver_ready_count
+            .set(self.io_driver_ready_count.get() + events as u64);
+    }
+
+    pub fn update_max_queue_depth(&self, depth: usize) {
+        if depth > self.max_queue_depth.get() {
+            self.max_queue_depth.set(depth);
+        }
+    }
+
+    /// Counter for threads that wake the runtime to increment
+    pub fn remote_wakes(&self) -> Arc<AtomicU64> {
+        self.remote_wakes.clone()
+    }
+
+    pub fn snapshot(&self) -> RuntimeMetrics {
+        let mean_poll_time = match self.poll_count.get() {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos(self.poll_time_nanos.get() / n),
+        };
+
+        RuntimeMetrics {
+            local_spawn_count: self.local_spawn_count.get(),
+            outside_spawn_count: self.outside_spawn_count.get(),
+            total_tasks_spawned: self.total_tasks_spawned.get(),
+            mean_poll_time,
+            io_driver_ready_count: self.io_driver_ready_count.get(),
+            // Filled in by the runtime, which owns the reactor and the queue
+            io_uring_completions: 0,
+            run_queue_capacity: 0,
+            max_queue_depth: self.max_queue_depth.get(),
+            remote_wakes: self.remote_wakes.load(Ordering::Relaxed),
+            // Filled in from the blocking pool
+            blocking_queue_depth: 0,
+            blocking_threads: 0,
+            idle_blocking_threads: 0,
+        }
+    }
+}
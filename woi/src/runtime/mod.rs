@@ -1,4 +1,14 @@
+mod builder;
+pub use builder::{Builder, UnhandledPanic};
+
 pub(crate) mod context;
 
+mod hooks;
+
+mod metrics;
+pub use metrics::RuntimeMetrics;
+
+pub mod multi_thread;
+
 mod runtime;
-pub use runtime::Runtime;
+pub use runtime::{Handle, Runtime};
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of a worker. Only the worker updates them, other threads read
+/// them
+#[derive(Default)]
+pub(super) struct WorkerStats {
+    steal_count: AtomicU64,
+    failed_steal_count: AtomicU64,
+}
+
+/// A snapshot of a multi-threaded runtime's metrics, taken with
+/// [`Runtime::metrics`]
+///
+/// The counts are kept by each worker and summed up when the snapshot is
+/// taken. [`workers`](Self::workers) has them worker by worker
+///
+/// [`Runtime::metrics`]: super::Runtime::metrics
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeMetrics {
+    /// Number of times a worker that ran out of tasks took some from
+    /// another worker's queue. Many of these mean tasks are spawned on a
+    /// few workers and run on the rest
+    pub steal_count: u64,
+    /// Number of times a worker that ran out of tasks looked in another
+    /// worker's queue and found it empty. Many of these, next to few
+    /// steals, mean there are more workers than there is work
+    pub failed_steal_count: u64,
+    /// The metrics of each worker, by its index
+    pub workers: Vec<WorkerMetrics>,
+}
+
+/// The metrics of one worker of a multi-threaded runtime, see
+/// [`RuntimeMetrics`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkerMetrics {
+    /// Number of steals this worker made
+    pub steal_count: u64,
+    /// Number of steals this worker attempted and found nothing to take
+    pub failed_steal_count: u64,
+}
+
+impl WorkerStats {
+    pub fn incr_steal_count(&self) {
+        self.steal_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn incr_failed_steal_count(&self) {
+        self.failed_steal_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> WorkerMetrics {
+        WorkerMetrics {
+            steal_count: self.steal_count.load(Ordering::Relaxed),
+            failed_steal_count: self.failed_steal_count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl RuntimeMetrics {
+    /// Sums up the metrics of the workers
+    pub(super) fn from_workers(workers: Vec<WorkerMetrics>) -> RuntimeMetrics {
+        RuntimeMetrics {
+            steal_count: workers.iter().map(|worker| worker.steal_count).sum(),
+            failed_steal_count: workers.iter().map(|worker| worker.failed_steal_count).sum(),
+            workers,
+        }
+    }
+}
@@ -0,0 +1,793 @@
+//! A runtime that runs tasks on a pool of worker threads
+//!
+//! The [`Runtime`](crate::Runtime) runs every task on the thread that calls
+//! `block_on`, which is why its tasks don't have to be `Send`. This one
+//! runs them on worker threads instead, so its tasks and their outputs do.
+//! It's built with [`Builder::build_multi_thread`]
+//!
+//! ```
+//! let rt = woi::runtime::Builder::new()
+//!     .worker_threads(4)
+//!     .build_multi_thread()
+//!     .unwrap();
+//!
+//! let handles: Vec<_> = (0..8u64).map(|i| rt.spawn(async move { i * i })).collect();
+//! let sum = rt.block_on(async {
+//!     let mut sum = 0;
+//!     for handle in handles {
+//!         sum += handle.await.unwrap();
+//!     }
+//!     sum
+//! });
+//! assert_eq!(sum, 140);
+//! ```
+//!
+//! Each worker has a queue of its own. Tasks spawned or woken on a worker
+//! go on its queue, and tasks spawned or woken from any other thread go on
+//! a queue shared by all of them. A worker that runs out of tasks takes
+//! some from the shared queue, or steals half of another worker's queue,
+//! before it parks
+//!
+//! The task spawned or woken last on a worker is kept in a slot of its own
+//! and run before the rest of the queue, so a task that spawns a child and
+//! awaits it has the child run straight after, on the same thread
+//!
+//! Timers, IO resources and channels of this crate belong to the runtime
+//! of the thread they're created on, which here would be whichever worker
+//! polled the task first. None of them are `Send`, so tasks can't hold
+//! them across an await. Tasks can wait on anything that wakes them from
+//! another thread, like a `futures` channel or a [`JoinHandle`]
+//!
+//! [`Builder::build_multi_thread`]: super::Builder::build_multi_thread
+
+mod metrics;
+pub use metrics::{RuntimeMetrics, WorkerMetrics};
+
+mod task;
+pub use task::JoinHandle;
+
+mod worker;
+
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+
+use self::worker::Shared;
+use super::builder::Builder;
+use crate::io::eventfd::EventFd;
+
+/// A runtime that runs tasks on a pool of worker threads. See the
+/// [module](self) docs
+///
+/// Dropping the runtime stops its workers, waiting for each to finish the
+/// task it's polling, then drops the futures of the tasks that are left.
+/// Their handles resolve to [`JoinError::Cancelled`]
+///
+/// [`JoinError::Cancelled`]: crate::JoinError::Cancelled
+pub struct Runtime {
+    handle: Handle,
+    /// The worker threads
+    threads: Vec<thread::JoinHandle<()>>,
+    /// Whether the workers are run by threads calling `run_worker` instead
+    external_worker_threads: bool,
+}
+
+/// Handle to a [`Runtime`]. It can be cloned and sent to other threads to
+/// spawn tasks from there
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+// ===== impl Runtime =====
+
+impl Runtime {
+    pub(super) fn from_builder(builder: &Builder) -> io::Result<Runtime> {
+        let workers = builder
+            .worker_threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |workers| workers.get()));
+        match Runtime::start(builder, workers) {
+            Err(e) if builder.fallback_to_current_thread => {
+                tracing::debug!(
+                    "Failed to start worker threads, running tasks on `block_on`: {}",
+                    e
+                );
+                Runtime::start(builder, 0)
+            }
+            res => res,
+        }
+    }
+
+    // Starts a runtime with `workers` worker threads. If one of them can't
+    // be started, the runtime is dropped, which stops the ones that were
+    fn start(builder: &Builder, workers: usize) -> io::Result<Runtime> {
+        let (shared, cores) = Shared::new(workers, builder);
+        let mut rt = Runtime {
+            handle: Handle {
+                shared: Arc::new(shared),
+            },
+            threads: Vec::with_capacity(cores.len()),
+            external_worker_threads: builder.external_worker_threads,
+        };
+        for core in cores {
+            let thread = spawn_worker(rt.handle.shared.clone(), core)?;
+            rt.threads.push(thread);
+        }
+        Ok(rt)
+    }
+
+    /// Get the handle to the runtime
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    /// Get a snapshot of the runtime's metrics
+    pub fn metrics(&self) -> RuntimeMetrics {
+        self.handle.shared.metrics()
+    }
+
+    /// Spawns a task onto the runtime. It starts running on one of the
+    /// workers straight away, whether or not `block_on` is running. On a
+    /// runtime that [fell back] to running tasks on `block_on`, it waits
+    /// for `block_on` instead
+    ///
+    /// [fell back]: super::Builder::fallback_to_current_thread
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.handle.spawn(future)
+    }
+
+    /// Runs one of the workers on the calling thread until the runtime shuts
+    /// down. See [`Handle::run_worker`]
+    pub fn run_worker(&self) -> io::Result<()> {
+        self.handle.run_worker()
+    }
+
+    /// Runs `future` to completion on the calling thread, which parks while
+    /// the future is waiting. The future doesn't have to be `Send`, since
+    /// it never leaves the thread. Tasks it spawns run on the workers, or
+    /// on this thread while the future waits if the runtime has none
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let shared = &self.handle.shared;
+        let _enter = worker::enter(shared.clone());
+        crate::pin!(future);
+
+        let root = Arc::new(RootWaker {
+            woken: AtomicBool::new(false),
+            unpark: Arc::new(EventFd::new().expect("Could not create eventfd to park on")),
+        });
+        let waker = Waker::from(root.clone());
+        let cx = &mut Context::from_waker(&waker);
+
+        loop {
+            root.woken.store(false, Ordering::SeqCst);
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                return output;
+            }
+            if shared.has_workers() {
+                // A wake from before this point left the eventfd readable,
+                // so the wait returns straight away
+                if !root.woken.load(Ordering::SeqCst) {
+                    if let Err(e) = root.unpark.wait() {
+                        tracing::debug!("Failed to park `block_on`: {}", e);
+                    }
+                }
+            } else {
+                // The future is only polled again once it's woken, so
+                // there's no point stopping before that
+                shared.run_injected(&root.woken);
+                if !root.woken.load(Ordering::SeqCst) {
+                    shared.park(&root.unpark);
+                }
+            }
+        }
+    }
+
+    /// Shuts the runtime down. See [`Runtime`]
+    pub fn shutdown(self) {
+        drop(self)
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        self.handle.shared.stop();
+        for thread in self.threads.drain(..) {
+            // A worker only panics if dropping a task's output did
+            let _ = thread.join();
+        }
+        if self.external_worker_threads {
+            self.handle.shared.wait_for_workers();
+        }
+        self.handle.shared.shut_down();
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    // Number of worker threads this thread can start before starting one
+    // fails, as it would once the system is out of threads
+    static SPAWN_LIMIT: std::cell::Cell<Option<usize>> = const { std::cell::Cell::new(None) };
+}
+
+fn spawn_worker(shared: Arc<Shared>, core: worker::Core) -> io::Result<thread::JoinHandle<()>> {
+    let unpark = Arc::new(EventFd::new()?);
+    #[cfg(test)]
+    if let Some(limit) = SPAWN_LIMIT.with(|l| l.get()) {
+        if limit == 0 {
+            return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+        }
+        SPAWN_LIMIT.with(|l| l.set(Some(limit - 1)));
+    }
+    thread::Builder::new()
+        .name("woi-worker".into())
+        .spawn(move || {
+            worker::run(shared, core, unpark);
+        })
+}
+
+// ===== impl Handle =====
+
+impl Handle {
+    /// Returns the handle of the runtime whose worker, or `block_on`, is
+    /// running on this thread
+    ///
+    /// Panics if there isn't one. Tasks spawned onto a [`Runtime`] can call
+    /// this to spawn tasks of their own
+    pub fn current() -> Handle {
+        match Handle::try_current() {
+            Some(handle) => handle,
+            None => panic!(
+                "No multi-threaded runtime running. Tasks can only be spawned from within \
+                 its workers or `Runtime::block_on`"
+            ),
+        }
+    }
+
+    /// Returns the handle of the runtime whose worker, or `block_on`, is
+    /// running on this thread, if there is one
+    pub fn try_current() -> Option<Handle> {
+        worker::current().map(|shared| Handle { shared })
+    }
+
+    /// Spawns a task onto the runtime. See [`Runtime::spawn`]
+    ///
+    /// A task spawned from one of the workers goes on that worker's queue.
+    /// Tasks spawned once the runtime has shut down are dropped unrun, and
+    /// their handles resolve to [`JoinError::Cancelled`]
+    ///
+    /// [`JoinError::Cancelled`]: crate::JoinError::Cancelled
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.shared.spawn(future)
+    }
+
+    /// Runs one of the workers of a runtime built with
+    /// [`Builder::external_worker_threads`] on the calling thread, until
+    /// the runtime shuts down
+    ///
+    /// This is how threads the app manages take up the work the runtime
+    /// would otherwise start threads for:
+    ///
+    /// - Each call runs one of the [`worker_threads`] workers, so up to that
+    ///   many threads can run workers at once. Calling it while all of them
+    ///   are running panics
+    /// - No task runs until a thread calls it, so a runtime with none only
+    ///   queues its tasks
+    /// - The [`on_thread_start`] and [`on_thread_stop`] callbacks run on
+    ///   the calling thread as it starts and stops running the worker
+    /// - It returns once the runtime is shut down, straight away if it
+    ///   already is. Tasks left in the worker's queue stay with the
+    ///   runtime for the next call, or are dropped when it shuts down
+    /// - Dropping the runtime waits for every call to return, so it must
+    ///   not be dropped from one of its own tasks
+    ///
+    /// Fails if the eventfd the worker parks on can't be created
+    ///
+    /// ```
+    /// use std::thread;
+    ///
+    /// let rt = woi::runtime::Builder::new()
+    ///     .worker_threads(2)
+    ///     .external_worker_threads()
+    ///     .build_multi_thread()
+    ///     .unwrap();
+    ///
+    /// let threads: Vec<_> = (0..2)
+    ///     .map(|_| {
+    ///         let handle = rt.handle().clone();
+    ///         thread::spawn(move || handle.run_worker().unwrap())
+    ///     })
+    ///     .collect();
+    /// assert_eq!(rt.block_on(rt.spawn(async { 1 + 1 })).unwrap(), 2);
+    ///
+    /// drop(rt);
+    /// for thread in threads {
+    ///     thread.join().unwrap();
+    /// }
+    /// ```
+    ///
+    /// [`Builder::external_worker_threads`]: super::Builder::external_worker_threads
+    /// [`worker_threads`]: super::Builder::worker_threads
+    /// [`on_thread_start`]: super::Builder::on_thread_start
+    /// [`on_thread_stop`]: super::Builder::on_thread_stop
+    pub fn run_worker(&self) -> io::Result<()> {
+        self.shared.run_worker()
+    }
+}
+
+// ===== Root waker =====
+
+// Waker of the future passed to `block_on`. The thread parks on the eventfd
+// whenever the future is pending, so a wake from any thread unparks it
+struct RootWaker {
+    woken: AtomicBool,
+    unpark: Arc<EventFd>,
+}
+
+impl Wake for RootWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // Only the first wake since the last poll has to unpark
+        if !self.woken.swap(true, Ordering::SeqCst) {
+            if let Err(e) = self.unpark.notify() {
+                tracing::debug!("Failed to unpark `block_on`: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    fn runtime(workers: usize) -> Runtime {
+        Builder::new()
+            .worker_threads(workers)
+            .build_multi_thread()
+            .unwrap()
+    }
+
+    #[test]
+    fn thousands_of_tasks_resolve_once() {
+        const TASKS: usize = 10_000;
+
+        let rt = runtime(4);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let threads = Arc::new(Mutex::new(std::collections::HashSet::new()));
+        let handles: Vec<_> = (0..TASKS)
+            .map(|i| {
+                let (runs, threads) = (runs.clone(), threads.clone());
+                rt.spawn(async move {
+                    // Requeued from the worker, so it may be stolen
+                    crate::task::yield_now().await;
+                    threads.lock().unwrap().insert(thread::current().id());
+                    runs.fetch_add(1, Ordering::Relaxed);
+                    i
+                })
+            })
+            .collect();
+
+        let outputs = rt.block_on(async {
+            let mut outputs = Vec::with_capacity(TASKS);
+            for handle in handles {
+                outputs.push(handle.await.unwrap());
+            }
+            outputs
+        });
+        assert_eq!(outputs, (0..TASKS).collect::<Vec<_>>());
+        assert_eq!(runs.load(Ordering::Relaxed), TASKS);
+        // None of them ran on the thread of `block_on`
+        assert!(!threads.lock().unwrap().contains(&thread::current().id()));
+    }
+
+    #[test]
+    fn wakes_from_many_threads_poll_one_at_a_time() {
+        use futures::channel::oneshot;
+
+        const TASKS: usize = 64;
+
+        let rt = runtime(4);
+        let overlapping = Arc::new(AtomicBool::new(false));
+        let mut senders = Vec::new();
+        let mut wakers = Vec::new();
+        let handles: Vec<_> = (0..TASKS)
+            .map(|_| {
+                let (tx, rx) = oneshot::channel::<()>();
+                let (waker_tx, waker_rx) = std::sync::mpsc::channel();
+                senders.push(tx);
+                wakers.push(waker_rx);
+                let overlapping = overlapping.clone();
+                let polling = AtomicBool::new(false);
+                let mut rx = rx;
+                rt.spawn(futures::future::poll_fn(move |cx| {
+                    if polling.swap(true, Ordering::SeqCst) {
+                        overlapping.store(true, Ordering::SeqCst);
+                    }
+                    let _ = waker_tx.send(cx.waker().clone());
+                    let res = std::pin::Pin::new(&mut rx).poll(cx);
+                    polling.store(false, Ordering::SeqCst);
+                    res.map(|_| ())
+                }))
+            })
+            .collect();
+
+        // Several threads wake every task over and over while it's pending,
+        // and while it's being polled
+        let wakers: Vec<Waker> = wakers.iter().map(|rx| rx.recv().unwrap()).collect();
+        let wakers = Arc::new(wakers);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let wakers = wakers.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        for waker in wakers.iter() {
+                            waker.wake_by_ref();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        for tx in senders {
+            tx.send(()).unwrap();
+        }
+
+        rt.block_on(async {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+        assert!(!overlapping.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn injected_tasks_are_not_starved_by_local_spawns() {
+        use std::sync::mpsc;
+
+        let rt = Builder::new()
+            .worker_threads(1)
+            .global_queue_interval(8)
+            .build_multi_thread()
+            .unwrap();
+        let injected = Arc::new(AtomicBool::new(false));
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let (started_tx, started_rx) = mpsc::channel();
+        let spawner = {
+            let (injected, spawned) = (injected.clone(), spawned.clone());
+            rt.spawn(async move {
+                started_tx.send(()).unwrap();
+                // The worker's queue never empties, since it always holds
+                // this task or the child it just spawned
+                while !injected.load(Ordering::SeqCst) {
+                    Handle::current().spawn(async {});
+                    spawned.fetch_add(1, Ordering::SeqCst);
+                    crate::task::yield_now().await;
+                }
+            })
+        };
+
+        started_rx.recv().unwrap();
+        let remote = {
+            let injected = injected.clone();
+            rt.spawn(async move { injected.store(true, Ordering::SeqCst) })
+        };
+        // At least this many had been spawned when the remote task was
+        // queued
+        let before = spawned.load(Ordering::SeqCst);
+        rt.block_on(async {
+            remote.await.unwrap();
+            spawner.await.unwrap();
+        });
+
+        // Each round is two polls, the spawner's and its child's, so the
+        // remote task ran within about four rounds of being queued
+        let after = spawned.load(Ordering::SeqCst);
+        assert!(after - before <= 8, "{} rounds", after - before);
+    }
+
+    #[test]
+    fn idle_workers_steal_from_busy_one() {
+        use std::sync::mpsc;
+
+        let rt = runtime(2);
+        let (ran_tx, ran_rx) = mpsc::channel();
+        rt.block_on(rt.spawn(async move {
+            // Queued on this worker, which is stuck in this poll until one
+            // of them has run. Only the other worker can get to them
+            let children: Vec<_> = (0..4)
+                .map(|_| {
+                    let ran_tx = ran_tx.clone();
+                    Handle::current().spawn(async move { ran_tx.send(()).unwrap() })
+                })
+                .collect();
+            ran_rx.recv().unwrap();
+            children
+        }))
+        .unwrap();
+
+        let metrics = rt.metrics();
+        assert!(metrics.steal_count > 0, "{:?}", metrics);
+        assert_eq!(metrics.workers.len(), 2);
+        let per_worker: u64 = metrics
+            .workers
+            .iter()
+            .map(|worker| worker.steal_count)
+            .sum();
+        assert_eq!(per_worker, metrics.steal_count);
+    }
+
+    #[test]
+    fn awaited_child_runs_before_queued_tasks() {
+        const CHILDREN: usize = 100;
+
+        // One worker, so the tasks run in the order it picks them
+        let rt = runtime(1);
+        let first = rt
+            .block_on(rt.spawn(async {
+                let mut first = 0;
+                for _ in 0..CHILDREN {
+                    // Queued ahead of the child
+                    let ran = Arc::new(AtomicBool::new(false));
+                    drop(Handle::current().spawn({
+                        let ran = ran.clone();
+                        async move { ran.store(true, Ordering::SeqCst) }
+                    }));
+                    let child = Handle::current().spawn(async move { !ran.load(Ordering::SeqCst) });
+                    if child.await.unwrap() {
+                        first += 1;
+                    }
+                }
+                first
+            }))
+            .unwrap();
+        assert_eq!(first, CHILDREN);
+    }
+
+    #[test]
+    fn lifo_slot_is_stolen_from_busy_worker() {
+        use std::sync::mpsc;
+
+        let rt = runtime(2);
+        let (spawned_tx, spawned_rx) = mpsc::channel();
+        let (ran_tx, ran_rx) = mpsc::channel();
+        let parent = rt.spawn(async move {
+            let child = Handle::current().spawn(async move {
+                ran_tx.send(thread::current().id()).unwrap();
+            });
+            spawned_tx.send(()).unwrap();
+            // The child is in this worker's LIFO slot, and the worker is
+            // stuck in this poll until the child has run
+            let child_thread = ran_rx.recv().unwrap();
+            (child, thread::current().id(), child_thread)
+        });
+        spawned_rx.recv().unwrap();
+        // A child in the LIFO slot doesn't unpark anyone. This does, and the
+        // worker that runs it goes on to steal the child
+        drop(rt.spawn(async {}));
+
+        let (child, parent_thread, child_thread) = rt.block_on(parent).unwrap();
+        rt.block_on(child).unwrap();
+        assert_ne!(parent_thread, child_thread);
+        assert!(rt.metrics().steal_count > 0);
+    }
+
+    #[test]
+    fn external_threads_run_the_workers() {
+        use std::collections::HashSet;
+
+        let started = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let rt = Builder::new()
+            .worker_threads(2)
+            .external_worker_threads()
+            .on_thread_start({
+                let started = started.clone();
+                move || {
+                    started.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .on_thread_stop({
+                let stopped = stopped.clone();
+                move || {
+                    stopped.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .build_multi_thread()
+            .unwrap();
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let handle = rt.handle().clone();
+                thread::spawn(move || handle.run_worker().unwrap())
+            })
+            .collect();
+        let ids: HashSet<_> = threads.iter().map(|thread| thread.thread().id()).collect();
+
+        let handles: Vec<_> = (0..100)
+            .map(|_| rt.spawn(async { thread::current().id() }))
+            .collect();
+        let ran_on = rt.block_on(async {
+            let mut ran_on = HashSet::new();
+            for handle in handles {
+                ran_on.insert(handle.await.unwrap());
+            }
+            ran_on
+        });
+        assert!(ran_on.is_subset(&ids), "{:?} not in {:?}", ran_on, ids);
+
+        // Returns once both threads are done with their workers
+        drop(rt);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        assert_eq!(stopped.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Every worker of the runtime is already running")]
+    fn running_a_worker_the_runtime_runs_panics() {
+        // Its one worker runs on a thread the runtime started
+        let rt = runtime(1);
+        rt.run_worker().unwrap();
+    }
+
+    // Counts the worker threads that start and stop
+    fn counting_builder(started: &Arc<AtomicUsize>, stopped: &Arc<AtomicUsize>) -> Builder {
+        let (started, stopped) = (started.clone(), stopped.clone());
+        let mut builder = Builder::new();
+        builder
+            .worker_threads(4)
+            .on_thread_start(move || {
+                started.fetch_add(1, Ordering::SeqCst);
+            })
+            .on_thread_stop(move || {
+                stopped.fetch_add(1, Ordering::SeqCst);
+            });
+        builder
+    }
+
+    #[test]
+    fn failed_thread_spawn_stops_started_workers() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let builder = counting_builder(&started, &stopped);
+
+        SPAWN_LIMIT.with(|l| l.set(Some(2)));
+        let res = builder.build_multi_thread();
+        SPAWN_LIMIT.with(|l| l.set(None));
+
+        let err = res.err().expect("started more threads than the limit");
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+        // Both threads that started were joined before the error returned
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        assert_eq!(stopped.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn failed_eventfd_falls_back_to_current_thread() {
+        use futures::channel::oneshot;
+
+        let started = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let mut builder = counting_builder(&started, &stopped);
+        builder.fallback_to_current_thread();
+
+        // The first worker gets its eventfd, the second doesn't
+        crate::io::eventfd::set_limit(Some(1));
+        let res = builder.build_multi_thread();
+        crate::io::eventfd::set_limit(None);
+
+        let rt = res.unwrap();
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+        assert_eq!(stopped.load(Ordering::SeqCst), 1);
+
+        let (tx, rx) = oneshot::channel();
+        let handle = rt.handle().clone();
+        let spawner = thread::spawn(move || {
+            let task = handle.spawn(async { thread::current().id() });
+            assert!(tx.send(task).is_ok());
+        });
+        // Spawned from another thread while `block_on` is parked, and run
+        // by `block_on`
+        let ran_on = rt.block_on(async { rx.await.unwrap().await.unwrap() });
+        spawner.join().unwrap();
+        assert_eq!(ran_on, thread::current().id());
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn tasks_spawn_onto_their_runtime() {
+        let rt = runtime(2);
+        let sum = rt.block_on(async {
+            let outer = Handle::current().spawn(async {
+                let children: Vec<_> = (1..=10u32)
+                    .map(|i| Handle::current().spawn(async move { i }))
+                    .collect();
+                let mut sum = 0;
+                for child in children {
+                    sum += child.await.unwrap();
+                }
+                sum
+            });
+            outer.await.unwrap()
+        });
+        assert_eq!(sum, 55);
+    }
+
+    #[test]
+    fn panicking_task_resolves_to_error() {
+        let rt = runtime(2);
+        let handle = rt.spawn(async { panic!("task panicked") });
+        let ok = rt.spawn(async { 1 });
+        let (err, ok) = rt.block_on(async { (handle.await.unwrap_err(), ok.await) });
+        assert!(err.is_panic());
+        // The worker keeps going
+        assert_eq!(ok.unwrap(), 1);
+    }
+
+    #[test]
+    fn handle_resolves_from_another_thread() {
+        let rt = runtime(2);
+        let handle = rt.spawn(async { 7 });
+        let awaited = thread::spawn(move || futures::executor::block_on(handle));
+        assert_eq!(awaited.join().unwrap().unwrap(), 7);
+        assert!(matches!(rt.block_on(rt.spawn(async {})), Ok(())));
+    }
+
+    #[test]
+    fn dropping_runtime_cancels_pending_tasks() {
+        struct DropFlag(Arc<AtomicBool>);
+
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let rt = runtime(2);
+        let flag = DropFlag(dropped.clone());
+        let pending = rt.spawn(async move {
+            let _flag = flag;
+            std::future::pending::<()>().await
+        });
+        let handle = rt.handle().clone();
+
+        drop(rt);
+        assert!(dropped.load(Ordering::SeqCst));
+        let res = futures::executor::block_on(pending);
+        assert!(res.unwrap_err().is_cancelled());
+        // Too late to run
+        let late = handle.spawn(async {});
+        assert!(futures::executor::block_on(late)
+            .unwrap_err()
+            .is_cancelled());
+    }
+
+    #[test]
+    fn aborting_pending_task() {
+        let rt = runtime(2);
+        let handle = rt.spawn(std::future::pending::<()>());
+        handle.abort();
+        let res = rt.block_on(handle);
+        assert!(res.unwrap_err().is_cancelled());
+    }
+}
@@ -0,0 +1,301 @@
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::task::{AtomicState, JoinError, TaskId};
+
+/// Schedules the tasks of the multi-threaded runtime. The counterpart of
+/// the single-threaded `Schedule`, for tasks that are woken on any thread
+pub(super) trait Schedule: Send + Sync + 'static {
+    fn schedule(&self, task: Task);
+
+    /// Queues a task that was woken while it was polled. Unlike a task that
+    /// was just spawned or woken by another, it goes behind the tasks
+    /// already queued, so a task that keeps waking itself can't hog a worker
+    fn yield_now(&self, task: Task) {
+        self.schedule(task)
+    }
+
+    /// Called once the task has completed, so the scheduler can forget it
+    fn release(&self, id: TaskId);
+}
+
+/// A reference to a task of the multi-threaded runtime, as held by the
+/// run queues
+#[derive(Clone)]
+pub(super) struct Task(Arc<dyn Run>);
+
+trait Run: Send + Sync {
+    fn id(&self) -> TaskId;
+
+    fn run(self: Arc<Self>);
+
+    /// Drops the future without polling it again, for tasks that haven't
+    /// completed by the time the runtime shuts down
+    fn shut_down(self: Arc<Self>);
+}
+
+/// Handle to a task spawned onto a [`multi_thread::Runtime`]
+///
+/// It works like [`task::JoinHandle`], except that it's `Send` so the task
+/// can be awaited from any thread. Awaiting it after it has resolved
+/// returns [`JoinError::OutputTaken`]
+///
+/// [`multi_thread::Runtime`]: super::Runtime
+/// [`task::JoinHandle`]: crate::task::JoinHandle
+pub struct JoinHandle<T> {
+    task: Arc<dyn Join<T>>,
+}
+
+trait Join<T>: Send + Sync {
+    fn id(&self) -> TaskId;
+
+    fn poll_join(&self, cx: &mut Context<'_>) -> Poll<crate::task::Result<T>>;
+
+    fn abort(self: Arc<Self>);
+
+    fn drop_join_handle(&self);
+}
+
+// The allocation of a task. It stays put until the last reference goes, so
+// the future is pinned in it
+struct Cell<F: Future, S> {
+    id: TaskId,
+    state: AtomicState,
+    scheduler: S,
+    /// The future, until the task completes. Only the thread that moved the
+    /// task to running locks it, so it's never contended
+    future: Mutex<Option<F>>,
+    /// The output, from when the task completes until the handle takes it
+    output: Mutex<Option<crate::task::Result<F::Output>>>,
+    /// Waker of the task awaiting the handle
+    join_waker: Mutex<Option<Waker>>,
+}
+
+/// Allocates a task for `future`. The returned task must be queued, since
+/// the task starts out scheduled
+pub(super) fn new<F, S>(future: F, scheduler: S) -> (Task, JoinHandle<F::Output>)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+    S: Schedule,
+{
+    let cell = Arc::new(Cell {
+        id: TaskId::new(),
+        state: AtomicState::new(),
+        scheduler,
+        future: Mutex::new(Some(future)),
+        output: Mutex::new(None),
+        join_waker: Mutex::new(None),
+    });
+    (Task(cell.clone()), JoinHandle { task: cell })
+}
+
+// ===== impl Task =====
+
+impl Task {
+    pub fn id(&self) -> TaskId {
+        self.0.id()
+    }
+
+    pub fn run(self) {
+        self.0.run()
+    }
+
+    pub fn shut_down(self) {
+        self.0.shut_down()
+    }
+}
+
+// ===== impl Cell =====
+
+impl<F, S> Cell<F, S>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+    S: Schedule,
+{
+    // Stores the output and wakes the handle. If the handle is gone, the
+    // output is dropped instead
+    fn complete(&self, output: crate::task::Result<F::Output>) {
+        *self.output.lock().unwrap() = Some(output);
+        if !self.state.transition_to_complete() {
+            let output = self.output.lock().unwrap().take();
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(output)));
+        }
+        // Completing happens before the waker is taken, so a handle that
+        // registers its waker after this sees the task complete instead
+        let waker = self.join_waker.lock().unwrap().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        self.scheduler.release(self.id);
+    }
+}
+
+impl<F, S> Run for Cell<F, S>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+    S: Schedule,
+{
+    fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn run(self: Arc<Self>) {
+        if !self.state.transition_to_running() {
+            return;
+        }
+
+        let _span = tracing::debug_span!("poll", task = %self.id).entered();
+        let waker = Waker::from(self.clone());
+        let cx = &mut Context::from_waker(&waker);
+
+        let res = {
+            let mut future = self.future.lock().unwrap();
+            // Panics are caught while the lock is held, so it's never
+            // poisoned
+            if self.state.is_cancelled() {
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| *future = None));
+                Poll::Ready(Err(JoinError::Cancelled))
+            } else {
+                let pinned = match future.as_mut() {
+                    // Safety: the future is never moved out of the cell,
+                    // only dropped in place
+                    Some(future) => unsafe { Pin::new_unchecked(future) },
+                    None => unreachable!("polled a completed task"),
+                };
+                let res = panic::catch_unwind(AssertUnwindSafe(|| pinned.poll(cx)));
+                let output = match res {
+                    Ok(Poll::Pending) => None,
+                    Ok(Poll::Ready(output)) => Some(Ok(output)),
+                    Err(panic) => Some(Err(JoinError::Panic(panic))),
+                };
+                match output {
+                    Some(output) => {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(|| *future = None));
+                        Poll::Ready(output)
+                    }
+                    None => Poll::Pending,
+                }
+            }
+        };
+
+        match res {
+            Poll::Pending => {
+                if self.state.transition_to_idle() {
+                    // Woken while it was polled. That wake left queueing it
+                    // to this thread, so it's only ever queued once
+                    tracing::debug!("Task pending, woken during poll");
+                    self.scheduler.yield_now(Task(self.clone()));
+                }
+            }
+            Poll::Ready(output) => self.complete(output),
+        }
+    }
+
+    fn shut_down(self: Arc<Self>) {
+        // Never polled again, so the run drops the future
+        self.state.transition_to_cancelled();
+        self.run()
+    }
+}
+
+impl<F, S> Wake for Cell<F, S>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+    S: Schedule,
+{
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.state.transition_to_notified() {
+            self.scheduler.schedule(Task(self.clone()));
+        }
+    }
+}
+
+impl<F, S> Join<F::Output> for Cell<F, S>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+    S: Schedule,
+{
+    fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn poll_join(&self, cx: &mut Context<'_>) -> Poll<crate::task::Result<F::Output>> {
+        {
+            let mut join_waker = self.join_waker.lock().unwrap();
+            if !self.state.is_complete() {
+                match &*join_waker {
+                    Some(waker) if waker.will_wake(cx.waker()) => {}
+                    _ => *join_waker = Some(cx.waker().clone()),
+                }
+                return Poll::Pending;
+            }
+        }
+
+        match self.output.lock().unwrap().take() {
+            Some(output) => Poll::Ready(output),
+            None => Poll::Ready(Err(JoinError::OutputTaken)),
+        }
+    }
+
+    fn abort(self: Arc<Self>) {
+        if self.state.transition_to_cancelled() {
+            tracing::debug!("Task {}: Aborting", self.id);
+            self.scheduler.schedule(Task(self.clone()));
+        }
+    }
+
+    fn drop_join_handle(&self) {
+        // The output will never be taken, so drop it now
+        if self.state.unset_join_handle() {
+            let output = self.output.lock().unwrap().take();
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(output)));
+        }
+    }
+}
+
+// ===== impl JoinHandle =====
+
+impl<T> JoinHandle<T> {
+    /// The id of the task
+    pub fn id(&self) -> TaskId {
+        self.task.id()
+    }
+
+    /// Aborts the task
+    ///
+    /// The task's future is dropped instead of being polled again, and the
+    /// handle resolves to [`JoinError::Cancelled`]. A task that is being
+    /// polled when it's aborted is stopped once that poll returns. Aborting
+    /// a task that has already completed does nothing
+    pub fn abort(&self) {
+        self.task.clone().abort()
+    }
+}
+
+impl<T> Unpin for JoinHandle<T> {}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = crate::task::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.task.poll_join(cx)
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        self.task.drop_join_handle()
+    }
+}
@@ -0,0 +1,452 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+
+use super::metrics::{RuntimeMetrics, WorkerStats};
+use super::task::{self, JoinHandle, Schedule, Task};
+use crate::io::eventfd::EventFd;
+use crate::runtime::hooks::ThreadHook;
+use crate::runtime::Builder;
+use crate::task::TaskId;
+
+/// State shared by the workers of a runtime and its handles
+pub(super) struct Shared {
+    /// Tasks spawned or woken from outside the workers
+    injector: Injector<Task>,
+    /// Ends of the workers' queues that other workers steal from, by the
+    /// index of the worker
+    stealers: Box<[Stealer<Task>]>,
+    /// The task each worker runs next, by the index of the worker. A task
+    /// spawned or woken on a worker goes here, so a parent that awaits the
+    /// child it just spawned has the child run next, on the same thread.
+    /// Workers with nothing to do steal from here too, once the queues are
+    /// empty
+    lifo: Box<[Mutex<Option<Task>>]>,
+    /// Eventfds of the workers that are parked, waiting for tasks
+    idle: Mutex<Vec<Arc<EventFd>>>,
+    /// Set once the runtime is shutting down. Workers exit as they see it
+    shutdown: AtomicBool,
+    /// Every task that hasn't completed. Tasks remove themselves as they
+    /// complete, see `Schedule::release`
+    owned: Mutex<HashMap<TaskId, Task>>,
+    /// Number of tasks a worker runs between checks of the injector
+    global_queue_interval: u32,
+    /// Counters of the workers, by their index
+    stats: Box<[WorkerStats]>,
+    /// Cores of the workers no thread is running, for runtimes whose
+    /// workers are run by `run_worker`
+    cores: Mutex<Vec<Core>>,
+    /// Notified whenever a core is put back in `cores`
+    core_returned: Condvar,
+    on_thread_start: Option<ThreadHook>,
+    on_thread_stop: Option<ThreadHook>,
+}
+
+/// What a worker owns: its end of its queue
+pub(super) struct Core {
+    index: usize,
+    local: Worker<Task>,
+    /// Number of tasks the worker has run, to know when to check the
+    /// injector
+    tick: Cell<u32>,
+    /// Number of tasks in a row the worker has taken from its LIFO slot
+    lifo_polls: Cell<u32>,
+}
+
+/// Number of tasks in a row a worker takes from its LIFO slot before it
+/// goes to its queue. Two tasks that keep waking each other would otherwise
+/// hold up the rest of the queue forever
+const MAX_LIFO_POLLS: u32 = 3;
+
+// The worker running on this thread, set for as long as it runs
+struct Context {
+    shared: Arc<Shared>,
+    core: Core,
+}
+
+thread_local! {
+    // The worker running on this thread. Null on other threads
+    static WORKER: Cell<*const Context> = const { Cell::new(ptr::null()) };
+    // The runtime whose worker or `block_on` is running on this thread
+    static CURRENT: RefCell<Option<Arc<Shared>>> = const { RefCell::new(None) };
+}
+
+pub(super) struct EnterGuard {
+    prev: Option<Arc<Shared>>,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        let _ = CURRENT.try_with(|current| *current.borrow_mut() = prev);
+    }
+}
+
+/// Makes `shared` the runtime of this thread until the guard is dropped
+pub(super) fn enter(shared: Arc<Shared>) -> EnterGuard {
+    let prev = CURRENT.with(|current| current.borrow_mut().replace(shared));
+    EnterGuard { prev }
+}
+
+/// The runtime whose worker or `block_on` is running on this thread
+pub(super) fn current() -> Option<Arc<Shared>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+// ===== impl Shared =====
+
+impl Shared {
+    /// Creates the state of a runtime with `workers` workers, along with
+    /// the cores the runtime starts threads for. Those of a runtime with
+    /// external worker threads are kept for `run_worker` instead
+    pub fn new(workers: usize, builder: &Builder) -> (Shared, Vec<Core>) {
+        let mut cores: Vec<_> = (0..workers)
+            .map(|index| Core {
+                index,
+                local: Worker::new_fifo(),
+                tick: Cell::new(0),
+                lifo_polls: Cell::new(0),
+            })
+            .collect();
+        let shared = Shared {
+            injector: Injector::new(),
+            stealers: cores.iter().map(|core| core.local.stealer()).collect(),
+            lifo: (0..workers).map(|_| Mutex::new(None)).collect(),
+            idle: Mutex::new(Vec::new()),
+            shutdown: AtomicBool::new(false),
+            owned: Mutex::new(HashMap::new()),
+            global_queue_interval: builder.global_queue_interval,
+            stats: (0..workers).map(|_| WorkerStats::default()).collect(),
+            cores: Mutex::new(Vec::new()),
+            core_returned: Condvar::new(),
+            on_thread_start: builder.on_thread_start.clone(),
+            on_thread_stop: builder.on_thread_stop.clone(),
+        };
+        if builder.external_worker_threads {
+            // Popped from the back, so the first call runs worker 0
+            cores.reverse();
+            *shared.cores.lock().unwrap() = cores;
+            return (shared, Vec::new());
+        }
+        (shared, cores)
+    }
+
+    pub fn spawn<F>(self: &Arc<Self>, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (task, handle) = task::new(future, self.clone());
+        tracing::debug!(task = %task.id(), "Task {}: Spawned", task.id());
+        {
+            let mut owned = self.owned.lock().unwrap();
+            // Checked under the lock, so a task is either shut down here or
+            // by `shut_down`
+            if self.shutdown.load(Ordering::SeqCst) {
+                drop(owned);
+                task.shut_down();
+                return handle;
+            }
+            owned.insert(task.id(), task.clone());
+        }
+        self.schedule(task);
+        handle
+    }
+
+    pub fn metrics(&self) -> RuntimeMetrics {
+        RuntimeMetrics::from_workers(self.stats.iter().map(WorkerStats::snapshot).collect())
+    }
+
+    /// Whether the runtime has workers. One that fell back to running its
+    /// tasks on `block_on` has none
+    pub fn has_workers(&self) -> bool {
+        !self.stealers.is_empty()
+    }
+
+    /// Runs the tasks in the injector on this thread, for runtimes without
+    /// workers, until it's empty or `stop` is set
+    pub fn run_injected(&self, stop: &AtomicBool) {
+        while !stop.load(Ordering::SeqCst) {
+            match steal(|| self.injector.steal()) {
+                Some(task) => task.run(),
+                None => return,
+            }
+        }
+    }
+
+    /// Waits for a task to be queued. The thread is listed as idle before
+    /// it checks the queues one last time, so a task queued after that
+    /// check finds it in the list and unparks it. Anything else that writes
+    /// to `unpark` unparks it too
+    pub fn park(&self, unpark: &Arc<EventFd>) {
+        self.idle.lock().unwrap().push(unpark.clone());
+        if !self.is_shutdown() && !self.has_tasks() {
+            if let Err(e) = unpark.wait() {
+                tracing::debug!("Failed to park: {}", e);
+            }
+        }
+        // Still listed if it found a task, or was unparked by a wake meant
+        // for an earlier park
+        self.idle
+            .lock()
+            .unwrap()
+            .retain(|idle| !Arc::ptr_eq(idle, unpark));
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Stops the workers. They exit once they're done with the task they're
+    /// polling
+    pub fn stop(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        for unpark in self.idle.lock().unwrap().drain(..) {
+            notify(&unpark);
+        }
+    }
+
+    /// Drops the future of every task that hasn't completed. Called once
+    /// the workers have exited, so none of them is being polled
+    pub fn shut_down(&self) {
+        let tasks: Vec<Task> = {
+            let mut owned = self.owned.lock().unwrap();
+            owned.drain().map(|(_, task)| task).collect()
+        };
+        for task in tasks {
+            task.shut_down();
+        }
+        // The queued references of tasks that were shut down
+        while !self.injector.is_empty() {
+            drop(self.injector.steal());
+        }
+        for slot in self.lifo.iter() {
+            drop(slot.lock().unwrap().take());
+        }
+    }
+
+    /// Runs a worker whose core no thread is running on this thread, until
+    /// the runtime shuts down. See `Handle::run_worker`
+    pub fn run_worker(self: &Arc<Self>) -> io::Result<()> {
+        let core = self.cores.lock().unwrap().pop();
+        let core = core.expect("Every worker of the runtime is already running");
+        let core = match EventFd::new() {
+            Ok(unpark) => run(self.clone(), core, Arc::new(unpark)),
+            Err(e) => {
+                self.return_core(core);
+                return Err(e);
+            }
+        };
+        self.return_core(core);
+        Ok(())
+    }
+
+    /// Waits for every thread in `run_worker` to return. Called once the
+    /// runtime has stopped
+    pub fn wait_for_workers(&self) {
+        let mut cores = self.cores.lock().unwrap();
+        while cores.len() < self.stealers.len() {
+            cores = self.core_returned.wait(cores).unwrap();
+        }
+    }
+
+    fn return_core(&self, core: Core) {
+        self.cores.lock().unwrap().push(core);
+        self.core_returned.notify_all();
+    }
+
+    // Unparks a parked worker, if there is one, to run a task that was just
+    // queued
+    fn notify_parked(&self) {
+        let unpark = self.idle.lock().unwrap().pop();
+        if let Some(unpark) = unpark {
+            notify(&unpark);
+        }
+    }
+
+    fn has_tasks(&self) -> bool {
+        !self.injector.is_empty()
+            || self.stealers.iter().any(|stealer| !stealer.is_empty())
+            || self.lifo.iter().any(|slot| slot.lock().unwrap().is_some())
+    }
+
+    // Queues `task` on this thread's worker if it belongs to this runtime,
+    // otherwise hands it back. `lifo` puts it in the worker's LIFO slot,
+    // moving the task it holds to the back of the queue. Returns whether a
+    // task was added to a queue other workers can steal from
+    fn schedule_local(self: &Arc<Self>, task: Task, lifo: bool) -> Result<bool, Task> {
+        WORKER.with(|worker| {
+            // Safety: the pointer is only set while the worker runs, see
+            // `run`
+            match unsafe { worker.get().as_ref() } {
+                Some(cx) if Arc::ptr_eq(&cx.shared, self) => {
+                    let task = if lifo {
+                        self.lifo[cx.core.index].lock().unwrap().replace(task)
+                    } else {
+                        Some(task)
+                    };
+                    match task {
+                        Some(task) => {
+                            cx.core.local.push(task);
+                            Ok(true)
+                        }
+                        None => Ok(false),
+                    }
+                }
+                _ => Err(task),
+            }
+        })
+    }
+
+    fn schedule_with(self: &Arc<Self>, task: Task, lifo: bool) {
+        match self.schedule_local(task, lifo) {
+            // Queued by one of the workers, which gets to it unless another
+            // worker steals it first. A task in the LIFO slot is run as soon
+            // as the worker is done with this one, so no one is unparked
+            // for it
+            Ok(queued) => {
+                if queued && self.stealers.len() > 1 {
+                    self.notify_parked();
+                }
+            }
+            Err(task) => {
+                self.injector.push(task);
+                self.notify_parked();
+            }
+        }
+    }
+}
+
+impl Schedule for Arc<Shared> {
+    fn schedule(&self, task: Task) {
+        self.schedule_with(task, true)
+    }
+
+    fn yield_now(&self, task: Task) {
+        self.schedule_with(task, false)
+    }
+
+    fn release(&self, id: TaskId) {
+        self.owned.lock().unwrap().remove(&id);
+    }
+}
+
+fn notify(unpark: &EventFd) {
+    if let Err(e) = unpark.notify() {
+        tracing::debug!("Failed to unpark worker: {}", e);
+    }
+}
+
+// ===== Workers =====
+
+/// Runs a worker until the runtime shuts down. Returns its core
+pub(super) fn run(shared: Arc<Shared>, core: Core, unpark: Arc<EventFd>) -> Core {
+    let _enter = enter(shared.clone());
+    let cx = Context { shared, core };
+    // Reset even if a task panics past the catch in its poll
+    struct Reset;
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            let _ = WORKER.try_with(|worker| worker.set(ptr::null()));
+        }
+    }
+
+    if let Some(hook) = &cx.shared.on_thread_start {
+        hook();
+    }
+    WORKER.with(|worker| worker.set(&cx));
+    let reset = Reset;
+    while !cx.shared.is_shutdown() {
+        match cx.next_task() {
+            Some(task) => task.run(),
+            None => cx.park(&unpark),
+        }
+    }
+    drop(reset);
+    if let Some(hook) = &cx.shared.on_thread_stop {
+        hook();
+    }
+    cx.core
+}
+
+impl Context {
+    // Looks for a task in the worker's LIFO slot, then its own queue, then
+    // the injector, then the queues of the other workers. Every so often the
+    // injector is checked first, so tasks queued from other threads aren't
+    // held up by a worker whose own queue never empties
+    fn next_task(&self) -> Option<Task> {
+        let tick = self.core.tick.get().wrapping_add(1);
+        self.core.tick.set(tick);
+        if tick.is_multiple_of(self.shared.global_queue_interval) {
+            if let Some(task) = steal(|| self.shared.injector.steal()) {
+                self.core.lifo_polls.set(0);
+                return Some(task);
+            }
+        }
+
+        if self.core.lifo_polls.get() < MAX_LIFO_POLLS {
+            if let Some(task) = self.take_lifo(self.core.index) {
+                self.core.lifo_polls.set(self.core.lifo_polls.get() + 1);
+                return Some(task);
+            }
+        }
+        self.core.lifo_polls.set(0);
+
+        if let Some(task) = self.core.local.pop() {
+            return Some(task);
+        }
+        if let Some(task) = steal(|| self.shared.injector.steal_batch_and_pop(&self.core.local)) {
+            return Some(task);
+        }
+        // Skipped above if the worker took too many tasks from it in a row,
+        // and nothing else is left
+        self.steal().or_else(|| self.take_lifo(self.core.index))
+    }
+
+    fn take_lifo(&self, index: usize) -> Option<Task> {
+        self.shared.lifo[index].lock().unwrap().take()
+    }
+
+    // Takes half the tasks of the first worker found with any, starting
+    // with the next worker round. A worker with an empty queue can still
+    // have a task in its LIFO slot, which is taken instead. Every worker
+    // looked at counts as a steal or a failed one
+    fn steal(&self) -> Option<Task> {
+        let stealers = &self.shared.stealers;
+        let stats = &self.shared.stats[self.core.index];
+        (1..stealers.len())
+            .map(|i| (self.core.index + i) % stealers.len())
+            .find_map(|index| {
+                let task = steal(|| stealers[index].steal_batch_and_pop(&self.core.local))
+                    .or_else(|| self.take_lifo(index));
+                match task {
+                    Some(_) => stats.incr_steal_count(),
+                    None => stats.incr_failed_steal_count(),
+                }
+                task
+            })
+    }
+
+    fn park(&self, unpark: &Arc<EventFd>) {
+        tracing::debug!("Worker {}: Parking", self.core.index);
+        self.shared.park(unpark)
+    }
+}
+
+// Retries a steal until it either finds a task or the queue is empty
+fn steal(mut f: impl FnMut() -> Steal<Task>) -> Option<Task> {
+    loop {
+        match f() {
+            Steal::Success(task) => return Some(task),
+            Steal::Empty => return None,
+            Steal::Retry => continue,
+        }
+    }
+}
@@ -1,15 +1,31 @@
 use std::cell::RefCell;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
-use std::marker::PhantomData;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::NonNull;
 use std::rc::Rc;
-use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
 
+use super::builder::{Builder, UnhandledPanic};
 use super::context;
-use crate::io::reactor::{Handle as IoHandle, Reactor};
+use super::hooks::Hooks;
+use super::metrics::{Metrics, RuntimeMetrics};
+use crate::future::Rng;
+use crate::io::epoll::Interest;
+use crate::io::eventfd::EventFd;
+use crate::io::pollable::Pollable;
+use crate::io::reactor::Handle as IoHandle;
+use crate::io::Driver;
+use crate::task::blocking::{self, Completions};
 use crate::task::join::JoinHandle;
 use crate::task::raw::{RawTask, Schedule};
 use crate::task::Task;
+use crate::task::{TaskId, TrySpawnError};
 
 pub struct Runtime {
     // Holds the reactor and task queue
@@ -19,10 +35,30 @@ pub struct Runtime {
 }
 
 struct Inner {
-    /// IO reactor
-    reactor: Reactor,
+    /// IO driver
+    driver: Driver,
     /// Queue that holds tasks
     queue: Queue,
+    /// Every task spawned onto the runtime that hasn't been freed yet
+    owned: OwnedTasks,
+    /// Written to when the `block_on` future is woken from another thread
+    /// while the runtime is parked, so the reactor stops waiting
+    unpark: Pollable<Arc<EventFd>>,
+    /// Where blocking closures report that they're done, shared with the
+    /// handle
+    blocking: Rc<Completions>,
+    /// Runtime metrics, shared with the spawner
+    metrics: Rc<Metrics>,
+    /// Instrumentation callbacks, shared with the spawner
+    hooks: Rc<Hooks>,
+    /// Number of tasks run before checking the IO driver for events
+    event_interval: u32,
+    /// What to do when the `block_on` future panics
+    unhandled_panic: UnhandledPanic,
+    /// Number of times `block_on` has parked, so tests can tell when it
+    /// has
+    #[cfg(test)]
+    parks: Arc<std::sync::atomic::AtomicUsize>,
 }
 
 /// Handle to the runtime
@@ -32,36 +68,107 @@ pub struct Handle {
     pub(crate) spawner: Spawner,
     /// Handle to the IO reactor
     pub(crate) io: IoHandle,
+    /// Where blocking closures report that they're done
+    pub(crate) blocking: Rc<Completions>,
+    /// Generator of `select!`, when the runtime was given a seed
+    pub(crate) rng: Option<Rc<Rng>>,
 }
 
 #[derive(Clone)]
 pub struct Spawner {
     queue: Queue,
+    /// Every task spawned onto the runtime that hasn't been freed yet
+    owned: OwnedTasks,
+    /// Runtime metrics, shared with the runtime
+    metrics: Rc<Metrics>,
+    /// Instrumentation callbacks, shared with the runtime
+    hooks: Rc<Hooks>,
+    /// Most tasks `try_spawn` lets be queued at once
+    queue_limit: Option<usize>,
 }
 
 type Queue = Rc<RefCell<VecDeque<Task>>>;
 
+// Tasks by id. They don't hold a reference, so the runtime doesn't keep them
+// alive. Instead each task removes itself as it's freed, see
+// `Schedule::release`
+type OwnedTasks = Rc<RefCell<HashMap<TaskId, NonNull<()>>>>;
+
+/// The most tasks run before the future passed to `block_on` is polled again
+const POLL_BUDGET: u32 = 256;
+
 // ===== impl Runtime =====
 
 impl Runtime {
     pub fn new() -> Runtime {
-        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        Builder::new()
+            .enable_all()
+            .build()
+            .expect("Could not start reactor!")
+    }
+
+    pub(super) fn from_builder(builder: &Builder) -> io::Result<Runtime> {
+        let queue = Rc::new(RefCell::new(VecDeque::with_capacity(
+            builder.queue_capacity,
+        )));
+        let owned = Rc::new(RefCell::new(HashMap::new()));
+        let metrics = Rc::new(Metrics::default());
+        let hooks = Rc::new(Hooks::new(
+            builder.on_task_schedule.clone(),
+            builder.on_task_poll.clone(),
+        ));
         let spawner = Spawner {
             queue: queue.clone(),
+            owned: owned.clone(),
+            metrics: metrics.clone(),
+            hooks: hooks.clone(),
+            queue_limit: builder.queue_limit,
         };
 
-        let reactor = Reactor::new().expect("Could not start reactor!");
-        let io_handle = reactor.handle();
+        let driver =
+            Driver::with_subsystems(builder.enable_io, builder.enable_time, builder.io_uring)?;
+        let io_handle = driver.handle();
+        let unpark = Pollable::new_with_handle(
+            Arc::new(EventFd::new()?),
+            Interest::READABLE,
+            io_handle.clone(),
+        )?;
+        let blocking = Rc::new(Completions::new(io_handle.clone(), metrics.remote_wakes())?);
 
         // Runtime handle
         let handle = Handle {
             spawner,
             io: io_handle,
+            blocking: blocking.clone(),
+            rng: builder.rng_seed.map(|seed| Rc::new(Rng::new(seed))),
         };
 
-        let inner = RefCell::new(Inner { reactor, queue });
+        let inner = RefCell::new(Inner {
+            driver,
+            queue,
+            owned,
+            unpark,
+            blocking,
+            metrics,
+            hooks,
+            event_interval: builder.event_interval,
+            unhandled_panic: builder.unhandled_panic,
+            #[cfg(test)]
+            parks: Default::default(),
+        });
+
+        Ok(Runtime { inner, handle })
+    }
 
-        Runtime { inner, handle }
+    /// Shuts the runtime down, dropping every task that hasn't completed
+    ///
+    /// The future of each task is dropped, and with it the timers and IO
+    /// resources it registered. Handles of the tasks resolve to
+    /// [`JoinError::Cancelled`]. Dropping the runtime does the same
+    ///
+    /// [`JoinError::Cancelled`]: crate::JoinError::Cancelled
+    pub fn shutdown(self) {
+        drop(self)
     }
 
     // Get the handle to the runtime
@@ -69,30 +176,112 @@ impl Runtime {
         &self.handle
     }
 
+    // Get a snapshot of the runtime's metrics
+    pub fn metrics(&self) -> RuntimeMetrics {
+        let mut metrics = self.handle.spawner.metrics.snapshot();
+        metrics.run_queue_capacity = self.handle.spawner.queue.borrow().capacity();
+        metrics.io_uring_completions = self.handle.io.inner.io_uring_completions();
+        blocking::fill_metrics(&mut metrics);
+        metrics
+    }
+
+    // Get the deadline of the earliest pending sleep, if there is one.
+    // Hosts driving woi from their own loop can use this to decide how
+    // long to block before calling back in
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.handle.io.inner.next_deadline()
+    }
+
     // Spawn a task onto the runtime
-    pub fn spawn<F: Future>(&self, future: F) -> JoinHandle<F::Output> {
+    pub fn spawn<F: Future + 'static>(&self, future: F) -> JoinHandle<F::Output> {
         self.handle.spawn(future)
     }
 
     pub fn block_on<F: Future>(&self, future: F) -> F::Output {
         // Enter runtime context
         let _enter = context::enter(self.handle.clone());
-        self.inner.borrow_mut().block_on(future)
+        let mut inner = self.inner.borrow_mut();
+        match inner.unhandled_panic {
+            UnhandledPanic::Ignore => inner.block_on(future),
+            UnhandledPanic::Shutdown => {
+                // The future is dropped while unwinding out of `block_on`, so
+                // tasks its state wakes are queued by the time it's caught
+                match panic::catch_unwind(AssertUnwindSafe(|| inner.block_on(future))) {
+                    Ok(output) => output,
+                    Err(panic) => {
+                        tracing::debug!("`block_on` future panicked, shutting down");
+                        inner.shutdown();
+                        panic::resume_unwind(panic)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        // Tasks waiting on a timer or IO are only referenced by the wakers
+        // they registered, which nothing would ever free
+        let _enter = context::enter(self.handle.clone());
+        self.inner.get_mut().shutdown();
     }
 }
 
 // ===== impl Inner =====
 
 impl Inner {
+    // Aborts every task and runs it, which drops its future. Aborting queues
+    // tasks that are waiting, and tasks woken while the futures are dropped
+    // are queued and aborted as well. Timers and IO resources are
+    // deregistered as the futures holding them are dropped
+    fn shutdown(&mut self) {
+        // Held on to until every task has been run, so that none is freed
+        // while another still points to it
+        let tasks: Vec<Task> = self
+            .owned
+            .borrow()
+            .values()
+            // Safety: tasks are removed as they are freed
+            .map(|&raw| unsafe { Task::from_raw(raw) })
+            .collect();
+        for task in &tasks {
+            task.abort();
+        }
+        self.drain_aborted();
+        // Tasks only the runtime still holds are freed here
+        drop(tasks);
+        self.drain_aborted();
+    }
+
+    fn drain_aborted(&mut self) {
+        loop {
+            let task = self.queue.borrow_mut().pop_front();
+            match task {
+                Some(task) => {
+                    self.hooks.polled(task.id());
+                    task.abort();
+                    task.run();
+                }
+                None => break,
+            }
+        }
+    }
+
     pub fn block_on<F: Future>(&mut self, future: F) -> F::Output {
         crate::pin!(future);
 
-        let waker = unsafe { Waker::from_raw(NoopWaker::waker()) };
+        let root = Arc::new(RootWaker::new(
+            self.unpark.get_ref().clone(),
+            self.metrics.remote_wakes(),
+        ));
+        let waker = Waker::from(root.clone());
         let cx = &mut Context::from_waker(&waker);
 
         loop {
             // If the future is ready, return the output
             tracing::debug!("Polling `block_on` future");
+            root.woken.store(false, Ordering::Relaxed);
             if let Poll::Ready(v) = future.as_mut().poll(cx) {
                 return v;
             }
@@ -102,40 +291,85 @@ impl Inner {
             // 1. If there are no tasks on the runtime, it means we're waiting on IO
             //    resources (e.g I'm performing a read and waiting on data to arrive).
             //    Essentially, this means we have events registered in our reactor and
-            //    we are waiting for them to fire.
+            //    we are waiting for them to fire. Unless the future woke itself, in
+            //    which case it is polled again straight away
             // 2. If there are tasks spawned onto the runtime, we can start processing them
-            if self.queue.borrow().is_empty() {
-                tracing::debug!("Parking on epoll");
-                self.reactor
-                    .react(None)
-                    .expect("Reactor failed to process events");
-            }
-
-            // We have tasks to process. We process all of them. After, we proceed to
-            // to poll the outer future again with the hope that we aren't waiting on
-            // anymore resources and are now finished our work (unless we are a web
-            // server of course)
-            loop {
+            if self.queue.borrow().is_empty() && !root.woken.load(Ordering::Relaxed) {
+                // Wakes from here on write to the eventfd. The future is
+                // checked once more, in case it was woken before they did
+                root.parked.store(true, Ordering::SeqCst);
+                #[cfg(test)]
+                self.parks.fetch_add(1, Ordering::SeqCst);
+                if !root.woken.load(Ordering::SeqCst) {
+                    tracing::debug!("Parking on epoll");
+                    self.turn(None);
+                }
+                root.parked.store(false, Ordering::SeqCst);
+
+                if root.woken.load(Ordering::SeqCst) {
+                    if let Err(e) = self.unpark.get_ref().drain() {
+                        tracing::debug!("Failed to drain unpark eventfd: {}", e);
+                    }
+                }
+            }
+
+            // We have tasks to process. We process all of them, up to the budget.
+            // After, we proceed to to poll the outer future again with the hope
+            // that we aren't waiting on anymore resources and are now finished our
+            // work (unless we are a web server of course). Tasks woken while they
+            // are polled go to the back of the queue, behind every other ready
+            // task. The budget stops a task that always wakes itself from keeping
+            // the queue from ever emptying, which would starve the outer future
+            let mut tick: u32 = 0;
+            while tick < POLL_BUDGET {
+                // Check for IO events every so often, so that tasks waiting
+                // on IO aren't held up by a long queue
+                tick = tick.wrapping_add(1);
+                if tick.is_multiple_of(self.event_interval) {
+                    self.turn(Some(Duration::ZERO));
+                }
+
                 let task = self.queue.borrow_mut().pop_front();
                 match task {
                     Some(task) => {
                         tracing::debug!(
+                            task = %task.id(),
                             "Task {}: Popped off executor queue and running",
                             task.id()
                         );
-                        task.run()
+                        self.hooks.polled(task.id());
+                        let start = Instant::now();
+                        task.run();
+                        self.metrics.record_poll(start.elapsed());
                     }
                     None => break,
                 }
             }
+
+            // Out of budget with tasks still queued, so the check for IO events
+            // that parking would have done is done here
+            if tick == POLL_BUDGET && !self.queue.borrow().is_empty() {
+                self.turn(Some(Duration::ZERO));
+            }
         }
     }
+
+    // Processes IO events, waiting up to `timeout` for the first one. Tasks
+    // awaiting blocking closures that finished are woken along with them
+    fn turn(&mut self, timeout: Option<Duration>) {
+        let events = self
+            .driver
+            .turn(timeout)
+            .expect("Reactor failed to process events");
+        self.metrics.incr_io_driver_ready_count(events);
+        self.blocking.dispatch();
+    }
 }
 
 // ===== impl Handle =====
 
 impl Handle {
-    pub fn spawn<F: Future>(&self, future: F) -> JoinHandle<F::Output> {
+    pub fn spawn<F: Future + 'static>(&self, future: F) -> JoinHandle<F::Output> {
         self.spawner.spawn(future)
     }
 }
@@ -143,41 +377,672 @@ impl Handle {
 // ===== impl Spawner =====
 
 impl Spawner {
-    pub fn spawn<F: Future>(&self, future: F) -> JoinHandle<F::Output> {
-        let raw = RawTask::new(future, self.queue.clone());
+    pub fn spawn<F: Future + 'static>(&self, future: F) -> JoinHandle<F::Output> {
+        #[cfg(debug_assertions)]
+        if crate::task::is_join_handle::<F>() {
+            tracing::warn!(
+                "Spawning a JoinHandle, which only waits on the task it belongs to. \
+                 Await the handle instead"
+            );
+        }
+
+        // The task starts with one reference for `task` and one for the
+        // join handle, see `INITIAL_STATE`
+        let raw = RawTask::new(future, self.clone());
         let task = Task { raw };
-        let join_handle = JoinHandle {
-            raw,
-            _marker: PhantomData,
-        };
-        tracing::debug!("Task {}: Spawned", task.id());
+        let join_handle = JoinHandle::new(raw);
+        self.owned.borrow_mut().insert(task.id(), raw);
+        tracing::debug!(task = %task.id(), "Task {}: Spawned", task.id());
+
+        if self.is_current() {
+            self.metrics.incr_local_spawn_count();
+        } else {
+            self.metrics.incr_outside_spawn_count();
+        }
+        self.metrics.incr_total_tasks_spawned();
 
-        self.queue.schedule(task);
+        self.schedule(task);
 
         join_handle
     }
-}
 
-// ===== impl Queue =====
+    /// Spawns the future, unless the queue is at its limit
+    pub fn try_spawn<F: Future + 'static>(
+        &self,
+        future: F,
+    ) -> Result<JoinHandle<F::Output>, TrySpawnError<F>> {
+        if let Some(limit) = self.queue_limit {
+            if self.queue.borrow().len() >= limit {
+                tracing::debug!("Run queue full, not spawning");
+                return Err(TrySpawnError::Full(future));
+            }
+        }
+        Ok(self.spawn(future))
+    }
 
-impl Schedule for Queue {
+    /// Is this the spawner of the runtime whose context is entered?
+    fn is_current(&self) -> bool {
+        context::try_spawner().is_some_and(|current| Rc::ptr_eq(&current.queue, &self.queue))
+    }
+}
+
+// Both spawns and wakes schedule through here, so tasks are run in the
+// order they were spawned or woken in
+impl Schedule for Spawner {
     fn schedule(&self, task: Task) {
-        self.borrow_mut().push_back(task);
+        let id = task.id();
+        {
+            let mut queue = self.queue.borrow_mut();
+            queue.push_back(task);
+            self.metrics.update_max_queue_depth(queue.len());
+        }
+        // The queue is released first, so the hook can spawn tasks
+        self.hooks.scheduled(id);
+    }
+
+    fn release(&self, id: TaskId) {
+        self.owned.borrow_mut().remove(&id);
+    }
+}
+
+// ===== Root waker =====
+
+// Waker of the future passed to `block_on`. The future is polled again after
+// every round of tasks and IO events, so all a wake has to do is stop the
+// runtime from parking on the reactor when there's nothing else to do. It
+// never allocates a task or touches the run queue. Tasks spawned by the
+// future are still scheduled on the queue as usual
+//
+// A wake from another thread can land while the runtime is already parked,
+// in which case it unparks the runtime through the eventfd
+struct RootWaker {
+    woken: AtomicBool,
+    /// Whether the runtime is, or is about to be, parked on the reactor
+    parked: AtomicBool,
+    unpark: Arc<EventFd>,
+    /// The runtime's thread, to tell remote wakes apart
+    thread: ThreadId,
+    remote_wakes: Arc<AtomicU64>,
+}
+
+impl RootWaker {
+    fn new(unpark: Arc<EventFd>, remote_wakes: Arc<AtomicU64>) -> RootWaker {
+        RootWaker {
+            woken: AtomicBool::new(false),
+            parked: AtomicBool::new(false),
+            unpark,
+            thread: thread::current().id(),
+            remote_wakes,
+        }
     }
 }
 
-// ===== No op waker =====
+impl Wake for RootWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if thread::current().id() != self.thread {
+            self.remote_wakes.fetch_add(1, Ordering::Relaxed);
+        }
+        // Only the first wake since the last poll has to unpark
+        if !self.woken.swap(true, Ordering::SeqCst) && self.parked.load(Ordering::SeqCst) {
+            if let Err(e) = self.unpark.notify() {
+                tracing::debug!("Failed to unpark runtime: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_on_future_that_yields() {
+        let rt = Runtime::new();
+        let mut yields = 0;
+        let value = rt.block_on(futures::future::poll_fn(|cx| {
+            if yields == 5 {
+                return Poll::Ready(42);
+            }
+            yields += 1;
+            // Nothing else is running, so only the wake stops the runtime
+            // from parking forever
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }));
+        assert_eq!(value, 42);
+        assert_eq!(yields, 5);
+    }
+
+    #[test]
+    fn root_wakes_skip_the_run_queue() {
+        use std::cell::Cell;
+
+        let task_polls = Rc::new(Cell::new(0));
+        let rt = {
+            let task_polls = task_polls.clone();
+            Builder::new()
+                .enable_all()
+                .on_task_poll(move |_, _| task_polls.set(task_polls.get() + 1))
+                .build()
+                .unwrap()
+        };
+
+        let mut root_polls = 0;
+        rt.block_on(futures::future::poll_fn(|cx| {
+            root_polls += 1;
+            if root_polls == 100 {
+                return Poll::Ready(());
+            }
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }));
+        // One poll per wake, and none of them ran through the queue
+        assert_eq!(root_polls, 100);
+        assert_eq!(task_polls.get(), 0);
+        assert_eq!(rt.metrics().max_queue_depth, 0);
+
+        // Children of the root still do
+        rt.block_on(async {
+            crate::spawn(crate::task::yield_now()).await.unwrap();
+        });
+        assert_eq!(task_polls.get(), 2);
+    }
+
+    #[test]
+    fn root_woken_from_another_thread_while_parked() {
+        use futures::channel::oneshot;
+        use std::thread;
+
+        let rt = Runtime::new();
+        let parks = rt.inner.borrow().parks.clone();
+        for _ in 0..10 {
+            let (tx, rx) = oneshot::channel();
+            let before = parks.load(Ordering::SeqCst);
+            let parks = parks.clone();
+            let sender = thread::spawn(move || {
+                // Only wake the runtime once it has parked. Then it's the
+                // eventfd that has to unpark it
+                while parks.load(Ordering::SeqCst) == before {
+                    thread::yield_now();
+                }
+                tx.send(7).unwrap();
+            });
+            // Nothing is registered with the reactor, so only the wake can
+            // stop the runtime from parking forever
+            assert_eq!(rt.block_on(rx), Ok(7));
+            sender.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn metrics_remote_wakes() {
+        use futures::channel::oneshot;
+        use std::pin::Pin;
+        use std::sync::mpsc;
+
+        // Polls `future`, signalling `polled` each time it's pending. By then
+        // it has registered its waker, so whatever completes it after the
+        // signal has to wake the runtime
+        async fn signal_when_pending<F: Future + Unpin>(
+            mut future: F,
+            polled: mpsc::Sender<()>,
+        ) -> F::Output {
+            futures::future::poll_fn(|cx| {
+                let poll = Pin::new(&mut future).poll(cx);
+                if poll.is_pending() {
+                    let _ = polled.send(());
+                }
+                poll
+            })
+            .await
+        }
+
+        let rt = Runtime::new();
+        // Wakes on the runtime's thread aren't counted
+        rt.block_on(crate::task::yield_now());
+        assert_eq!(rt.metrics().remote_wakes, 0);
+
+        let (tx, rx) = oneshot::channel();
+        let (polled_tx, polled_rx) = mpsc::channel();
+        let sender = thread::spawn(move || {
+            polled_rx.recv().unwrap();
+            tx.send(()).unwrap();
+        });
+        rt.block_on(signal_when_pending(rx, polled_tx)).unwrap();
+        sender.join().unwrap();
+        assert_eq!(rt.metrics().remote_wakes, 1);
 
-struct NoopWaker;
+        let (polled_tx, polled_rx) = mpsc::channel();
+        rt.block_on(async {
+            let handle = crate::task::spawn_blocking(move || polled_rx.recv().unwrap());
+            signal_when_pending(handle, polled_tx).await.unwrap()
+        });
+        assert_eq!(rt.metrics().remote_wakes, 2);
+    }
+
+    #[test]
+    fn hooks_measure_queue_latency() {
+        use crate::task::TaskId;
+        use std::time::Duration;
 
-impl NoopWaker {
-    fn waker() -> RawWaker {
-        fn no_op(_: *const ()) {}
-        fn clone(_: *const ()) -> RawWaker {
-            NoopWaker::waker()
+        #[derive(Debug, PartialEq)]
+        enum Event {
+            Scheduled(TaskId),
+            Polled(TaskId),
         }
 
-        let vtable = &RawWakerVTable::new(clone, no_op, no_op, no_op);
-        RawWaker::new(0 as *const (), vtable)
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let latencies = Rc::new(RefCell::new(Vec::new()));
+        let rt = {
+            let events = events.clone();
+            let polled = events.clone();
+            let latencies = latencies.clone();
+            Builder::new()
+                .on_task_schedule(move |id| events.borrow_mut().push(Event::Scheduled(id)))
+                .on_task_poll(move |id, queued| {
+                    polled.borrow_mut().push(Event::Polled(id));
+                    latencies.borrow_mut().push((id, queued));
+                })
+                .build()
+                .unwrap()
+        };
+
+        rt.block_on(async {
+            // Woken while `slow` is queued, so it waits for `slow` to run
+            let woken = crate::spawn(crate::task::yield_now());
+            let slow = crate::spawn(async { std::thread::sleep(Duration::from_millis(20)) });
+            let _ = crate::join!(woken, slow);
+        });
+
+        let events = events.borrow();
+        let (woken, slow) = match events[..2] {
+            [Event::Scheduled(woken), Event::Scheduled(slow)] => (woken, slow),
+            _ => panic!("unexpected events: {:?}", events),
+        };
+        assert_eq!(
+            events[2..],
+            [
+                Event::Polled(woken),
+                Event::Scheduled(woken),
+                Event::Polled(slow),
+                Event::Polled(woken),
+            ]
+        );
+
+        let latencies = latencies.borrow();
+        let (id, queued) = latencies[latencies.len() - 1];
+        assert_eq!(id, woken);
+        assert!(
+            queued >= Duration::from_millis(20),
+            "queued for {:?}",
+            queued
+        );
+    }
+
+    #[test]
+    fn try_spawn_fails_while_queue_is_full() {
+        use crate::task::{try_spawn, yield_now};
+
+        let rt = Builder::new().queue_limit(4).build().unwrap();
+        rt.block_on(async {
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    try_spawn(async move {
+                        // Woken while the queue is at its limit
+                        yield_now().await;
+                        i
+                    })
+                    .unwrap()
+                })
+                .collect();
+
+            let err = match try_spawn(async { 4 }) {
+                Err(err @ TrySpawnError::Full(_)) => err,
+                Ok(_) => panic!("spawned onto a full queue"),
+            };
+            // The future is handed back untouched
+            assert_eq!(err.into_inner().await, 4);
+            // `spawn` isn't held back by the limit
+            let extra = crate::spawn(async { 5 });
+
+            for (i, handle) in handles.into_iter().enumerate() {
+                assert_eq!(handle.await.unwrap(), i);
+            }
+            assert_eq!(extra.await.unwrap(), 5);
+
+            // Drained, so there's room again
+            assert_eq!(try_spawn(async { 6 }).unwrap().await.unwrap(), 6);
+        });
+    }
+
+    #[test]
+    fn self_waking_task_does_not_starve_others() {
+        use std::cell::Cell;
+
+        let greedy_polls = Rc::new(Cell::new(0));
+        let rt = Runtime::new();
+        let polls_before_other = rt.block_on(async {
+            let polls = greedy_polls.clone();
+            // Never completes, and is always ready to be polled again
+            crate::spawn(futures::future::poll_fn(move |cx| -> Poll<()> {
+                polls.set(polls.get() + 1);
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }));
+
+            let polls = greedy_polls.clone();
+            let other = crate::spawn(async move { polls.get() });
+            // Only returns if `block_on` gets to poll this future again
+            other.await.unwrap()
+        });
+
+        // The greedy task was requeued behind the other task
+        assert_eq!(polls_before_other, 1);
+        assert!(greedy_polls.get() <= POLL_BUDGET as usize);
+    }
+
+    #[test]
+    fn panicking_root_shuts_down_tasks() {
+        use crate::channel::oneshot;
+        use crate::test_util::DropFlag;
+        use std::cell::Cell;
+
+        let queued = Rc::new(Cell::new(false));
+        let waiting = Rc::new(Cell::new(false));
+        let rt = Builder::new()
+            .unhandled_panic(UnhandledPanic::Shutdown)
+            .build()
+            .unwrap();
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            rt.block_on(async {
+                let (tx, rx) = oneshot::channel::<()>();
+                let flag = DropFlag(waiting.clone());
+                crate::spawn(async move {
+                    let _flag = flag;
+                    let _ = rx.await;
+                    futures::future::pending::<()>().await
+                });
+                // Let it start waiting on the channel
+                crate::task::yield_now().await;
+
+                // Never gets to run
+                let flag = DropFlag(queued.clone());
+                crate::spawn(async move {
+                    let _flag = flag;
+                });
+
+                let _tx = tx;
+                panic!("root future panicked");
+            })
+        }));
+
+        assert!(res.is_err());
+        assert!(queued.get());
+        // Woken by the sender being dropped as the root unwound
+        assert!(waiting.get());
+    }
+
+    #[test]
+    fn dropping_runtime_frees_pending_tasks() {
+        use crate::channel::oneshot;
+        use crate::test_util::DropFlag;
+        use std::cell::Cell;
+
+        // One flag per task
+        let dropped: Vec<_> = (0..4).map(|_| Rc::new(Cell::new(false))).collect();
+        let mut flags = dropped.iter().map(|dropped| DropFlag(dropped.clone()));
+        let mut flag = || flags.next().unwrap();
+        let rt = Runtime::new();
+        rt.block_on(async {
+            // Only referenced by the waker of its timer
+            let f = flag();
+            crate::spawn(async move {
+                let _f = f;
+                crate::time::sleep(Duration::from_secs(60)).await;
+            });
+
+            // Waits on a sender held by a task waiting on it in turn
+            let (tx, rx) = oneshot::channel::<()>();
+            let f = flag();
+            let waiting = crate::spawn(async move {
+                let _f = f;
+                let _ = rx.await;
+            });
+            let f = flag();
+            crate::spawn(async move {
+                let _f = f;
+                let _tx = tx;
+                let _ = waiting.await;
+            });
+            crate::task::yield_now().await;
+
+            // Only referenced by the queue, and never run
+            let f = flag();
+            crate::spawn(async move {
+                let _f = f;
+            });
+        });
+
+        assert!(dropped.iter().all(|dropped| !dropped.get()));
+        drop(rt);
+        assert!(dropped.iter().all(|dropped| dropped.get()));
+    }
+
+    #[test]
+    fn metrics_spawn_counts() {
+        let rt = Runtime::new();
+        // Spawned from outside the runtime's context
+        let outside = rt.spawn(async {});
+
+        let handle = rt.handle().clone();
+        rt.block_on(async move {
+            let h1 = crate::spawn(async {});
+            let h2 = handle.spawn(async {});
+            let _ = crate::join!(outside, h1, h2);
+        });
+
+        let metrics = rt.metrics();
+        assert_eq!(metrics.local_spawn_count, 2);
+        assert_eq!(metrics.outside_spawn_count, 1);
+    }
+
+    #[test]
+    fn total_tasks_spawned_counts_finished_tasks() {
+        let rt = Runtime::new();
+        let outside = rt.spawn(async {});
+
+        rt.block_on(async {
+            for i in 0..5 {
+                assert_eq!(crate::spawn(async move { i }).await.unwrap(), i);
+            }
+            crate::task::spawn_blocking(|| ()).await.unwrap();
+            outside.await.unwrap();
+            // Never completes
+            crate::spawn(std::future::pending::<()>());
+        });
+
+        let metrics = rt.metrics();
+        assert_eq!(metrics.total_tasks_spawned, 8);
+        assert_eq!(metrics.local_spawn_count + metrics.outside_spawn_count, 8);
+    }
+
+    #[test]
+    fn next_deadline_of_pending_sleep() {
+        use crate::time::sleep;
+        use std::time::Duration;
+
+        let rt = Runtime::new();
+        assert_eq!(rt.next_deadline(), None);
+
+        rt.block_on(async {
+            let long = sleep(Duration::from_secs(10));
+            let short = sleep(Duration::from_millis(10));
+            assert_eq!(rt.next_deadline(), Some(short.deadline()));
+
+            short.await;
+            assert_eq!(rt.next_deadline(), Some(long.deadline()));
+            drop(long);
+            assert_eq!(rt.next_deadline(), None);
+        });
+    }
+
+    #[test]
+    fn metrics_mean_poll_time() {
+        use std::time::Duration;
+
+        let rt = Runtime::new();
+        assert_eq!(rt.metrics().mean_poll_time, Duration::ZERO);
+
+        let start = Instant::now();
+        rt.block_on(async {
+            for _ in 0..3 {
+                crate::spawn(async { std::thread::sleep(Duration::from_millis(10)) })
+                    .await
+                    .unwrap();
+            }
+        });
+        let total = start.elapsed();
+
+        // Each poll sleeps for 10ms, and none can take longer than the
+        // whole run. Anything tighter would depend on the scheduler
+        let mean = rt.metrics().mean_poll_time;
+        assert!(mean >= Duration::from_millis(10), "mean was {:?}", mean);
+        assert!(mean <= total, "mean was {:?} of {:?}", mean, total);
+    }
+
+    #[test]
+    fn spawns_and_wakes_run_in_order() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use std::task::Waker;
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let stashed: Rc<RefCell<Option<Waker>>> = Rc::new(RefCell::new(None));
+
+        // Logs `name` and completes
+        let record = |name: &'static str| {
+            let log = log.clone();
+            async move { log.borrow_mut().push(name) }
+        };
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let a = {
+                let (log, stashed) = (log.clone(), stashed.clone());
+                let mut woken = false;
+                crate::spawn(futures::future::poll_fn(move |cx| {
+                    if woken {
+                        log.borrow_mut().push("a woken");
+                        return Poll::Ready(());
+                    }
+                    woken = true;
+                    log.borrow_mut().push("a");
+                    *stashed.borrow_mut() = Some(cx.waker().clone());
+                    Poll::Pending
+                }))
+            };
+
+            // Let `a` run once
+            let mut yielded = false;
+            futures::future::poll_fn(|_| {
+                if yielded {
+                    return Poll::Ready(());
+                }
+                yielded = true;
+                Poll::Pending
+            })
+            .await;
+
+            let b = crate::spawn(record("b"));
+            stashed.borrow_mut().take().unwrap().wake();
+            let c = crate::spawn(record("c"));
+            let _ = crate::join!(a, b, c);
+        });
+
+        assert_eq!(*log.borrow(), vec!["a", "b", "a woken", "c"]);
+    }
+
+    #[test]
+    fn metrics_io_driver_ready_count() {
+        use crate::io::AsyncReadExt;
+        use crate::net::TcpStream;
+        use std::io::Write;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let rt = Runtime::new();
+        assert_eq!(rt.metrics().io_driver_ready_count, 0);
+
+        rt.block_on(async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let (mut peer, _) = listener.accept().unwrap();
+
+            for i in 0..3u8 {
+                peer.write_all(&[i]).unwrap();
+                let mut buf = [0; 1];
+                stream.read_exact(&mut buf).await.unwrap();
+                assert_eq!(buf[0], i);
+            }
+        });
+
+        assert!(rt.metrics().io_driver_ready_count > 0);
+    }
+
+    #[test]
+    fn queue_capacity_covers_burst() {
+        let rt = Builder::new().global_queue_capacity(256).build().unwrap();
+        let capacity = rt.metrics().run_queue_capacity;
+        assert!(capacity >= 256);
+
+        rt.block_on(async {
+            let handles: Vec<_> = (0..256).map(|i| crate::spawn(async move { i })).collect();
+            for (i, handle) in handles.into_iter().enumerate() {
+                assert_eq!(handle.await.unwrap(), i);
+            }
+        });
+        assert_eq!(rt.metrics().run_queue_capacity, capacity);
+    }
+
+    #[test]
+    #[should_panic(expected = "enable_time")]
+    fn sleep_without_time_enabled_panics() {
+        let rt = Builder::new().enable_io().build().unwrap();
+        rt.block_on(async {
+            crate::time::sleep(std::time::Duration::from_millis(1)).await;
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "enable_io")]
+    fn io_without_io_enabled_panics() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let rt = Builder::new().enable_time().build().unwrap();
+        rt.block_on(async {
+            let _ = crate::net::TcpStream::connect(addr).await;
+        });
+    }
+
+    #[test]
+    fn max_queue_depth_reflects_burst() {
+        let rt = Runtime::new();
+        assert_eq!(rt.metrics().max_queue_depth, 0);
+
+        rt.block_on(async {
+            let handles: Vec<_> = (0..100).map(|_| crate::spawn(async {})).collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            // A smaller burst afterwards doesn't lower the high-water mark
+            crate::spawn(async {}).await.unwrap();
+        });
+        assert_eq!(rt.metrics().max_queue_depth, 100);
     }
 }
@@ -0,0 +1,6 @@
+mod shutdown;
+mod signal;
+mod signalfd;
+
+pub use shutdown::Shutdown;
+pub use signal::{ctrl_c, signal, Signal, SignalKind};
@@ -0,0 +1,137 @@
+use std::io;
+use std::rc::Rc;
+
+use super::signal::{signal, SignalKind};
+use crate::task::{AbortHandle, CancellationToken};
+
+/// Exit code of a process stopped by a second ctrl-c, the same as a
+/// process terminated by `SIGINT`
+const FORCED_EXIT_CODE: i32 = 128 + libc::SIGINT;
+
+/// Tells a server when to begin shutting down
+///
+/// Shutdown starts on the first ctrl-c, or when the token from
+/// [`Shutdown::token`] is cancelled. A second ctrl-c exits the process
+/// straight away, for when teardown hangs. Clones share the same signal,
+/// so one can be handed to every task that needs to stop
+///
+/// ```no_run
+/// let rt = woi::Runtime::new();
+/// rt.block_on(async {
+///     let shutdown = woi::signal::Shutdown::new().unwrap();
+///     loop {
+///         woi::select! {
+///             _ = shutdown.recv() => break,
+///             _ = woi::time::sleep(std::time::Duration::from_secs(1)) => {}
+///         }
+///     }
+/// });
+/// ```
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    token: CancellationToken,
+    /// The task listening for ctrl-c
+    listener: AbortHandle,
+}
+
+impl Shutdown {
+    /// Starts listening for ctrl-c on the current runtime
+    ///
+    /// Like [`signal`], this blocks `SIGINT` on the calling thread
+    pub fn new() -> io::Result<Shutdown> {
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let token = CancellationToken::new();
+
+        let listener = {
+            let token = token.clone();
+            crate::spawn(async move {
+                sigint.recv().await;
+                tracing::debug!("Shutdown: received ctrl-c, shutting down");
+                token.cancel();
+
+                sigint.recv().await;
+                tracing::debug!("Shutdown: received second ctrl-c, exiting");
+                std::process::exit(FORCED_EXIT_CODE);
+            })
+        };
+
+        let inner = Inner {
+            token,
+            listener: listener.abort_handle(),
+        };
+        Ok(Shutdown {
+            inner: Rc::new(inner),
+        })
+    }
+
+    /// Completes once shutdown has started. Completes straight away if it
+    /// already has
+    pub async fn recv(&self) {
+        self.inner.token.cancelled().await
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.token.is_cancelled()
+    }
+
+    /// The token that starts shutdown when cancelled
+    pub fn token(&self) -> &CancellationToken {
+        &self.inner.token
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Stops listening once no one is waiting on the signal
+        self.listener.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::time::Duration;
+
+    #[test]
+    fn cancelled_token_starts_shutdown() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let shutdown = Shutdown::new().unwrap();
+
+            let worker = {
+                let shutdown = shutdown.clone();
+                crate::spawn(async move {
+                    shutdown.recv().await;
+                    "worker stopped"
+                })
+            };
+
+            // Stands in for ctrl-c, which would start shutdown the same way
+            let token = shutdown.token().clone();
+            crate::spawn(async move {
+                crate::time::sleep(Duration::from_millis(10)).await;
+                token.cancel();
+            });
+
+            let mut ticks = 0;
+            let reason = loop {
+                crate::select! {
+                    _ = shutdown.recv() => break "shutdown",
+                    _ = crate::time::sleep(Duration::from_millis(1)) => ticks += 1,
+                }
+            };
+            assert_eq!(reason, "shutdown");
+            assert!(ticks > 0);
+            assert!(shutdown.is_shutdown());
+            assert_eq!(worker.await.unwrap(), "worker stopped");
+
+            // Later listeners see it too
+            assert!(futures::poll!(Box::pin(shutdown.recv())).is_ready());
+        });
+    }
+}
@@ -0,0 +1,233 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll, Waker};
+
+use futures::future::poll_fn;
+use slab::Slab;
+
+use super::signalfd::SignalFd;
+use crate::io::epoll::Interest;
+use crate::io::io_source::Direction;
+use crate::io::pollable::Pollable;
+use crate::io::reactor::{Handle, Inner as ReactorInner};
+
+thread_local! {
+    // The signals being listened for on this thread. All listeners of a
+    // signal share a single signalfd, since a signal is only read once
+    static SIGNALS: RefCell<HashMap<libc::c_int, Weak<Entry>>> = RefCell::new(HashMap::new());
+}
+
+/// A kind of Unix signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SignalKind(libc::c_int);
+
+/// Listens for a kind of signal. Created with [`signal`]
+pub struct Signal {
+    entry: Rc<Entry>,
+    /// Key of this listener's waker in the entry
+    key: usize,
+    /// The generation of the entry last seen by this listener
+    seen: u64,
+}
+
+/// State shared by every listener of a signal
+struct Entry {
+    fd: Pollable<SignalFd>,
+    /// Incremented every time the signalfd is drained. Signals that arrive
+    /// before the fd is read are coalesced into one increment
+    generation: Cell<u64>,
+    /// Wakers of the listeners waiting on the signal
+    wakers: RefCell<Slab<Option<Waker>>>,
+    /// The reactor the signalfd is registered with
+    reactor: Rc<ReactorInner>,
+}
+
+/// Listens for signals of `kind` on the current thread
+///
+/// The signal is blocked on the calling thread so that it is delivered to
+/// woi rather than its default handler. Signals sent to the whole process,
+/// like a `SIGINT` from the terminal, can be handled by any thread that
+/// doesn't block them, so block the signal before spawning other threads
+/// when that matters
+///
+/// Only signals received after the listener is created are seen by it.
+/// Several signals received before it is polled are reported once
+pub fn signal(kind: SignalKind) -> io::Result<Signal> {
+    let entry = Entry::get_or_create(kind.0)?;
+    let key = entry.wakers.borrow_mut().insert(None);
+    let seen = entry.generation.get();
+    Ok(Signal { entry, key, seen })
+}
+
+/// Completes once a `SIGINT` is received
+pub async fn ctrl_c() -> io::Result<()> {
+    signal(SignalKind::interrupt())?.recv().await;
+    Ok(())
+}
+
+// ===== impl SignalKind =====
+
+impl SignalKind {
+    /// Creates a kind from a raw signal number
+    pub const fn from_raw(signum: libc::c_int) -> SignalKind {
+        SignalKind(signum)
+    }
+
+    /// `SIGHUP`
+    pub const fn hangup() -> SignalKind {
+        SignalKind(libc::SIGHUP)
+    }
+
+    /// `SIGINT`, sent on ctrl-c
+    pub const fn interrupt() -> SignalKind {
+        SignalKind(libc::SIGINT)
+    }
+
+    /// `SIGTERM`
+    pub const fn terminate() -> SignalKind {
+        SignalKind(libc::SIGTERM)
+    }
+
+    /// `SIGUSR1`
+    pub const fn user_defined1() -> SignalKind {
+        SignalKind(libc::SIGUSR1)
+    }
+
+    /// `SIGUSR2`
+    pub const fn user_defined2() -> SignalKind {
+        SignalKind(libc::SIGUSR2)
+    }
+}
+
+// ===== impl Signal =====
+
+impl Signal {
+    /// Waits for the next signal. Never returns `None`, it's an `Option`
+    /// so that `Signal` can be used like a stream of signals
+    pub async fn recv(&mut self) -> Option<()> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        self.entry.drain(cx);
+
+        let generation = self.entry.generation.get();
+        if generation != self.seen {
+            self.seen = generation;
+            return Poll::Ready(Some(()));
+        }
+
+        self.entry.wakers.borrow_mut()[self.key] = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Signal {
+    fn drop(&mut self) {
+        self.entry.wakers.borrow_mut().remove(self.key);
+        // This listener may have been the one the signalfd wakes. Wake the
+        // others so that one of them takes its place
+        self.entry.wake_all();
+    }
+}
+
+// ===== impl Entry =====
+
+impl Entry {
+    fn get_or_create(signum: libc::c_int) -> io::Result<Rc<Entry>> {
+        let reactor = Handle::current().inner();
+        SIGNALS.with(|signals| {
+            let mut signals = signals.borrow_mut();
+            if let Some(entry) = signals.get(&signum).and_then(Weak::upgrade) {
+                if Rc::ptr_eq(&entry.reactor, &reactor) {
+                    return Ok(entry);
+                }
+            }
+
+            SignalFd::block(signum)?;
+            let fd = Pollable::new_with_interest(SignalFd::new(signum)?, Interest::READABLE)?;
+            let entry = Rc::new(Entry {
+                fd,
+                generation: Cell::new(0),
+                wakers: RefCell::new(Slab::new()),
+                reactor,
+            });
+            signals.insert(signum, Rc::downgrade(&entry));
+            Ok(entry)
+        })
+    }
+
+    // Reads every pending signal. If there were any, the generation is
+    // bumped and every listener is woken. Leaves `cx` registered to be
+    // woken when the next signal arrives
+    fn drain(&self, cx: &mut Context<'_>) {
+        let mut received = false;
+        while let Poll::Ready(Ok(())) = self.fd.poll_readable(cx) {
+            match self.fd.get_ref().read() {
+                Ok(()) => received = true,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.fd.clear_readiness(Direction::Read)
+                }
+                Err(e) => {
+                    tracing::debug!("Signal: failed to read signalfd: {}", e);
+                    break;
+                }
+            }
+        }
+
+        if received {
+            self.generation.set(self.generation.get() + 1);
+            self.wake_all();
+        }
+    }
+
+    fn wake_all(&self) {
+        let wakers: Vec<_> = self
+            .wakers
+            .borrow_mut()
+            .iter_mut()
+            .filter_map(|(_, waker)| waker.take())
+            .collect();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn sigusr1_wakes_every_listener() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let mut first = signal(SignalKind::user_defined1()).unwrap();
+            let mut second = signal(SignalKind::user_defined1()).unwrap();
+            let first = crate::spawn(async move {
+                first.recv().await;
+                first
+            });
+            let second = crate::spawn(async move {
+                second.recv().await;
+                second
+            });
+            // Let both listeners start waiting
+            crate::spawn(async {}).await.unwrap();
+
+            // Raised twice before being read, so they're coalesced
+            unsafe {
+                libc::raise(libc::SIGUSR1);
+                libc::raise(libc::SIGUSR1);
+            }
+
+            let mut first = first.await.unwrap();
+            let mut second = second.await.unwrap();
+            assert!(futures::poll!(Box::pin(first.recv())).is_pending());
+            assert!(futures::poll!(Box::pin(second.recv())).is_pending());
+        });
+    }
+}
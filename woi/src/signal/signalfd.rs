@@ -0,0 +1,70 @@
+use std::io;
+use std::mem;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+
+/// Safe wrapper around a signalfd. It's nonblocking and close on exec
+pub(super) struct SignalFd {
+    fd: RawFd,
+}
+
+impl SignalFd {
+    /// Creates a signalfd receiving `signum`. The signal must be blocked
+    /// so that it is queued for the signalfd instead of being handled
+    pub fn new(signum: libc::c_int) -> io::Result<SignalFd> {
+        let set = unsafe {
+            let mut set = mem::zeroed::<libc::sigset_t>();
+            cvt(libc::sigemptyset(&mut set))?;
+            cvt(libc::sigaddset(&mut set, signum))?;
+            set
+        };
+
+        let flags = libc::SFD_NONBLOCK | libc::SFD_CLOEXEC;
+        let fd = cvt(unsafe { libc::signalfd(-1, &set, flags) })?;
+        Ok(SignalFd { fd })
+    }
+
+    /// Blocks `signum` on the calling thread
+    pub fn block(signum: libc::c_int) -> io::Result<()> {
+        unsafe {
+            let mut set = mem::zeroed::<libc::sigset_t>();
+            cvt(libc::sigemptyset(&mut set))?;
+            cvt(libc::sigaddset(&mut set, signum))?;
+            match libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) {
+                0 => Ok(()),
+                errno => Err(io::Error::from_raw_os_error(errno)),
+            }
+        }
+    }
+
+    /// Reads one pending signal. Fails with `WouldBlock` if there are none
+    pub fn read(&self) -> io::Result<()> {
+        let mut info = mem::MaybeUninit::<libc::signalfd_siginfo>::uninit();
+        let size = mem::size_of::<libc::signalfd_siginfo>();
+        let n = unsafe { libc::read(self.fd, info.as_mut_ptr() as *mut libc::c_void, size) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        let _ = cvt(unsafe { libc::close(self.fd) });
+    }
+}
+
+// Converts C error codes into a Rust Result type
+fn cvt(result: i32) -> io::Result<i32> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
@@ -0,0 +1,79 @@
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{ready, Stream};
+
+/// Stream returned by [`StreamExt::chunks`]
+///
+/// [`StreamExt::chunks`]: super::StreamExt::chunks
+pub struct Chunks<S: Stream> {
+    stream: S,
+    items: Vec<S::Item>,
+    capacity: usize,
+    /// Set once the inner stream has ended
+    done: bool,
+}
+
+impl<S: Stream> Chunks<S> {
+    pub(super) fn new(stream: S, capacity: usize) -> Chunks<S> {
+        assert!(capacity > 0, "chunk capacity must be greater than zero");
+        Chunks {
+            stream,
+            items: Vec::with_capacity(capacity),
+            capacity,
+            done: false,
+        }
+    }
+
+    fn take_items(&mut self) -> Vec<S::Item> {
+        mem::replace(&mut self.items, Vec::with_capacity(self.capacity))
+    }
+}
+
+impl<S: Stream> Stream for Chunks<S> {
+    type Item = Vec<S::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // Safety: the stream is pinned along with `Chunks` and is never moved
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            let stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+            match ready!(stream.poll_next(cx)) {
+                Some(item) => {
+                    this.items.push(item);
+                    if this.items.len() == this.capacity {
+                        return Poll::Ready(Some(this.take_items()));
+                    }
+                }
+                None => {
+                    this.done = true;
+                    if this.items.is_empty() {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(mem::take(&mut this.items)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stream::StreamExt;
+    use crate::Runtime;
+
+    #[test]
+    fn chunks_flush_partial_batch() {
+        let rt = Runtime::new();
+        let chunks: Vec<Vec<u8>> = rt.block_on(async {
+            let stream = futures::stream::iter(1..=7).chunks(3);
+            futures::StreamExt::collect(stream).await
+        });
+        assert_eq!(chunks, vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]]);
+    }
+}
@@ -0,0 +1,63 @@
+mod chunks;
+pub use chunks::Chunks;
+
+mod ready_chunks;
+pub use ready_chunks::ReadyChunks;
+
+mod throttle;
+pub use throttle::Throttle;
+
+use std::time::Duration;
+
+use futures::Stream;
+
+/// Extension trait adding combinators to streams
+pub trait StreamExt: Stream {
+    /// Batches the items of the stream into `Vec`s of up to `capacity`
+    /// items
+    ///
+    /// A batch is yielded once it is full. When the stream ends, a partial
+    /// batch is yielded before the stream ends too. Items buffered in a
+    /// partial batch are lost if the `Chunks` is dropped
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero
+    fn chunks(self, capacity: usize) -> Chunks<Self>
+    where
+        Self: Sized,
+    {
+        Chunks::new(self, capacity)
+    }
+
+    /// Batches the items of the stream into `Vec`s of up to `capacity`
+    /// items, without holding on to items for longer than `max_delay`
+    ///
+    /// A batch is yielded once it is full, or once `max_delay` has passed
+    /// since its first item was buffered, whichever comes first. When the
+    /// stream ends, a partial batch is yielded before the stream ends too
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero
+    fn ready_chunks(self, capacity: usize, max_delay: Duration) -> ReadyChunks<Self>
+    where
+        Self: Sized,
+    {
+        ReadyChunks::new(self, capacity, max_delay)
+    }
+
+    /// Rate-limits the stream, so that at least `duration` passes between
+    /// the items it yields
+    ///
+    /// The first item is yielded as soon as it's ready. A stream that is
+    /// already slower than `duration` isn't delayed any further
+    fn throttle(self, duration: Duration) -> Throttle<Self>
+    where
+        Self: Sized,
+    {
+        Throttle::new(self, duration)
+    }
+}
+
+impl<S: Stream + ?Sized> StreamExt for S {}
@@ -0,0 +1,136 @@
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+
+use crate::time::{sleep, Sleep};
+
+/// Stream returned by [`StreamExt::ready_chunks`]
+///
+/// [`StreamExt::ready_chunks`]: super::StreamExt::ready_chunks
+pub struct ReadyChunks<S: Stream> {
+    stream: S,
+    items: Vec<S::Item>,
+    capacity: usize,
+    max_delay: Duration,
+    /// Fires `max_delay` after the first item of the current batch. It is
+    /// created with the first batch and reset for every batch after that
+    sleep: Option<Sleep>,
+    /// Set once the inner stream has ended
+    done: bool,
+}
+
+impl<S: Stream> ReadyChunks<S> {
+    pub(super) fn new(stream: S, capacity: usize, max_delay: Duration) -> ReadyChunks<S> {
+        assert!(capacity > 0, "chunk capacity must be greater than zero");
+        ReadyChunks {
+            stream,
+            items: Vec::with_capacity(capacity),
+            capacity,
+            max_delay,
+            sleep: None,
+            done: false,
+        }
+    }
+
+    fn take_items(&mut self) -> Vec<S::Item> {
+        mem::replace(&mut self.items, Vec::with_capacity(self.capacity))
+    }
+
+    // Starts the flush timer for a new batch
+    fn start_batch(&mut self) {
+        match &mut self.sleep {
+            Some(sleep) => Pin::new(sleep).reset(Instant::now() + self.max_delay),
+            None => self.sleep = Some(sleep(self.max_delay)),
+        }
+    }
+}
+
+impl<S: Stream> Stream for ReadyChunks<S> {
+    type Item = Vec<S::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // Safety: the stream is pinned along with `ReadyChunks` and is never moved
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            let stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+            match stream.poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    if this.items.is_empty() {
+                        this.start_batch();
+                    }
+                    this.items.push(item);
+                    if this.items.len() == this.capacity {
+                        return Poll::Ready(Some(this.take_items()));
+                    }
+                }
+                Poll::Ready(None) => {
+                    this.done = true;
+                    if this.items.is_empty() {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(mem::take(&mut this.items)));
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        // The stream has nothing more for now. Flush the batch if it has
+        // waited long enough
+        if let Some(sleep) = &mut this.sleep {
+            if !this.items.is_empty() && Pin::new(sleep).poll(cx).is_ready() {
+                return Poll::Ready(Some(this.take_items()));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::StreamExt;
+    use crate::Runtime;
+
+    #[test]
+    fn ready_chunks_flush_when_full() {
+        let rt = Runtime::new();
+        let chunks: Vec<Vec<u8>> = rt.block_on(async {
+            let stream = futures::stream::iter(1..=6).ready_chunks(3, Duration::from_secs(10));
+            futures::StreamExt::collect(stream).await
+        });
+        assert_eq!(chunks, vec![vec![1, 2, 3], vec![4, 5, 6]]);
+    }
+
+    #[test]
+    fn ready_chunks_flush_after_delay() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            // Yields 1 and 2 straight away, then 3 well after the delay
+            let stream = futures::stream::unfold(1, |i| async move {
+                if i == 3 {
+                    sleep(Duration::from_millis(200)).await;
+                }
+                (i <= 3).then(|| (i, i + 1))
+            });
+            let stream = stream.ready_chunks(10, Duration::from_millis(50));
+            crate::pin!(stream);
+
+            // 3 is still sleeping when the delay runs out, so the first
+            // chunk is flushed without it
+            assert_eq!(
+                futures::StreamExt::next(&mut stream).await,
+                Some(vec![1, 2])
+            );
+            assert_eq!(futures::StreamExt::next(&mut stream).await, Some(vec![3]));
+            assert_eq!(futures::StreamExt::next(&mut stream).await, None);
+        });
+    }
+}
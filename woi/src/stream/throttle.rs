@@ -0,0 +1,152 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::{ready, Stream};
+
+use crate::time::{sleep, Sleep};
+
+/// Stream returned by [`StreamExt::throttle`]
+///
+/// [`StreamExt::throttle`]: super::StreamExt::throttle
+pub struct Throttle<S: Stream> {
+    stream: S,
+    duration: Duration,
+    /// Item taken from the stream while waiting on `sleep`
+    item: Option<S::Item>,
+    /// Set once the inner stream has ended
+    done: bool,
+    /// Fires `duration` after the last item was yielded. It is created with
+    /// the first item and reset for every item after that
+    sleep: Option<Sleep>,
+    /// Set while the next item has to wait for `sleep`
+    waiting: bool,
+}
+
+impl<S: Stream> Throttle<S> {
+    pub(super) fn new(stream: S, duration: Duration) -> Throttle<S> {
+        Throttle {
+            stream,
+            duration,
+            item: None,
+            done: false,
+            sleep: None,
+            waiting: false,
+        }
+    }
+
+    // Holds back the next item until `duration` from now
+    fn start_wait(&mut self) {
+        match &mut self.sleep {
+            Some(sleep) => Pin::new(sleep).reset(Instant::now() + self.duration),
+            None => self.sleep = Some(sleep(self.duration)),
+        }
+        self.waiting = true;
+    }
+}
+
+impl<S: Stream> Stream for Throttle<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // Safety: the stream is pinned along with `Throttle` and is never moved
+        let this = unsafe { self.get_unchecked_mut() };
+
+        // The stream is polled while waiting too, so the wait doesn't add to
+        // the time the stream takes to produce its next item
+        if this.item.is_none() && !this.done {
+            let stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+            match ready!(stream.poll_next(cx)) {
+                Some(item) => this.item = Some(item),
+                None => this.done = true,
+            }
+        }
+
+        if this.item.is_none() {
+            return Poll::Ready(None);
+        }
+
+        if this.waiting {
+            if let Some(sleep) = &mut this.sleep {
+                ready!(Pin::new(sleep).poll(cx));
+            }
+            this.waiting = false;
+        }
+
+        this.start_wait();
+        Poll::Ready(this.item.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::StreamExt;
+    use crate::Runtime;
+
+    #[test]
+    fn throttle_spaces_items() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let stream = futures::stream::iter(1..=3).throttle(Duration::from_millis(30));
+            crate::pin!(stream);
+
+            let mut yielded = Vec::new();
+            while let Some(item) = futures::StreamExt::next(&mut stream).await {
+                yielded.push((item, Instant::now()));
+            }
+
+            let items: Vec<_> = yielded.iter().map(|(item, _)| *item).collect();
+            assert_eq!(items, vec![1, 2, 3]);
+            for pair in yielded.windows(2) {
+                let gap = pair[1].1 - pair[0].1;
+                assert!(gap >= Duration::from_millis(30), "gap was {:?}", gap);
+            }
+        });
+    }
+
+    #[test]
+    fn throttle_adds_no_delay_to_slow_stream() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            // Each item takes longer to arrive than the throttle duration.
+            // `produced` counts the items the source has yielded
+            let produced = Rc::new(Cell::new(0));
+            let stream = {
+                let produced = produced.clone();
+                futures::stream::unfold(1, move |i| {
+                    let produced = produced.clone();
+                    async move {
+                        sleep(Duration::from_millis(50)).await;
+                        (i <= 3).then(|| {
+                            produced.set(i);
+                            (i, i + 1)
+                        })
+                    }
+                })
+            };
+            let stream = stream.throttle(Duration::from_millis(20));
+            crate::pin!(stream);
+
+            let mut items = Vec::new();
+            futures::future::poll_fn(|cx| loop {
+                match stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(item)) => items.push(item),
+                    Poll::Ready(None) => return Poll::Ready(()),
+                    Poll::Pending => {
+                        // Only pending while the source is. The throttle never
+                        // holds an item back, the first one included
+                        assert_eq!(produced.get(), items.len());
+                        return Poll::Pending;
+                    }
+                }
+            })
+            .await;
+            assert_eq!(items, vec![1, 2, 3]);
+        });
+    }
+}
@@ -0,0 +1,153 @@
+use std::cell::Cell;
+use std::ptr::NonNull;
+
+use crate::task::header::Header;
+
+thread_local! {
+    // Set while an aborted task's future is being dropped
+    static CANCELLING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f`, which drops the future of an aborted task. Join handles dropped
+/// by `f` can tell through [`is_cancelling`]
+pub(crate) fn cancelling<R>(f: impl FnOnce() -> R) -> R {
+    // Restores the flag even if dropping the future panics
+    struct Reset(bool);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CANCELLING.with(|cancelling| cancelling.set(self.0));
+        }
+    }
+
+    let _reset = Reset(CANCELLING.with(|cancelling| cancelling.replace(true)));
+    f()
+}
+
+/// Returns true if the task being dropped was aborted
+pub(crate) fn is_cancelling() -> bool {
+    CANCELLING.with(|cancelling| cancelling.get())
+}
+
+/// A handle that can abort a task, without being able to await it
+///
+/// Created with [`JoinHandle::abort_handle`]. It can be cloned cheaply and
+/// keeps the task allocated while it is around
+///
+/// [`JoinHandle::abort_handle`]: crate::task::JoinHandle::abort_handle
+pub struct AbortHandle {
+    raw: NonNull<()>,
+}
+
+impl AbortHandle {
+    /// Takes out a reference to the task for the handle
+    pub(crate) fn new(raw: NonNull<()>) -> AbortHandle {
+        let ptr = raw.as_ptr();
+        let header = ptr as *const Header;
+        unsafe { ((*header).vtable.clone_reference)(ptr) };
+        AbortHandle { raw }
+    }
+
+    /// Aborts the task
+    ///
+    /// The task's future is dropped instead of being polled again, and its
+    /// `JoinHandle` resolves to [`JoinError::Cancelled`]. A task that is being
+    /// polled when it's aborted is stopped at its next poll. Aborting a task
+    /// that has already completed does nothing
+    ///
+    /// [`JoinError::Cancelled`]: crate::JoinError::Cancelled
+    pub fn abort(&self) {
+        let ptr = self.raw.as_ptr();
+        let header = ptr as *const Header;
+        unsafe { ((*header).vtable.abort)(ptr) }
+    }
+
+    /// Returns true if the task has completed, including by being aborted
+    pub fn is_finished(&self) -> bool {
+        let header = self.raw.as_ptr() as *const Header;
+        unsafe { (*header).state.is_complete() }
+    }
+}
+
+impl Clone for AbortHandle {
+    fn clone(&self) -> Self {
+        AbortHandle::new(self.raw)
+    }
+}
+
+impl Drop for AbortHandle {
+    fn drop(&mut self) {
+        let ptr = self.raw.as_ptr();
+        let header = ptr as *const Header;
+        unsafe { ((*header).vtable.drop_reference)(ptr) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task::JoinError;
+    use crate::test_util::DropFlag;
+    use crate::Runtime;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn abort_queued_task() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let dropped = Rc::new(Cell::new(false));
+            let flag = DropFlag(dropped.clone());
+            let polled = Rc::new(Cell::new(false));
+            let was_polled = polled.clone();
+
+            // Aborted while it's still on the queue, so it's never polled
+            let handle = crate::spawn(async move {
+                let _flag = flag;
+                was_polled.set(true);
+            });
+            let abort = handle.abort_handle();
+            abort.abort();
+
+            let err = handle.await.unwrap_err();
+            assert!(matches!(err, JoinError::Cancelled));
+            assert!(!polled.get());
+            assert!(dropped.get());
+            assert!(abort.is_finished());
+        });
+    }
+
+    #[test]
+    fn abort_pending_task() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let dropped = Rc::new(Cell::new(false));
+            let flag = DropFlag(dropped.clone());
+            let handle = crate::spawn(async move {
+                let _flag = flag;
+                futures::future::pending::<()>().await
+            });
+            // Let it park, with no one left to wake it
+            crate::task::yield_now().await;
+            assert!(!dropped.get());
+
+            handle.abort_handle().abort();
+            assert!(handle.await.unwrap_err().is_cancelled());
+            assert!(dropped.get());
+        });
+    }
+
+    #[test]
+    fn abort_complete_task_is_noop() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let mut handle = crate::spawn(async { 7 });
+            let abort = handle.abort_handle();
+            crate::task::yield_now().await;
+            assert!(abort.is_finished());
+
+            abort.abort();
+            abort.clone().abort();
+            assert_eq!((&mut handle).await.unwrap(), 7);
+        });
+    }
+}
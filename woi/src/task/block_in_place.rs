@@ -0,0 +1,75 @@
+use crate::runtime;
+
+/// Runs the blocking function `f` from within a task
+///
+/// On a multi-threaded runtime this would hand the worker's other tasks off
+/// to another thread first. The runtime is single-threaded, so there is no
+/// one to hand them to: `f` is run inline and every other task is stalled
+/// until it returns. A warning is logged when this happens inside a runtime,
+/// to make the stall easy to find. Outside of a runtime, `f` is just called
+pub fn block_in_place<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    if runtime::context::try_spawner().is_some() {
+        tracing::warn!(
+            "`block_in_place` called on a single-threaded runtime. Other tasks are stalled until it returns"
+        );
+    }
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Level, Metadata, Subscriber};
+
+    // Counts the warnings logged while it is the default subscriber
+    #[derive(Clone, Default)]
+    struct CountWarnings(Arc<AtomicUsize>);
+
+    impl Subscriber for CountWarnings {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            if *event.metadata().level() == Level::WARN {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn runs_closure_and_warns_inside_runtime() {
+        let warnings = CountWarnings::default();
+        let count = warnings.0.clone();
+
+        tracing::subscriber::with_default(warnings, || {
+            assert_eq!(block_in_place(|| 1), 1);
+            assert_eq!(count.load(Ordering::Relaxed), 0);
+
+            let rt = Runtime::new();
+            let value =
+                rt.block_on(async { crate::spawn(async { block_in_place(|| 2) }).await.unwrap() });
+            assert_eq!(value, 2);
+            assert_eq!(count.load(Ordering::Relaxed), 1);
+        });
+    }
+}
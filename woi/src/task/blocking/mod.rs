@@ -0,0 +1,346 @@
+mod pool;
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::io;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use slab::Slab;
+
+use self::pool::{JobId, Pool};
+use crate::io::epoll::Interest;
+use crate::io::eventfd::EventFd;
+use crate::io::io_source::Direction;
+use crate::io::pollable::Pollable;
+use crate::io::reactor::Handle;
+use crate::runtime::{context, RuntimeMetrics};
+use crate::task::{self, JoinHandle, WakeSource};
+
+/// Runs `f` on a thread of the blocking pool, returning a handle to await
+/// its result
+///
+/// Use this for synchronous work that would otherwise block the runtime,
+/// like blocking syscalls or long computations. The handle is awaited like
+/// the handle of any spawned task. If `f` panics, it resolves to
+/// [`JoinError::Panic`]
+///
+/// Aborting the task before a thread has picked up `f` takes `f` back out
+/// of the pool's queue, so it never runs. Once `f` is running it can't be
+/// stopped, and aborting the task only stops the runtime from waiting on it
+///
+/// Panics if called outside of a runtime, like [`spawn`](crate::spawn)
+///
+/// [`JoinError::Panic`]: crate::JoinError::Panic
+pub fn spawn_blocking<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let completions = context::blocking();
+    let key = completions.waiters.borrow_mut().insert(None);
+    let output = Arc::new(Mutex::new(None));
+
+    let done = completions.pollable.get_ref().clone();
+    let slot = output.clone();
+    let job = Pool::get().execute(Box::new(move || {
+        *slot.lock().unwrap() = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+        done.complete(key);
+    }));
+
+    crate::spawn(Blocking {
+        key,
+        job,
+        finished: false,
+        output,
+        completions,
+    })
+}
+
+/// Copies the queue depth and thread counts of the blocking pool into
+/// `metrics`
+pub(crate) fn fill_metrics(metrics: &mut RuntimeMetrics) {
+    Pool::get().fill_metrics(metrics)
+}
+
+/// Where the blocking closures of a runtime report that they're done
+///
+/// Every closure a runtime hands to the pool writes to the same eventfd.
+/// Whenever the runtime finds it readable, it wakes the tasks awaiting the
+/// closures that finished
+pub(crate) struct Completions {
+    pollable: Pollable<Arc<Done>>,
+    /// Wakers of the tasks awaiting a closure, by the key of the closure
+    waiters: RefCell<Slab<Option<Waker>>>,
+}
+
+/// The part of [`Completions`] written to by the threads of the pool
+struct Done {
+    fd: EventFd,
+    /// Keys of the closures that finished since the runtime last looked
+    keys: Mutex<Vec<usize>>,
+    /// Counts each closure finishing, as a wake from another thread
+    remote_wakes: Arc<AtomicU64>,
+}
+
+/// Task awaiting a blocking closure
+struct Blocking<T> {
+    key: usize,
+    job: JobId,
+    /// Whether the output was returned, so there's nothing left to cancel
+    finished: bool,
+    /// Set by the pool once the closure returns or panics
+    output: Arc<Mutex<Option<thread::Result<T>>>>,
+    completions: Rc<Completions>,
+}
+
+// ===== impl Completions =====
+
+impl Completions {
+    pub(crate) fn new(handle: Handle, remote_wakes: Arc<AtomicU64>) -> io::Result<Completions> {
+        let done = Arc::new(Done {
+            fd: EventFd::new()?,
+            keys: Mutex::new(Vec::new()),
+            remote_wakes,
+        });
+        Ok(Completions {
+            pollable: Pollable::new_with_handle(done, Interest::READABLE, handle)?,
+            waiters: RefCell::new(Slab::new()),
+        })
+    }
+
+    /// Wakes the tasks whose closures finished. Called by the runtime each
+    /// time it has processed IO events
+    pub(crate) fn dispatch(&self) {
+        if !self.pollable.readable() {
+            return;
+        }
+
+        // Closures that finish from here on make the eventfd readable again
+        let done = self.pollable.get_ref();
+        if let Err(e) = done.fd.drain() {
+            tracing::debug!("Blocking: failed to drain eventfd: {}", e);
+        }
+        self.pollable.clear_readiness(Direction::Read);
+        let keys = std::mem::take(&mut *done.keys.lock().unwrap());
+
+        // Woken once the waiters are released, since a wake can drop a task
+        let wakers: Vec<Waker> = {
+            let mut waiters = self.waiters.borrow_mut();
+            keys.into_iter()
+                .filter_map(|key| waiters.get_mut(key).and_then(Option::take))
+                .collect()
+        };
+        for waker in wakers {
+            task::wake(waker, WakeSource::Io);
+        }
+    }
+}
+
+// ===== impl Done =====
+
+impl Done {
+    fn complete(&self, key: usize) {
+        self.keys.lock().unwrap().push(key);
+        self.remote_wakes.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = self.fd.notify() {
+            tracing::debug!("Blocking: failed to notify eventfd: {}", e);
+        }
+    }
+}
+
+impl AsRawFd for Done {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+// ===== impl Blocking =====
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let output = self.output.lock().unwrap().take();
+        if output.is_some() {
+            self.finished = true;
+        }
+        match output {
+            Some(Ok(output)) => Poll::Ready(output),
+            // Caught by the task, so the handle resolves to a panic error
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            None => {
+                // The closure can only be seen to finish once the runtime
+                // processes IO events, which is after this returns
+                self.completions.waiters.borrow_mut()[self.key] = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Blocking<T> {
+    fn drop(&mut self) {
+        // Dropped before finishing when the task is aborted. The closure is
+        // cancelled if it's still queued
+        if !self.finished && Pool::get().cancel(self.job) {
+            tracing::debug!("Blocking: cancelled queued closure");
+        }
+        // A key left behind by the closure wakes nothing, or at worst the
+        // task that reused the key
+        self.completions.waiters.borrow_mut().remove(self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn blocking_closure_signalled_from_task() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = mpsc::channel();
+            let handle = spawn_blocking(move || rx.recv().unwrap() * 2);
+
+            crate::spawn(async move {
+                crate::time::sleep(Duration::from_millis(10)).await;
+                tx.send(21).unwrap();
+            });
+
+            assert_eq!(handle.await.unwrap(), 42);
+        });
+    }
+
+    #[test]
+    fn blocking_closure_waits_on_later_closure() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            // Leaves a thread of the pool idle
+            spawn_blocking(|| ()).await.unwrap();
+            crate::time::sleep(Duration::from_millis(10)).await;
+
+            // Both are queued before the idle thread takes the first one,
+            // so the second needs a thread of its own
+            let (tx, rx) = mpsc::channel();
+            let first = spawn_blocking(move || rx.recv_timeout(Duration::from_secs(5)));
+            let second = spawn_blocking(move || tx.send(7).unwrap());
+
+            second.await.unwrap();
+            assert_eq!(first.await.unwrap(), Ok(7));
+        });
+    }
+
+    #[test]
+    fn closures_finishing_together_wake_their_tasks() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            // The closures all report to the same eventfd, possibly within
+            // a single wake of the runtime
+            let (tx, rx) = mpsc::channel::<()>();
+            let rx = Arc::new(Mutex::new(rx));
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let rx = rx.clone();
+                    spawn_blocking(move || {
+                        let _ = rx.lock().unwrap().recv();
+                        i
+                    })
+                })
+                .collect();
+            drop(tx);
+
+            for (i, handle) in handles.into_iter().enumerate() {
+                assert_eq!(handle.await.unwrap(), i);
+            }
+        });
+    }
+
+    #[test]
+    fn aborting_queued_closure_means_it_never_runs() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            // Takes up every thread of the pool, so the next closure queues
+            let (tx, rx) = mpsc::channel::<()>();
+            let rx = Arc::new(Mutex::new(rx));
+            let blockers: Vec<_> = (0..pool::MAX_THREADS)
+                .map(|_| {
+                    let rx = rx.clone();
+                    spawn_blocking(move || {
+                        let _ = rx.lock().unwrap().recv();
+                    })
+                })
+                .collect();
+
+            let ran = Arc::new(AtomicBool::new(false));
+            let queued = {
+                let ran = ran.clone();
+                spawn_blocking(move || ran.store(true, Ordering::SeqCst))
+            };
+            queued.abort_handle().abort();
+            assert!(queued.await.unwrap_err().is_cancelled());
+
+            drop(tx);
+            for blocker in blockers {
+                blocker.await.unwrap();
+            }
+            // Every thread is free again, and the closure still hasn't run
+            spawn_blocking(|| ()).await.unwrap();
+            assert!(!ran.load(Ordering::SeqCst));
+        });
+    }
+
+    #[test]
+    fn metrics_show_saturated_pool() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = mpsc::channel::<()>();
+            let rx = Arc::new(Mutex::new(rx));
+            let blockers: Vec<_> = (0..pool::MAX_THREADS + 2)
+                .map(|_| {
+                    let rx = rx.clone();
+                    spawn_blocking(move || {
+                        let _ = rx.lock().unwrap().recv();
+                    })
+                })
+                .collect();
+
+            // Once every thread has taken a closure, the rest stay queued
+            let metrics = loop {
+                let metrics = rt.metrics();
+                if metrics.blocking_threads == pool::MAX_THREADS
+                    && metrics.idle_blocking_threads == 0
+                {
+                    break metrics;
+                }
+                crate::time::sleep(Duration::from_millis(1)).await;
+            };
+            assert!(metrics.blocking_queue_depth >= 2);
+
+            drop(tx);
+            for blocker in blockers {
+                blocker.await.unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn panicking_closure_is_panic() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let handle = spawn_blocking(|| panic!("blocking panic"));
+            assert!(handle.await.unwrap_err().is_panic());
+        });
+    }
+}
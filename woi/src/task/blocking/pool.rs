@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::runtime::RuntimeMetrics;
+
+/// The most threads the pool runs at once. Closures handed to it beyond
+/// that wait for a thread to free up
+pub(super) const MAX_THREADS: usize = 16;
+
+/// How long a thread waits for another closure before it exits
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Identifies a closure handed to the pool, so it can be taken back out
+/// of the queue
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) struct JobId(u64);
+
+static POOL: OnceLock<Pool> = OnceLock::new();
+
+/// Threads running blocking closures, shared by every runtime in the
+/// process. Threads are started as closures arrive and exit once they've
+/// been idle for a while
+pub(super) struct Pool {
+    state: Mutex<State>,
+    /// Notified when a closure is queued
+    condvar: Condvar,
+}
+
+struct State {
+    queue: VecDeque<(JobId, Job)>,
+    /// Id of the next closure queued
+    next_id: u64,
+    /// Threads that are running
+    threads: usize,
+    /// Threads waiting for a closure to be queued
+    idle: usize,
+}
+
+impl Pool {
+    pub fn get() -> &'static Pool {
+        POOL.get_or_init(|| Pool {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                next_id: 0,
+                threads: 0,
+                idle: 0,
+            }),
+            condvar: Condvar::new(),
+        })
+    }
+
+    /// Runs `job` on a thread of the pool
+    pub fn execute(&'static self, job: Job) -> JobId {
+        let mut state = self.state.lock().unwrap();
+        let id = JobId(state.next_id);
+        state.next_id += 1;
+        state.queue.push_back((id, job));
+
+        // A thread that was notified counts as idle until it wakes up, so
+        // several closures queued in a row need more threads than there are
+        // idle ones. Otherwise a closure waiting on one queued after it would
+        // hold the only thread that could run it
+        if state.idle > 0 {
+            self.condvar.notify_one();
+        }
+        if state.queue.len() > state.idle && state.threads < MAX_THREADS {
+            state.threads += 1;
+            drop(state);
+            thread::Builder::new()
+                .name("woi-blocking".into())
+                .spawn(move || self.run())
+                .expect("failed to spawn blocking thread");
+        }
+        id
+    }
+
+    /// Removes the closure from the queue, unless a thread already took it.
+    /// Returns whether it was removed, in which case it never runs
+    pub fn cancel(&self, id: JobId) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.queue.iter().position(|(queued, _)| *queued == id) {
+            Some(index) => {
+                let job = state.queue.remove(index);
+                // Whatever the closure captured is dropped outside the lock
+                drop(state);
+                drop(job);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copies the queue depth and thread counts into `metrics`
+    pub fn fill_metrics(&self, metrics: &mut RuntimeMetrics) {
+        let state = self.state.lock().unwrap();
+        metrics.blocking_queue_depth = state.queue.len();
+        metrics.blocking_threads = state.threads;
+        metrics.idle_blocking_threads = state.idle;
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some((_, job)) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            state.idle += 1;
+            let (guard, result) = self.condvar.wait_timeout(state, KEEP_ALIVE).unwrap();
+            state = guard;
+            state.idle -= 1;
+
+            if result.timed_out() && state.queue.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+}
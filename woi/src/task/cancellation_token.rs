@@ -0,0 +1,130 @@
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use slab::Slab;
+
+/// Signals to any number of tasks that they should stop
+///
+/// Clones share the same state, so cancelling one clone cancels all of
+/// them. Once cancelled, a token stays cancelled
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Rc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: Cell<bool>,
+    /// Wakers of the tasks waiting on `cancelled`
+    wakers: RefCell<Slab<Option<Waker>>>,
+}
+
+/// Future returned by [`CancellationToken::cancelled`]
+pub struct Cancelled<'a> {
+    token: &'a CancellationToken,
+    /// Key of this future's waker in the token. Only set while it's waiting
+    key: Option<usize>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels the token, waking every task waiting on it. Cancelling a
+    /// token that is already cancelled does nothing
+    pub fn cancel(&self) {
+        if self.inner.cancelled.replace(true) {
+            return;
+        }
+
+        let wakers: Vec<_> = self
+            .inner
+            .wakers
+            .borrow_mut()
+            .iter_mut()
+            .filter_map(|(_, waker)| waker.take())
+            .collect();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.get()
+    }
+
+    /// Completes once the token is cancelled
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled {
+            token: self,
+            key: None,
+        }
+    }
+}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let inner = &self.token.inner;
+        if inner.cancelled.get() {
+            return Poll::Ready(());
+        }
+
+        let waker = Some(cx.waker().clone());
+        match self.key {
+            Some(key) => inner.wakers.borrow_mut()[key] = waker,
+            None => {
+                let key = inner.wakers.borrow_mut().insert(waker);
+                self.key = Some(key);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.token.inner.wakers.borrow_mut().remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn cancel_wakes_every_waiter() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let token = CancellationToken::new();
+            let handles: Vec<_> = (0..3)
+                .map(|_| {
+                    let token = token.clone();
+                    crate::spawn(async move { token.cancelled().await })
+                })
+                .collect();
+            crate::task::yield_now().await;
+            assert_eq!(token.inner.wakers.borrow().len(), 3);
+
+            token.cancel();
+            token.cancel();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            assert!(token.is_cancelled());
+            // Each waiter left the token when it completed
+            assert!(token.inner.wakers.borrow().is_empty());
+
+            // Already cancelled, so it completes straight away
+            assert!(futures::poll!(token.cancelled()).is_ready());
+        });
+    }
+}
@@ -0,0 +1,170 @@
+use std::cell::Cell;
+use std::ptr;
+use std::task::{Context, Poll};
+
+/// How many times a task can make progress on a resource before it's made
+/// to yield
+const BUDGET: u8 = 128;
+
+/// What's left of a task's budget
+///
+/// It's kept in the task's header, so it carries over from one poll of the
+/// task to the next. It's only refilled when the task yields, either by
+/// awaiting [`yield_now`](crate::task::yield_now) or because it ran out.
+/// A task that keeps finding its resources ready, like a receiver of a
+/// channel that's never empty, is made to yield every so often
+pub(crate) struct Budget(Cell<u8>);
+
+thread_local! {
+    // Budget of the task being polled. Points into the header of the task,
+    // so it is only valid while the guard lives. Null outside of tasks,
+    // where progress isn't limited
+    static CURRENT: Cell<*const Budget> = const { Cell::new(ptr::null()) };
+}
+
+pub(crate) struct EnterGuard {
+    prev: *const Budget,
+}
+
+impl Budget {
+    pub fn new() -> Budget {
+        Budget(Cell::new(BUDGET))
+    }
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.prev));
+    }
+}
+
+/// Sets `budget` as the budget of the task being polled until the returned
+/// guard is dropped. The guard must be dropped before `budget` is
+pub(crate) fn enter(budget: &Budget) -> EnterGuard {
+    let prev = CURRENT.with(|current| current.replace(budget));
+    EnterGuard { prev }
+}
+
+// Runs `f` with the budget of the task being polled, if there is one
+fn with_current<R>(f: impl FnOnce(Option<&Budget>) -> R) -> R {
+    CURRENT.with(|current| {
+        // Safety: the pointer is only set while the budget it points to is
+        // alive, see `enter`
+        let budget = unsafe { current.get().as_ref() };
+        f(budget)
+    })
+}
+
+/// Makes the task yield if it has spent its budget, before a resource
+/// checks whether it's ready. The task is woken straight away and gets a
+/// full budget for when it's polled again
+pub(crate) fn poll_proceed(cx: &mut Context<'_>) -> Poll<()> {
+    with_current(|budget| match budget {
+        Some(budget) if budget.0.get() == 0 => {
+            tracing::debug!("Coop budget spent, yielding");
+            budget.0.set(BUDGET);
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+        _ => Poll::Ready(()),
+    })
+}
+
+/// Spends a unit of the task's budget. Called by a resource that was ready
+pub(crate) fn spend() {
+    with_current(|budget| {
+        if let Some(budget) = budget {
+            budget.0.set(budget.0.get().saturating_sub(1));
+        }
+    })
+}
+
+/// Refills the task's budget, since it yielded of its own accord
+pub(crate) fn reset() {
+    with_current(|budget| {
+        if let Some(budget) = budget {
+            budget.0.set(BUDGET);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::unbounded;
+    use crate::Runtime;
+    use std::future::Future;
+    use std::rc::Rc;
+
+    #[test]
+    fn ready_receiver_is_made_to_yield() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = unbounded::channel();
+            for i in 0..BUDGET as usize * 2 {
+                tx.send(i).unwrap();
+            }
+
+            let done = Rc::new(Cell::new(false));
+            let receiver = {
+                let done = done.clone();
+                crate::spawn(async move {
+                    for _ in 0..BUDGET as usize * 2 {
+                        rx.recv().await.unwrap();
+                    }
+                    done.set(true);
+                })
+            };
+            // Gets a turn once the receiver has spent its budget, though
+            // every message it waits on is already queued
+            let other = {
+                let done = done.clone();
+                crate::spawn(async move { done.get() })
+            };
+            assert!(!other.await.unwrap());
+            receiver.await.unwrap();
+            assert!(done.get());
+        });
+    }
+
+    #[test]
+    fn yield_now_refills_budget() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = unbounded::channel();
+            for i in 0..BUDGET as usize * 2 {
+                tx.send(i).unwrap();
+            }
+
+            let forced = Rc::new(Cell::new(false));
+            let receiver = {
+                let forced = forced.clone();
+                crate::spawn(async move {
+                    // Spends all but one unit, then yields of its own accord
+                    for _ in 0..BUDGET - 1 {
+                        rx.recv().await.unwrap();
+                    }
+                    crate::task::yield_now().await;
+
+                    // A budget carried over from before the yield would run
+                    // out on the second message
+                    for _ in 0..BUDGET {
+                        let polls = Cell::new(0);
+                        let recv = rx.recv();
+                        crate::pin!(recv);
+                        let counted = futures::future::poll_fn(|cx| {
+                            polls.set(polls.get() + 1);
+                            recv.as_mut().poll(cx)
+                        });
+                        counted.await.unwrap();
+                        if polls.get() > 1 {
+                            forced.set(true);
+                        }
+                    }
+                })
+            };
+            receiver.await.unwrap();
+            assert!(!forced.get());
+        });
+    }
+}
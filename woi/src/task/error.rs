@@ -2,7 +2,30 @@ use core::fmt;
 use std::any::Any;
 
 pub enum JoinError {
-    Panic(Box<dyn Any + 'static>),
+    /// The task panicked. The payload is `Send`, so errors of tasks on the
+    /// [multi-threaded runtime](crate::runtime::multi_thread) can be sent
+    /// back to the thread awaiting them
+    Panic(Box<dyn Any + Send + 'static>),
+    /// The output of the task was already taken by an earlier poll of its
+    /// `JoinHandle`
+    OutputTaken,
+    /// The task was aborted before it completed
+    Cancelled,
+}
+
+impl JoinError {
+    /// Returns true if the task panicked
+    ///
+    /// When built with `panic = "abort"`, a panicking task aborts the process
+    /// instead, so this is never true
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panic(_))
+    }
+
+    /// Returns true if the task was aborted
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
 }
 
 impl std::error::Error for JoinError {}
@@ -11,6 +34,8 @@ impl fmt::Display for JoinError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             JoinError::Panic(_) => write!(f, "panic"),
+            JoinError::OutputTaken => write!(f, "output already taken"),
+            JoinError::Cancelled => write!(f, "task was cancelled"),
         }
     }
 }
@@ -19,6 +44,44 @@ impl fmt::Debug for JoinError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             JoinError::Panic(_) => write!(f, "JoinError::Panic(..)"),
+            JoinError::OutputTaken => write!(f, "JoinError::OutputTaken"),
+            JoinError::Cancelled => write!(f, "JoinError::Cancelled"),
+        }
+    }
+}
+
+/// Error returned by [`try_spawn`] when the run queue is at its limit. The
+/// future is handed back without a task being allocated for it
+///
+/// [`try_spawn`]: crate::task::try_spawn
+pub enum TrySpawnError<F> {
+    /// The run queue already holds as many tasks as its limit
+    Full(F),
+}
+
+impl<F> TrySpawnError<F> {
+    /// Returns the future that couldn't be spawned
+    pub fn into_inner(self) -> F {
+        match self {
+            TrySpawnError::Full(future) => future,
+        }
+    }
+}
+
+impl<F> std::error::Error for TrySpawnError<F> {}
+
+impl<F> fmt::Display for TrySpawnError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySpawnError::Full(_) => write!(f, "run queue is full"),
+        }
+    }
+}
+
+impl<F> fmt::Debug for TrySpawnError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySpawnError::Full(_) => write!(f, "TrySpawnError::Full(..)"),
         }
     }
 }
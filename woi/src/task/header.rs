@@ -1,62 +1,102 @@
-use std::cell::Cell;
 use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::task::Waker;
 
+use slab::Slab;
+
+use crate::task::coop::Budget;
 use crate::task::raw::TaskVTable;
 use crate::task::state::State;
+use crate::task::task_local::TaskLocals;
+#[cfg(debug_assertions)]
+use crate::task::WakeSource;
 
 pub(crate) struct Header {
     pub state: State,
-    pub waker: Option<Waker>,        // Why is this wrapped in UnsafeCell?
+    /// Wakers of the join handles waiting on the task to complete, by the
+    /// key each handle registered. Emptied once they have been woken
+    pub wakers: Slab<Option<Waker>>,
     pub vtable: &'static TaskVTable, // Why &'static? Think cause they are fns
     pub id: TaskId,
+    /// Values of the task-local keys set by the task
+    pub locals: TaskLocals,
+    /// What's left of the task's coop budget
+    pub budget: Budget,
+    /// What last woke the task
+    #[cfg(debug_assertions)]
+    pub wake_source: WakeSource,
 }
 
 impl Header {
-    pub fn register_waker(&mut self, waker: &Waker) {
-        self.waker = Some(waker.clone());
+    /// Stores the waker of a join handle, replacing the waker it stored
+    /// before if it has a `key`. Returns the key to replace it with next time
+    ///
+    /// A handle is usually polled by the same task every time, so the stored
+    /// waker is only replaced when it wouldn't wake the same task
+    pub fn register_join_waker(&mut self, key: Option<usize>, waker: &Waker) -> usize {
+        self.state.set_join_waker();
+        match key {
+            Some(key) => {
+                let slot = &mut self.wakers[key];
+                match slot {
+                    Some(existing) if existing.will_wake(waker) => {}
+                    _ => *slot = Some(waker.clone()),
+                }
+                key
+            }
+            None => self.wakers.insert(Some(waker.clone())),
+        }
+    }
+
+    /// Removes the waker of a join handle that is no longer waiting
+    pub fn deregister_join_waker(&mut self, key: usize) {
+        self.wakers.remove(key);
+        if self.wakers.is_empty() {
+            self.state.unset_join_waker();
+        }
     }
 
-    pub fn wake_join_handle(&self) {
-        match &self.waker {
-            Some(waker) => waker.wake_by_ref(),
-            None => panic!("Missing waker!"),
+    /// Wakes the latest waker of every join handle. The keys stay taken
+    /// until the handles deregister them
+    pub fn wake_join_handle(&mut self) {
+        let wakers: Vec<_> = self
+            .wakers
+            .iter_mut()
+            .filter_map(|(_, waker)| waker.take())
+            .collect();
+        for waker in wakers {
+            waker.wake();
         }
     }
 }
 
 /// A monotonic counter that is updated through interior
 /// mutability. Allows it used as a static while still
-/// being able to be updated
+/// being able to be updated. Runtimes on different threads
+/// share it, so it is atomic
 #[derive(Default)]
-struct Counter(Cell<u64>);
+struct Counter(AtomicU64);
 
-#[derive(Clone, Copy)]
-pub(crate) struct TaskId(u64);
+/// Identifies a task. Ids are unique within the process and increase in
+/// the order tasks are spawned
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(u64);
 
 // ===== impl Counter =====
 
-// Implement sync for counter to enable it to be used as
-// a static. It is safe to do so because we aren't sharing
-// it across threads
-unsafe impl Sync for Counter {}
-
 impl Counter {
     const fn new() -> Counter {
-        Counter(Cell::new(0))
+        Counter(AtomicU64::new(0))
     }
     pub fn incr(&self) -> u64 {
-        let prev = self.0.get();
-        let new = prev + 1;
-        self.0.set(new);
-        new
+        self.0.fetch_add(1, Ordering::Relaxed) + 1
     }
 }
 
 // ===== impl TaskId =====
 
 impl TaskId {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         static ID: Counter = Counter::new();
         TaskId(ID.incr())
     }
@@ -1,22 +1,151 @@
+use std::cell::RefCell;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::ptr::NonNull;
+use std::rc::Rc;
 use std::task::{Context, Poll};
 
-use crate::task::header::Header;
+use crate::channel::oneshot;
+use crate::task::abort::AbortHandle;
+use crate::task::error::JoinError;
+use crate::task::header::{Header, TaskId};
 
 /// A handle to the task
+///
+/// Awaiting the handle returns the task's output. The output is kept in the
+/// task until then, even if the task completes before the handle is first
+/// polled. Awaiting it again afterwards returns [`JoinError::OutputTaken`].
+/// It doesn't panic, so a handle polled after it resolved, e.g. from a
+/// loop, fails in a way the caller can handle
 pub struct JoinHandle<T> {
     /// Pointer to raw task
     pub(crate) raw: NonNull<()>,
+    /// Key of the waker registered with the task, once polled
+    waker_key: Option<usize>,
+    /// Whether to abort the task if the task holding the handle is aborted
+    cascade: bool,
     pub(crate) _marker: PhantomData<T>,
 }
 
+/// A handle to a task that can be cloned, so that several tasks can await
+/// the same task. Created with [`JoinHandle::shared`]
+///
+/// The output is cloned for every handle. A join error can't be cloned, so
+/// it is shared behind an `Rc` instead
+pub struct SharedJoinHandle<T> {
+    inner: Rc<Shared<T>>,
+    /// Key of the waker this handle registered with the task, once polled
+    waker_key: Option<usize>,
+}
+
+struct Shared<T> {
+    handle: JoinHandle<T>,
+    /// The output of the task, once it has been taken out of the task
+    output: RefCell<Option<Result<T, Rc<JoinError>>>>,
+}
+
+/// Future returned by [`JoinHandle::map_err`]
+pub struct MapErr<T, F> {
+    handle: JoinHandle<T>,
+    f: Option<F>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(raw: NonNull<()>) -> JoinHandle<T> {
+        JoinHandle {
+            raw,
+            waker_key: None,
+            cascade: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Makes aborting the task that holds the handle abort this task too
+    pub(crate) fn cascade(mut self) -> JoinHandle<T> {
+        self.cascade = true;
+        self
+    }
+
+    /// The id of the task. It's the id passed to runtime hooks and recorded
+    /// in the task's tracing events
+    pub fn id(&self) -> TaskId {
+        let header = self.raw.as_ptr() as *const Header;
+        unsafe { (*header).id }
+    }
+
+    /// Lets the task run to completion without a handle. Its output is
+    /// dropped as soon as it completes
+    ///
+    /// This is dropping the handle, except that a handle from
+    /// [`spawn_cascading`](crate::task::spawn_cascading) no longer aborts
+    /// the task. The task is freed once nothing else refers to it, so a
+    /// task that is queued or being polled keeps running
+    pub fn detach(mut self) {
+        self.cascade = false;
+        drop(self)
+    }
+
+    /// Returns a handle that can abort the task, without being able to
+    /// await it
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle::new(self.raw)
+    }
+
+    /// Converts the handle into one that can be cloned, so that the task can
+    /// be awaited from several places
+    pub fn shared(self) -> SharedJoinHandle<T>
+    where
+        T: Clone,
+    {
+        SharedJoinHandle {
+            inner: Rc::new(Shared {
+                handle: self,
+                output: RefCell::new(None),
+            }),
+            waker_key: None,
+        }
+    }
+
+    /// Maps the [`JoinError`] of the task with `f`, so that awaiting it
+    /// returns your own error type and composes with `?`
+    pub fn map_err<E, F>(self, f: F) -> MapErr<T, F>
+    where
+        F: FnOnce(JoinError) -> E,
+    {
+        MapErr {
+            handle: self,
+            f: Some(f),
+        }
+    }
+}
+
+impl<T: 'static> JoinHandle<T> {
+    /// Converts the handle into a [`oneshot::Receiver`] that receives the
+    /// task's result. The result is kept until the receiver is awaited, even
+    /// if the task completes first
+    ///
+    /// This spawns a task that forwards the result, so it must be called
+    /// from within a runtime
+    ///
+    /// [`oneshot::Receiver`]: crate::channel::oneshot::Receiver
+    pub fn into_receiver(self) -> oneshot::Receiver<super::Result<T>> {
+        let (tx, rx) = oneshot::channel();
+        crate::spawn(async move {
+            // Nothing to do if the receiver is gone
+            let _ = tx.send(self.await);
+        });
+        rx
+    }
+}
+
+// The handle never pins the task's output, it only points to the task
+impl<T> Unpin for JoinHandle<T> {}
+
 impl<T> Future for JoinHandle<T> {
     type Output = super::Result<T>;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let raw = self.raw.as_ptr();
         let mut output = Poll::Pending;
 
@@ -32,11 +161,13 @@ impl<T> Future for JoinHandle<T> {
 
             if !header.state.is_complete() {
                 // Register waker with the task
-                header.register_waker(cx.waker());
-                header.state.set_join_waker();
+                self.waker_key = Some(header.register_join_waker(self.waker_key, cx.waker()));
             } else {
                 tracing::debug!("Task {}: JoinHandle ready", id);
-                (header.vtable.get_output)(self.raw.as_ptr(), &mut output as *mut _ as *mut ());
+                let ptr = self.raw.as_ptr();
+                if !(header.vtable.get_output)(ptr, &mut output as *mut _ as *mut ()) {
+                    output = Poll::Ready(Err(JoinError::OutputTaken));
+                }
             }
         }
 
@@ -44,6 +175,21 @@ impl<T> Future for JoinHandle<T> {
     }
 }
 
+impl<T, F> Unpin for MapErr<T, F> {}
+
+impl<T, E, F> Future for MapErr<T, F>
+where
+    F: FnOnce(JoinError) -> E,
+{
+    type Output = Result<T, E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let res = futures::ready!(Pin::new(&mut self.handle).poll(cx));
+        let f = self.f.take().expect("MapErr polled after completion");
+        Poll::Ready(res.map_err(f))
+    }
+}
+
 impl<T> Drop for JoinHandle<T> {
     fn drop(&mut self) {
         let raw = self.raw.as_ptr();
@@ -51,7 +197,278 @@ impl<T> Drop for JoinHandle<T> {
 
         unsafe {
             tracing::debug!("Task {}: Dropping JoinHandle", ((*header).id));
+            if let Some(key) = self.waker_key.take() {
+                (*header).deregister_join_waker(key);
+            }
+            // The task holding the handle was aborted
+            if self.cascade && super::abort::is_cancelling() {
+                ((*header).vtable.abort)(raw);
+            }
             ((*header).vtable.drop_join_handle)(self.raw.as_ptr())
         }
     }
 }
+
+// ===== impl SharedJoinHandle =====
+
+impl<T: Clone> Future for SharedJoinHandle<T> {
+    type Output = Result<T, Rc<JoinError>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(output) = &*self.inner.output.borrow() {
+            return Poll::Ready(output.clone());
+        }
+
+        let raw = self.inner.handle.raw.as_ptr();
+        unsafe {
+            let header = &mut *(raw as *mut Header);
+            if !header.state.is_complete() {
+                self.waker_key = Some(header.register_join_waker(self.waker_key, cx.waker()));
+                return Poll::Pending;
+            }
+
+            // The first handle to see the task complete takes the output
+            // out of the task for everyone
+            let mut output = Poll::Pending;
+            if !(header.vtable.get_output)(raw, &mut output as *mut _ as *mut ()) {
+                output = Poll::Ready(Err(JoinError::OutputTaken));
+            }
+            let output = match output {
+                Poll::Ready(output) => output.map_err(Rc::new),
+                Poll::Pending => unreachable!("output of a complete task"),
+            };
+            *self.inner.output.borrow_mut() = Some(output.clone());
+            Poll::Ready(output)
+        }
+    }
+}
+
+impl<T> Clone for SharedJoinHandle<T> {
+    fn clone(&self) -> Self {
+        SharedJoinHandle {
+            inner: self.inner.clone(),
+            waker_key: None,
+        }
+    }
+}
+
+impl<T> Drop for SharedJoinHandle<T> {
+    fn drop(&mut self) {
+        // Stop waiting on the task. The task itself is kept alive by the
+        // join handle until the last shared handle is dropped
+        if let Some(key) = self.waker_key.take() {
+            let header = self.inner.handle.raw.as_ptr() as *mut Header;
+            unsafe { (*header).deregister_join_waker(key) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn ids_increase_and_match_the_executor() {
+        use crate::runtime::Builder;
+
+        let polled = Rc::new(RefCell::new(Vec::new()));
+        let rt = {
+            let polled = polled.clone();
+            Builder::new()
+                .on_task_poll(move |id, _| polled.borrow_mut().push(id))
+                .build()
+                .unwrap()
+        };
+
+        let ids = rt.block_on(async {
+            let handles: Vec<_> = (0..5).map(|i| crate::spawn(async move { i })).collect();
+            let ids: Vec<_> = handles.iter().map(JoinHandle::id).collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            ids
+        });
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", ids);
+        assert_eq!(*polled.borrow(), ids);
+    }
+
+    #[test]
+    fn poll_after_output_taken() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let mut handle = crate::spawn(async { 5 });
+            assert_eq!((&mut handle).await.unwrap(), 5);
+
+            let res = (&mut handle).await;
+            assert!(matches!(res, Err(JoinError::OutputTaken)));
+        });
+    }
+
+    #[test]
+    fn output_kept_when_task_completes_before_poll() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let handle = crate::spawn(async { String::from("done") });
+            // Let the task run to completion before the handle is polled
+            crate::spawn(async {}).await.unwrap();
+
+            let header = handle.raw.as_ptr() as *const Header;
+            assert!(unsafe { (*header).state.is_complete() });
+            assert_eq!(handle.await.unwrap(), "done");
+        });
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn panicked_task_is_panic() {
+        let rt = Runtime::new();
+        let err = rt
+            .block_on(async { crate::spawn(async { panic!("Panicking!") }).await })
+            .unwrap_err();
+        assert!(err.is_panic());
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn map_err_into_custom_error() {
+        #[derive(Debug)]
+        enum MyError {
+            TaskFailed(JoinError),
+        }
+
+        async fn run() -> Result<u8, MyError> {
+            let value = crate::spawn(async { panic!("boom") })
+                .map_err(MyError::TaskFailed)
+                .await?;
+            Ok(value)
+        }
+
+        let rt = Runtime::new();
+        match rt.block_on(run()) {
+            Err(MyError::TaskFailed(e)) => assert!(e.is_panic()),
+            Ok(_) => panic!("task should have panicked"),
+        }
+    }
+
+    #[test]
+    fn into_receiver_after_completion() {
+        let rt = Runtime::new();
+        let res = rt.block_on(async {
+            let rx = crate::spawn(async { 9 }).into_receiver();
+            // Let the task and the forwarder run before awaiting
+            crate::spawn(async {}).await.unwrap();
+            rx.await
+        });
+        assert_eq!(res.unwrap().unwrap(), 9);
+    }
+
+    #[test]
+    fn only_join_handle_reference_left_after_completion() {
+        fn ref_count<T>(handle: &JoinHandle<T>) -> usize {
+            let header = handle.raw.as_ptr() as *const Header;
+            unsafe { (*header).state.ref_count() }
+        }
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let mut handle = crate::spawn(async { 1 });
+            assert_eq!(ref_count(&handle), 2);
+
+            assert_eq!((&mut handle).await.unwrap(), 1);
+            // Dropping the handle takes the count to zero and frees the task
+            assert_eq!(ref_count(&handle), 1);
+
+            // A stored waker keeps its own reference
+            let waker = std::rc::Rc::new(std::cell::RefCell::new(None));
+            let stash = waker.clone();
+            let mut handle = crate::spawn(futures::future::poll_fn(move |cx| {
+                *stash.borrow_mut() = Some(cx.waker().clone());
+                std::task::Poll::Ready(())
+            }));
+            (&mut handle).await.unwrap();
+            assert_eq!(ref_count(&handle), 2);
+            drop(waker.borrow_mut().take());
+            assert_eq!(ref_count(&handle), 1);
+        });
+    }
+
+    #[test]
+    fn latest_waker_woken_on_completion() {
+        use crate::test_util::counting_waker;
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = oneshot::channel();
+            let mut handle = crate::spawn(async move { rx.await.unwrap() });
+            crate::task::yield_now().await;
+
+            // E.g. a handle moved from one `select!` to another
+            let (old, old_waker) = counting_waker();
+            let (new, new_waker) = counting_waker();
+            for waker in [&old_waker, &new_waker, &new_waker] {
+                let cx = &mut Context::from_waker(waker);
+                assert!(Pin::new(&mut handle).poll(cx).is_pending());
+            }
+
+            tx.send(3).unwrap();
+            crate::task::yield_now().await;
+            assert_eq!(old.count(), 0);
+            assert_eq!(new.count(), 1);
+            assert_eq!(handle.await.unwrap(), 3);
+        });
+    }
+
+    #[test]
+    fn detached_task_runs_after_root_returns() {
+        use std::cell::Cell;
+
+        let count = Rc::new(Cell::new(0));
+        let (tx, rx) = oneshot::channel();
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let count = count.clone();
+            crate::spawn(async move {
+                rx.await.unwrap();
+                count.set(count.get() + 1);
+            })
+            .detach();
+        });
+        assert_eq!(count.get(), 0);
+
+        // Driven by the next call to `block_on`, without its handle
+        rt.block_on(async {
+            tx.send(()).unwrap();
+            while count.get() == 0 {
+                crate::task::yield_now().await;
+            }
+        });
+        assert_eq!(count.get(), 1);
+        // And freed, dropping its clone
+        assert_eq!(Rc::strong_count(&count), 1);
+    }
+
+    #[test]
+    fn shared_handle_awaited_by_two_tasks() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let shared = crate::spawn(async {
+                crate::time::sleep(std::time::Duration::from_millis(10)).await;
+                String::from("done")
+            })
+            .shared();
+
+            // A waiter that gives up before the task completes
+            let mut dropped = shared.clone();
+            assert!(futures::poll!(&mut dropped).is_pending());
+            drop(dropped);
+
+            let first = crate::spawn(shared.clone());
+            let second = crate::spawn(shared.clone());
+            assert_eq!(first.await.unwrap().unwrap(), "done");
+            assert_eq!(second.await.unwrap().unwrap(), "done");
+            assert_eq!(shared.await.unwrap(), "done");
+        });
+    }
+}
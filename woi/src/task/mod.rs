@@ -1,19 +1,57 @@
+mod abort;
+pub use abort::AbortHandle;
+
+pub(crate) mod blocking;
+pub use blocking::spawn_blocking;
+
+mod block_in_place;
+pub use block_in_place::block_in_place;
+
+mod cancellation_token;
+pub use cancellation_token::{CancellationToken, Cancelled};
+
+pub(crate) mod coop;
+
 mod error;
+pub use error::{JoinError, TrySpawnError};
 
 mod header;
+pub use header::TaskId;
 
 pub(crate) mod join;
-pub use join::JoinHandle;
+pub use join::{JoinHandle, MapErr, SharedJoinHandle};
 
 pub(crate) mod raw;
 
+mod poll_once;
+pub use poll_once::poll_once;
+
 mod result;
 pub(crate) use result::Result;
 
+mod slots;
+
 mod spawn;
-pub use spawn::spawn;
+pub(crate) use spawn::is_join_handle;
+pub use spawn::{
+    assert_not_join_handle, spawn, spawn_abortable, spawn_cascading, spawn_with, try_spawn,
+};
 
 mod state;
+pub(crate) use state::AtomicState;
 
 mod task;
 pub(crate) use task::Task;
+
+mod task_local;
+pub use task_local::LocalKey;
+
+mod wake_source;
+pub use wake_source::WakeSource;
+pub(crate) use wake_source::{wake, wake_by_ref};
+
+mod waker;
+pub use waker::waker;
+
+mod yield_now;
+pub use yield_now::{yield_now, YieldNow};
@@ -0,0 +1,50 @@
+use std::future::Future;
+use std::task::{Context, Poll};
+
+use futures::task::noop_waker_ref;
+
+/// Polls `future` exactly once with a waker that does nothing, and drops it
+///
+/// The future is pinned internally, so it doesn't need to be `Unpin`. This
+/// is meant for tests and benchmarks that check whether a future is ready
+/// straight away. A pending future is never woken, so it is dropped
+pub fn poll_once<F: Future>(future: F) -> Poll<F::Output> {
+    crate::pin!(future);
+    let cx = &mut Context::from_waker(noop_waker_ref());
+    future.poll(cx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+
+    // Pending on its first poll
+    struct PendingOnce(bool);
+
+    impl Future for PendingOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn poll_once_ready_and_pending() {
+        assert_eq!(poll_once(async { 5 }), Poll::Ready(5));
+        assert!(poll_once(PendingOnce(false)).is_pending());
+
+        // An async block awaiting across a suspension point is !Unpin
+        let future = async {
+            PendingOnce(false).await;
+            5
+        };
+        assert!(poll_once(future).is_pending());
+    }
+}
@@ -5,8 +5,12 @@ use std::pin::Pin;
 use std::ptr::NonNull;
 use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
 
+use slab::Slab;
+
+use super::coop::Budget;
 use super::error::JoinError;
 use super::header::{Header, TaskId};
+use super::slots;
 use super::state::State;
 use super::task::Task;
 
@@ -41,18 +45,30 @@ pub struct TaskLayout {
     layout: Layout,
     offset_schedule: usize,
     offset_status: usize,
+    /// Whether the task fits in a recycled slot. The offsets are the same
+    /// either way, since a slot is at least as large and aligned as `layout`
+    inline: bool,
 }
 
 pub struct TaskVTable {
     pub(crate) poll: unsafe fn(*const ()),
-    pub(crate) get_output: unsafe fn(*const (), *mut ()),
+    pub(crate) get_output: unsafe fn(*const (), *mut ()) -> bool,
     pub(crate) drop_join_handle: unsafe fn(*const ()),
+    /// Releases a reference held by a `Task`
+    pub(crate) drop_reference: unsafe fn(*const ()),
+    /// Takes out a reference for an `AbortHandle`
+    pub(crate) clone_reference: unsafe fn(*const ()),
+    /// Aborts the task
+    pub(crate) abort: unsafe fn(*const ()),
 }
 
 // All schedulers must implement the Schedule trait. They
 // are responsible for sending tasks to the runtime queue
 pub(crate) trait Schedule {
     fn schedule(&self, task: Task);
+
+    /// Called as the task is deallocated, so the scheduler can forget it
+    fn release(&self, _id: TaskId) {}
 }
 
 // ===== impl RawTask =====
@@ -71,10 +87,18 @@ where
         Self::drop_waker,
     );
 
+    // The layout only depends on `F` and `S`, so it is computed once, at
+    // compile time, for each task type
+    const TASK_LAYOUT: TaskLayout = Self::layout();
+
     pub fn new(future: F, scheduler: S) -> NonNull<()> {
-        let task_layout = Self::layout();
         unsafe {
-            let ptr = match NonNull::new(alloc::alloc(task_layout.layout) as *mut ()) {
+            let ptr = if Self::TASK_LAYOUT.inline {
+                slots::alloc()
+            } else {
+                alloc::alloc(Self::TASK_LAYOUT.layout)
+            };
+            let ptr = match NonNull::new(ptr as *mut ()) {
                 None => panic!("Could not allocate task!"),
                 Some(ptr) => ptr,
             };
@@ -85,11 +109,18 @@ where
             let header = Header {
                 id,
                 state: State::new_with_id(id),
-                waker: None,
+                wakers: Slab::new(),
+                locals: Default::default(),
+                budget: Budget::new(),
+                #[cfg(debug_assertions)]
+                wake_source: Default::default(),
                 vtable: &TaskVTable {
                     poll: Self::poll,
                     get_output: Self::get_output,
                     drop_join_handle: Self::drop_join_handle,
+                    drop_reference: Self::drop_waker,
+                    clone_reference: Self::clone_reference,
+                    abort: Self::abort,
                 },
             };
             (raw.header as *mut Header).write(header);
@@ -103,7 +134,7 @@ where
     }
 
     fn from_ptr(ptr: *const ()) -> Self {
-        let task_layout = Self::layout();
+        let task_layout = Self::TASK_LAYOUT;
         let ptr = ptr as *const u8;
         unsafe {
             Self {
@@ -116,24 +147,28 @@ where
 
     // Calculates the memory layout requirements and stores offsets into the
     // task to find the respective fields. The space that needs to be allocated
-    // is for: the future, the scheduling function and the task header
-    pub fn layout() -> TaskLayout {
+    // is for: the future, the scheduling function and the task header.
+    // Use `TASK_LAYOUT` instead of calling this directly
+    const fn layout() -> TaskLayout {
         let header_layout = Layout::new::<Header>();
         let schedule_layout = Layout::new::<S>();
         let stage_layout = Layout::new::<Status<F>>();
 
         let layout = header_layout;
-        let (layout, offset_schedule) = layout
-            .extend(schedule_layout)
-            .expect("Could not allocate task!");
-        let (layout, offset_status) = layout
-            .extend(stage_layout)
-            .expect("Could not allocate task!");
+        let (layout, offset_schedule) = match layout.extend(schedule_layout) {
+            Ok(extended) => extended,
+            Err(_) => panic!("Could not allocate task!"),
+        };
+        let (layout, offset_status) = match layout.extend(stage_layout) {
+            Ok(extended) => extended,
+            Err(_) => panic!("Could not allocate task!"),
+        };
 
         TaskLayout {
             layout,
             offset_schedule,
             offset_status,
+            inline: slots::fits(layout),
         }
     }
 
@@ -143,18 +178,68 @@ where
 
         tracing::debug!("Task {}: Deallocating", header.id);
 
-        let layout = Self::layout();
-        // TODO: Investigate if I need to use .drop_in_place()
-        alloc::dealloc(ptr as *mut u8, layout.layout);
+        // Catch use-after-free and double-free bugs in development. A task
+        // can be freed before completing, e.g. when it's dropped unrun, but
+        // never while something still points to it or it's being polled
+        debug_assert_eq!(
+            header.state.ref_count(),
+            0,
+            "Task {}: deallocated with references left",
+            header.id
+        );
+        debug_assert!(
+            !header.state.is_running(),
+            "Task {}: deallocated while being polled",
+            header.id
+        );
+
+        // Drop the future or output if it is still around, e.g. the task was
+        // discarded without running to completion. Dropping the status drops
+        // whichever of the two the variant holds. Ignore it if it panics
+        let _ = catch_unwind(std::panic::AssertUnwindSafe(|| {
+            std::ptr::drop_in_place(raw.status)
+        }));
+        // The scheduler and header own resources too, e.g. the runtime queue
+        // and any join wakers that were never woken
+        (*raw.scheduler).release(header.id);
+        std::ptr::drop_in_place(raw.scheduler as *mut S);
+        std::ptr::drop_in_place(raw.header as *mut Header);
+        if Self::TASK_LAYOUT.inline {
+            slots::free(ptr as *mut u8);
+        } else {
+            alloc::dealloc(ptr as *mut u8, Self::TASK_LAYOUT.layout);
+        }
     }
 
     // Makes a clone of the waker
     // Increments the number of references to the waker
     unsafe fn clone_waker(ptr: *const ()) -> RawWaker {
+        Self::clone_reference(ptr);
+        RawWaker::new(ptr, &Self::RAW_WAKER_VTABLE)
+    }
+
+    unsafe fn clone_reference(ptr: *const ()) {
         let raw = Self::from_ptr(ptr);
         let header = &mut *(raw.header as *mut Header);
         header.state.ref_incr();
-        RawWaker::new(ptr, &Self::RAW_WAKER_VTABLE)
+    }
+
+    // Marks the task as cancelled and schedules it, unless it's already
+    // queued or running, so its next poll drops the future. Aborting a
+    // complete task does nothing
+    unsafe fn abort(ptr: *const ()) {
+        let raw = Self::from_ptr(ptr);
+        let header = &mut *(raw.header as *mut Header);
+        if header.state.is_complete() || header.state.is_cancelled() {
+            return;
+        }
+
+        tracing::debug!("Task {}: Aborting", header.id);
+        header.state.set_cancelled();
+        if Self::transition_to_notified(ptr) {
+            header.state.ref_incr();
+            Self::schedule(ptr);
+        }
     }
 
     // This is responsible for decrementing a reference count and ensuring
@@ -174,36 +259,67 @@ where
     unsafe fn wake(ptr: *const ()) {
         let raw = Self::from_ptr(ptr);
         let header = &mut *(raw.header as *mut Header);
-        tracing::debug!("Task {}: Waking raw task", header.id);
-
-        header.state.transition_to_scheduled();
-        // We get one reference count from the caller. We schedule a task which
-        // increases our reference count by one.
-        Self::schedule(ptr);
-        // We can now drop our reference from the caller
-        Self::drop_waker(ptr);
+        tracing::debug!(task = %header.id, "Task {}: Waking raw task", header.id);
+        #[cfg(debug_assertions)]
+        {
+            header.wake_source = super::wake_source::current();
+        }
+
+        // We get one reference count from the caller. If the task needs to be
+        // scheduled, that reference is handed over to the queued task.
+        // Otherwise we drop it
+        if Self::transition_to_notified(ptr) {
+            Self::schedule(ptr);
+        } else {
+            Self::drop_waker(ptr);
+        }
     }
 
     unsafe fn wake_by_ref(ptr: *const ()) {
         let raw = Self::from_ptr(ptr);
         let header = &mut *(raw.header as *mut Header);
-        tracing::debug!("Task {}: Waking raw task by ref", header.id);
+        tracing::debug!(task = %header.id, "Task {}: Waking raw task by ref", header.id);
+        #[cfg(debug_assertions)]
+        {
+            header.wake_source = super::wake_source::current();
+        }
 
-        header.state.transition_to_scheduled();
-        Self::schedule(ptr);
+        // The caller keeps its reference, so the queued task needs its own
+        if Self::transition_to_notified(ptr) {
+            header.state.ref_incr();
+            Self::schedule(ptr);
+        }
     }
 
-    unsafe fn schedule(ptr: *const ()) {
+    // Marks the task as woken. Returns true if the task should be put on the
+    // queue, which is when it isn't already queued, complete or being polled.
+    // A task woken while it is being polled is only marked as notified;
+    // `poll` re-enqueues it once the future returns
+    unsafe fn transition_to_notified(ptr: *const ()) -> bool {
         let raw = Self::from_ptr(ptr);
         let header = &mut *(raw.header as *mut Header);
 
+        if header.state.is_running() {
+            header.state.set_notified();
+            false
+        } else if !header.state.is_scheduled() && !header.state.is_complete() {
+            header.state.transition_to_scheduled();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Hands the task to the scheduler. The caller must provide the reference
+    // held by the queued task
+    unsafe fn schedule(ptr: *const ()) {
+        let raw = Self::from_ptr(ptr);
+        let id = (*raw.header).id;
+        tracing::debug!(task = %id, "Task {}: Scheduling", id);
+
         let task = Task {
             raw: NonNull::new_unchecked(ptr as *mut ()),
         };
-        // When we create a new task, we need to increment its reference
-        // count since we now have another 'thing' holding a reference
-        // to the raw task
-        header.state.ref_incr();
 
         let scheduler = &*raw.scheduler;
         scheduler.schedule(task)
@@ -211,18 +327,50 @@ where
 
     // Runs the future and updates its state
     unsafe fn poll(ptr: *const ()) {
-        use std::panic;
+        use std::panic::AssertUnwindSafe;
 
         let raw = Self::from_ptr(ptr);
         let header = &mut *(raw.header as *mut Header);
 
+        // Events emitted while the task is polled, by the task and by the
+        // runtime, carry the id of the task through the span
+        #[cfg(debug_assertions)]
+        let _span = tracing::debug_span!(
+            "poll",
+            task = %header.id,
+            wake_source = ?header.wake_source
+        )
+        .entered();
+        #[cfg(not(debug_assertions))]
+        let _span = tracing::debug_span!("poll", task = %header.id).entered();
+
         let waker = Waker::from_raw(RawWaker::new(ptr, &Self::RAW_WAKER_VTABLE));
         let cx = &mut Context::from_waker(&waker);
+        // Makes the waker available through `task::waker()` during this poll
+        let _enter = super::waker::enter(&waker);
+        // And the task's task-local values to its keys
+        let _locals = super::task_local::enter(&header.locals);
+        // And its budget to the resources it uses
+        let _budget = super::coop::enter(&header.budget);
 
         header.state.transition_to_running();
 
         let status = &mut *raw.status;
-        match Self::poll_inner(status, cx) {
+        let res = if header.state.is_cancelled() {
+            Self::cancel(status)
+        } else {
+            Self::poll_inner(status, cx)
+        };
+        match res {
+            Poll::Pending if header.state.is_notified() => {
+                // The task was woken while it was being polled
+                tracing::debug!("Task pending, woken during poll");
+                header.state.transition_to_scheduled();
+                // The poll's reference is dropped along with its waker, so the
+                // queued task needs its own
+                header.state.ref_incr();
+                Self::schedule(ptr);
+            }
             Poll::Pending => {
                 tracing::debug!("Task pending");
                 header.state.transition_to_idle();
@@ -232,20 +380,37 @@ where
                 // Catch a panic if waking the JoinHandle or dropping the future
                 // panics. Since the task is already completed, we're not concerned
                 // about propagating the failure up to the caller
-                let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                let _ = catch_unwind(AssertUnwindSafe(|| {
                     if header.state.has_join_waker() {
                         header.wake_join_handle();
-                    } else {
-                        // Drop the future or output by replacing it with Consumed
+                    } else if !header.state.has_join_handle() {
+                        // No one can retrieve the output. Drop it by replacing
+                        // it with Consumed
                         status.drop_future_or_output();
                     }
+                    // Otherwise the handle hasn't been polled yet. The output
+                    // is kept for when it is
                 }));
             }
         }
     }
 
+    // Drops the future of an aborted task in place of polling it. The task
+    // completes with a cancelled error
+    fn cancel(status: &mut Status<F>) -> Poll<()> {
+        use std::panic::AssertUnwindSafe;
+
+        // Ignore it if dropping the future panics. Either way there's nothing
+        // left to drop, so the error is written without dropping the status
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            super::abort::cancelling(|| status.drop_future_or_output())
+        }));
+        unsafe { std::ptr::write(status, Status::Finished(Err(JoinError::Cancelled))) };
+        Poll::Ready(())
+    }
+
     fn poll_inner(status: &mut Status<F>, cx: &mut Context) -> Poll<()> {
-        use std::panic;
+        use std::panic::AssertUnwindSafe;
 
         struct Guard<'a, F: Future> {
             status: &'a mut Status<F>,
@@ -255,12 +420,12 @@ where
             fn drop(&mut self) {
                 // If polling the future panics, we want to drop the future/output
                 // If dropping the future/output panics, we've wrapped the entire method in
-                // a panic::catch_unwind so we can return a JoinError
+                // a catch_unwind so we can return a JoinError
                 self.status.drop_future_or_output()
             }
         }
 
-        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let res = catch_unwind(AssertUnwindSafe(|| {
             let guard = Guard { status };
             let res = guard.status.poll(cx);
             // Successfully polled the future. Prevent the guard's destructor from running
@@ -275,23 +440,30 @@ where
         };
 
         // Store output in task. Ignore if the future panics on drop
-        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let _ = catch_unwind(AssertUnwindSafe(|| {
             *status = Status::Finished(output);
         }));
 
         Poll::Ready(())
     }
 
-    unsafe fn get_output(ptr: *const (), dst: *mut ()) {
+    // Moves the output of the task into `dst`. If the output has already
+    // been taken, `dst` is left untouched and false is returned
+    unsafe fn get_output(ptr: *const (), dst: *mut ()) -> bool {
         let raw = Self::from_ptr(ptr);
         let dst = dst as *mut Poll<super::Result<F::Output>>;
-        // TODO: Improve error handling
+
+        match &*raw.status {
+            Status::Finished(_) => {}
+            Status::Consumed => return false,
+            Status::Running(_) => panic!("Could not retrieve output!"),
+        }
+
         match mem::replace(&mut *raw.status, Status::Consumed) {
-            Status::Finished(output) => {
-                *dst = Poll::Ready(output);
-            }
-            _ => panic!("Could not retrieve output!"),
+            Status::Finished(output) => *dst = Poll::Ready(output),
+            _ => unreachable!("unexpected status"),
         }
+        true
     }
 
     unsafe fn drop_join_handle(ptr: *const ()) {
@@ -300,6 +472,12 @@ where
 
         // unset join handle bit
         header.state.unset_join_handle();
+        // The output will never be retrieved, so drop it now
+        if header.state.is_complete() {
+            let _ = catch_unwind(std::panic::AssertUnwindSafe(|| {
+                (*raw.status).drop_future_or_output()
+            }));
+        }
         // drop the reference the handle was holding, possibly
         // deallocating the task
         header.state.ref_decr();
@@ -309,6 +487,21 @@ where
     }
 }
 
+// ===== Panic handling =====
+
+// Runs `f`, catching the panic if it panics. When built with
+// `panic = "abort"` a panic aborts the process before it can be
+// caught, so `f` is just called
+#[cfg(panic = "unwind")]
+fn catch_unwind<F: FnOnce() -> R + std::panic::UnwindSafe, R>(f: F) -> std::thread::Result<R> {
+    std::panic::catch_unwind(f)
+}
+
+#[cfg(not(panic = "unwind"))]
+fn catch_unwind<F: FnOnce() -> R + std::panic::UnwindSafe, R>(f: F) -> std::thread::Result<R> {
+    Ok(f())
+}
+
 // ====== impl Status =====
 
 impl<F: Future> Status<F> {
@@ -328,3 +521,296 @@ impl<F: Future> Status<F> {
         *self = Status::Consumed
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoopScheduler;
+
+    impl Schedule for NoopScheduler {
+        fn schedule(&self, _task: Task) {}
+    }
+
+    impl Schedule for std::rc::Rc<NoopScheduler> {
+        fn schedule(&self, _task: Task) {}
+    }
+
+    fn check_layout<F: Future, S: Schedule>() {
+        let cached = RawTask::<F, S>::TASK_LAYOUT;
+
+        let (layout, offset_schedule) = Layout::new::<Header>().extend(Layout::new::<S>()).unwrap();
+        let (layout, offset_status) = layout.extend(Layout::new::<Status<F>>()).unwrap();
+
+        assert_eq!(cached.layout, layout);
+        assert_eq!(cached.offset_schedule, offset_schedule);
+        assert_eq!(cached.offset_status, offset_status);
+        assert_eq!(cached.inline, super::slots::fits(layout));
+    }
+
+    #[test]
+    fn cached_layout_matches_computed() {
+        check_layout::<std::future::Ready<u8>, NoopScheduler>();
+        check_layout::<std::future::Ready<[u64; 32]>, NoopScheduler>();
+        check_layout::<std::future::Pending<()>, std::rc::Rc<NoopScheduler>>();
+    }
+
+    #[test]
+    fn over_aligned_output_of_large_future() {
+        use crate::Runtime;
+
+        #[repr(align(64))]
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Aligned([u8; 64]);
+
+        // Far larger than its output, which is more strictly aligned
+        struct LargeState {
+            state: [u8; 4096],
+        }
+
+        impl Future for LargeState {
+            type Output = Aligned;
+
+            fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Aligned> {
+                let mut bytes = [0; 64];
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte = self.state[i * 64] ^ i as u8;
+                }
+                Poll::Ready(Aligned(bytes))
+            }
+        }
+
+        // The status is laid out for whichever variant is larger, and aligned
+        // for whichever is stricter, so both fit at the same offset
+        check_layout::<LargeState, NoopScheduler>();
+        let layout = RawTask::<LargeState, NoopScheduler>::TASK_LAYOUT;
+        assert!(layout.layout.align() >= 64);
+        assert_eq!(layout.offset_status % 64, 0);
+        assert!(layout.layout.size() >= layout.offset_status + 4096);
+        assert!(!layout.inline);
+
+        let mut state = [0; 4096];
+        for (i, byte) in state.iter_mut().enumerate() {
+            *byte = (i / 64 * 3) as u8;
+        }
+        let mut expected = [0; 64];
+        for (i, byte) in expected.iter_mut().enumerate() {
+            *byte = (i * 3) as u8 ^ i as u8;
+        }
+
+        let rt = Runtime::new();
+        let output = rt.block_on(async {
+            let handle = crate::spawn(LargeState { state });
+            // Completes before the handle is polled, so the output sits in
+            // the task in place of the future
+            crate::task::yield_now().await;
+            handle.await.unwrap()
+        });
+        assert_eq!(output, Aligned(expected));
+    }
+
+    #[test]
+    fn dropping_unrun_task_drops_future() {
+        use crate::test_util::DropFlag;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let spawn = |dropped: &Rc<Cell<bool>>| {
+            let flag = DropFlag(dropped.clone());
+            let raw = RawTask::new(
+                async move {
+                    let _flag = flag;
+                },
+                NoopScheduler,
+            );
+            (Task { raw }, super::super::JoinHandle::<()>::new(raw))
+        };
+
+        // Shutdown drops the queued task after the handle is gone
+        let dropped = Rc::new(Cell::new(false));
+        let (task, handle) = spawn(&dropped);
+        drop(handle);
+        assert!(!dropped.get());
+        drop(task);
+        assert!(dropped.get());
+
+        // Or before it
+        let dropped = Rc::new(Cell::new(false));
+        let (task, handle) = spawn(&dropped);
+        drop(task);
+        assert!(!dropped.get());
+        drop(handle);
+        assert!(dropped.get());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "deallocated with references left")]
+    fn dealloc_with_references_left_panics() {
+        let raw = RawTask::new(std::future::ready(()), NoopScheduler);
+        // Frees the task while the task and join handle references are
+        // still around. Neither is created, so nothing uses it afterwards
+        unsafe { RawTask::<std::future::Ready<()>, NoopScheduler>::dealloc(raw.as_ptr()) };
+    }
+
+    #[test]
+    fn dropping_handle_of_pending_task_drops_future_and_scheduler() {
+        use crate::test_util::DropFlag;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let dropped = Rc::new(Cell::new(false));
+        let flag = DropFlag(dropped.clone());
+        let scheduler = Rc::new(NoopScheduler);
+        let raw = RawTask::new(
+            async move {
+                let _flag = flag;
+                futures::future::pending::<()>().await
+            },
+            scheduler.clone(),
+        );
+        let handle = super::super::JoinHandle::<()>::new(raw);
+
+        // Polled once and left pending, without holding on to its waker
+        Task { raw }.run();
+        assert!(!dropped.get());
+        assert_eq!(Rc::strong_count(&scheduler), 2);
+
+        drop(handle);
+        assert!(dropped.get());
+        assert_eq!(Rc::strong_count(&scheduler), 1);
+    }
+
+    #[test]
+    fn self_wake_with_cloned_waker_releases_references() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use std::task::Waker;
+
+        let stashed: Rc<RefCell<Option<Waker>>> = Rc::new(RefCell::new(None));
+        let stash = stashed.clone();
+        let mut polls = 0;
+        let future = futures::future::poll_fn(move |cx| {
+            polls += 1;
+            match polls {
+                // Wakes itself while being polled, consuming a clone
+                1 => {
+                    let waker = cx.waker().clone();
+                    waker.wake();
+                    Poll::Pending
+                }
+                2 => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+                // Wakes itself later, from a clone stored elsewhere
+                3 => {
+                    *stash.borrow_mut() = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+                _ => Poll::Ready(()),
+            }
+        });
+
+        let rt = crate::Runtime::new();
+        rt.block_on(async {
+            let mut handle = crate::spawn(future);
+            // Let the task run its first three polls
+            crate::spawn(async {}).await.unwrap();
+            stashed.borrow_mut().take().unwrap().wake();
+            (&mut handle).await.unwrap();
+
+            // Only the join handle's reference is left
+            let header = handle.raw.as_ptr() as *const Header;
+            assert_eq!(unsafe { (*header).state.ref_count() }, 1);
+        });
+    }
+
+    #[test]
+    fn self_wake_during_poll_repolls() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        // Wakes itself (twice) and returns pending on its first poll
+        struct SelfWake {
+            polls: Rc<Cell<usize>>,
+        }
+
+        impl Future for SelfWake {
+            type Output = ();
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+                self.polls.set(self.polls.get() + 1);
+                if self.polls.get() == 1 {
+                    cx.waker().wake_by_ref();
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            }
+        }
+
+        let polls = Rc::new(Cell::new(0));
+        let rt = crate::Runtime::new();
+        let future = SelfWake {
+            polls: polls.clone(),
+        };
+        rt.block_on(async { crate::spawn(future).await }).unwrap();
+        // Polled again once, not once per wake
+        assert_eq!(polls.get(), 2);
+    }
+
+    #[test]
+    fn wakes_while_queued_enqueue_once() {
+        use std::cell::{Cell, RefCell};
+        use std::rc::Rc;
+
+        let polls = Rc::new(Cell::new(0));
+        let stored = Rc::new(RefCell::new(None));
+        let rt = crate::Runtime::new();
+        rt.block_on(async {
+            let mut handle = {
+                let polls = polls.clone();
+                let stored = stored.clone();
+                crate::spawn(futures::future::poll_fn(move |cx| {
+                    polls.set(polls.get() + 1);
+                    if polls.get() == 1 {
+                        *stored.borrow_mut() = Some(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(())
+                }))
+            };
+
+            // Lets the task be polled once
+            let mut yielded = false;
+            futures::future::poll_fn(|cx| {
+                if yielded {
+                    return Poll::Ready(());
+                }
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+            .await;
+            assert_eq!(polls.get(), 1);
+
+            // Woken twice before the runtime gets to it, once by value
+            let waker: Waker = stored.borrow_mut().take().unwrap();
+            let by_value = waker.clone();
+            waker.wake_by_ref();
+            by_value.wake();
+            let header = handle.raw.as_ptr() as *const Header;
+            // The queued task, the stored waker and the handle
+            assert_eq!(unsafe { (*header).state.ref_count() }, 3);
+
+            (&mut handle).await.unwrap();
+            assert_eq!(polls.get(), 2);
+            // Waking a complete task doesn't queue it, so only the handle's
+            // reference is left
+            waker.wake();
+            assert_eq!(unsafe { (*header).state.ref_count() }, 1);
+        });
+    }
+}
@@ -0,0 +1,115 @@
+use std::alloc::{self, Layout};
+use std::cell::RefCell;
+use std::ptr::NonNull;
+
+/// The size, in bytes, of a slot. Tasks whose layout fits in a slot are
+/// allocated from the recycled slots instead of individually
+pub(crate) const SLOT_SIZE: usize = 256;
+
+/// The alignment of a slot
+pub(crate) const SLOT_ALIGN: usize = 16;
+
+/// The most freed slots kept around for reuse. Beyond that, freed slots
+/// are returned to the allocator
+const MAX_CACHED: usize = 1024;
+
+const SLOT_LAYOUT: Layout = match Layout::from_size_align(SLOT_SIZE, SLOT_ALIGN) {
+    Ok(layout) => layout,
+    Err(_) => panic!("invalid slot layout"),
+};
+
+thread_local! {
+    static CACHE: RefCell<Cache> = const { RefCell::new(Cache { slots: Vec::new() }) };
+}
+
+/// Freed slots, waiting to be used by the next small task
+struct Cache {
+    slots: Vec<NonNull<u8>>,
+}
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        for slot in self.slots.drain(..) {
+            unsafe { alloc::dealloc(slot.as_ptr(), SLOT_LAYOUT) }
+        }
+    }
+}
+
+/// Returns true if memory of `layout` can be stored in a slot
+pub(crate) const fn fits(layout: Layout) -> bool {
+    layout.size() <= SLOT_SIZE && layout.align() <= SLOT_ALIGN
+}
+
+/// Takes a freed slot, or allocates a new one if there are none
+///
+/// Every slot has the same layout, so whichever way a slot was allocated,
+/// it can be freed with [`free`]
+pub(crate) fn alloc() -> *mut u8 {
+    let cached = CACHE
+        .try_with(|cache| cache.borrow_mut().slots.pop())
+        .ok()
+        .flatten();
+    match cached {
+        Some(slot) => slot.as_ptr(),
+        None => unsafe { alloc::alloc(SLOT_LAYOUT) },
+    }
+}
+
+/// Frees a slot returned by [`alloc`], keeping it for reuse if there's room
+pub(crate) unsafe fn free(ptr: *mut u8) {
+    let slot = NonNull::new_unchecked(ptr);
+    let cached = CACHE
+        .try_with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if cache.slots.len() < MAX_CACHED {
+                cache.slots.push(slot);
+                true
+            } else {
+                false
+            }
+        })
+        .unwrap_or(false);
+
+    // The cache is full, or is being torn down along with the thread
+    if !cached {
+        alloc::dealloc(ptr, SLOT_LAYOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    // The number of freed slots waiting to be reused
+    fn cached() -> usize {
+        CACHE.with(|cache| cache.borrow().slots.len())
+    }
+
+    #[test]
+    fn small_tasks_reuse_slots_and_large_tasks_use_heap() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let before = cached();
+            let handles: Vec<_> = (0..10).map(|i| crate::spawn(async move { i })).collect();
+            // Taken from the cache first
+            assert_eq!(cached(), before.saturating_sub(10));
+            for (i, handle) in handles.into_iter().enumerate() {
+                assert_eq!(handle.await.unwrap(), i);
+            }
+            // Every slot was given back once its task was freed
+            let after = cached();
+            assert_eq!(after, before.max(10));
+
+            let big = [1u8; SLOT_SIZE * 4];
+            let large = crate::spawn(async move { big.iter().map(|&b| b as usize).sum::<usize>() });
+            assert_eq!(cached(), after);
+            assert_eq!(large.await.unwrap(), SLOT_SIZE * 4);
+            assert_eq!(cached(), after);
+
+            // And reused straight away
+            assert_eq!(crate::spawn(async { 5 }).await.unwrap(), 5);
+            assert_eq!(cached(), after);
+        });
+    }
+}
@@ -1,9 +1,283 @@
 use std::future::Future;
 
 use crate::runtime;
+use crate::task::abort::AbortHandle;
+use crate::task::error::TrySpawnError;
 use crate::task::join::JoinHandle;
 
-pub fn spawn<F: Future>(future: F) -> JoinHandle<F::Output> {
+/// Spawns a task onto the current runtime
+///
+/// Tasks are run in the order they are scheduled. Spawned tasks and woken
+/// tasks go to the back of the same FIFO queue, so a task spawned before
+/// another task is woken is polled first, and vice versa
+///
+/// The task keeps running if the task awaiting its handle is aborted. Use
+/// [`spawn_cascading`] to abort it along with that task
+///
+/// Panics if called outside of a runtime, i.e. not from within
+/// [`Runtime::block_on`](crate::Runtime::block_on)
+///
+/// The future is moved into the task's allocation, which stays put until
+/// the task is freed, so it is pinned there and doesn't have to be `Unpin`.
+/// The handle only points at the task, so moving it doesn't move the future
+pub fn spawn<F: Future + 'static>(future: F) -> JoinHandle<F::Output> {
     let spawner = runtime::context::spawner();
     spawner.spawn(future)
 }
+
+/// Panics if `future` is a [`JoinHandle`]
+///
+/// Spawning a handle, as in `spawn(spawn(fut))`, runs a second task that
+/// only waits on the first, which is almost never what was meant. Debug
+/// builds warn when it happens. Call this in tests, or before spawning
+/// futures of a generic type, to turn the mistake into a panic
+///
+/// ```should_panic
+/// let rt = woi::Runtime::new();
+/// rt.block_on(async {
+///     let handle = woi::spawn(async {});
+///     woi::task::assert_not_join_handle(&handle);
+/// });
+/// ```
+pub fn assert_not_join_handle<F: Future>(future: &F) {
+    let _ = future;
+    assert!(
+        !is_join_handle::<F>(),
+        "expected a future, got a JoinHandle. Await the handle instead of spawning it"
+    );
+}
+
+/// Returns true if `F` is a [`JoinHandle`], whatever its output
+pub(crate) fn is_join_handle<F>() -> bool {
+    let handle = std::any::type_name::<JoinHandle<()>>();
+    // The path of the handle, without its type parameter
+    let path = &handle[..handle.len() - "<()>".len()];
+    std::any::type_name::<F>()
+        .strip_prefix(path)
+        .is_some_and(|rest| rest.starts_with('<'))
+}
+
+/// Spawns a task onto the current runtime, unless its run queue is full
+///
+/// Runtimes built with a [`queue_limit`] hand the future back in
+/// [`TrySpawnError::Full`] when that many tasks are already queued, without
+/// allocating a task. Otherwise this is the same as [`spawn`], which ignores
+/// the limit
+///
+/// [`queue_limit`]: crate::runtime::Builder::queue_limit
+pub fn try_spawn<F: Future + 'static>(
+    future: F,
+) -> Result<JoinHandle<F::Output>, TrySpawnError<F>> {
+    let spawner = runtime::context::spawner();
+    spawner.try_spawn(future)
+}
+
+/// Spawns a task that is aborted if the task holding its handle is aborted
+///
+/// Cancellation cascades from a task to the tasks it spawned this way, as
+/// long as it still holds their handles when it's aborted. Dropping the
+/// handle any other way leaves the task running, as with [`spawn`]
+pub fn spawn_cascading<F: Future + 'static>(future: F) -> JoinHandle<F::Output> {
+    spawn(future).cascade()
+}
+
+/// Spawns a task, returning its handle along with a handle that aborts it
+///
+/// This is [`spawn`] followed by [`JoinHandle::abort_handle`], for tasks
+/// that are going to be cancelled from somewhere other than where they're
+/// awaited
+pub fn spawn_abortable<F: Future + 'static>(future: F) -> (JoinHandle<F::Output>, AbortHandle) {
+    let handle = spawn(future);
+    let abort = handle.abort_handle();
+    (handle, abort)
+}
+
+/// Spawns the future returned by `f`, calling `f` when the task is first
+/// polled rather than when it is spawned
+///
+/// The runtime is single-threaded, so neither `f` nor its future need to be
+/// `Send`. Setup that should only happen once the task actually runs can go
+/// in the closure
+pub fn spawn_with<F, Fut>(f: F) -> JoinHandle<Fut::Output>
+where
+    F: FnOnce() -> Fut + 'static,
+    Fut: Future,
+{
+    spawn(async move { f().await })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    #[test]
+    fn root_sums_children() {
+        let rt = Runtime::new();
+        let sum = rt.block_on(async {
+            let a = spawn(async { 2 });
+            let b = spawn(async { 3 });
+            a.await.unwrap() + b.await.unwrap()
+        });
+        assert_eq!(sum, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "No runtime running")]
+    fn spawn_outside_runtime_panics() {
+        spawn(async {});
+    }
+
+    #[test]
+    fn join_handles_are_detected() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let handle = spawn(async { 1 });
+            assert!(is_join_handle::<JoinHandle<u8>>());
+            assert!(!is_join_handle::<Rc<JoinHandle<u8>>>());
+            assert!(!is_join_handle::<
+                crate::task::MapErr<u8, fn(crate::JoinError)>,
+            >());
+
+            let future = async { 2 };
+            assert_not_join_handle(&future);
+            assert_eq!(future.await, 2);
+
+            let panicked = std::panic::catch_unwind(|| assert_not_join_handle(&handle));
+            assert!(panicked.is_err());
+            assert_eq!(handle.await.unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn aborting_parent_aborts_cascading_child() {
+        use crate::task::AbortHandle;
+        use std::cell::RefCell;
+
+        fn spawn_parent(cascade: bool) -> (AbortHandle, Rc<RefCell<Option<AbortHandle>>>) {
+            let child = Rc::new(RefCell::new(None));
+            let slot = child.clone();
+            let parent = spawn(async move {
+                let pending = futures::future::pending::<()>();
+                let handle = if cascade {
+                    spawn_cascading(pending)
+                } else {
+                    spawn(pending)
+                };
+                *slot.borrow_mut() = Some(handle.abort_handle());
+                handle.await
+            });
+            (parent.abort_handle(), child)
+        }
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            for cascade in [true, false] {
+                let (parent, child) = spawn_parent(cascade);
+                // Let the parent spawn the child and wait on it
+                crate::task::yield_now().await;
+                parent.abort();
+                crate::task::yield_now().await;
+                crate::task::yield_now().await;
+
+                assert!(parent.is_finished());
+                let child = child.borrow_mut().take().unwrap();
+                assert_eq!(child.is_finished(), cascade);
+                child.abort();
+            }
+        });
+    }
+
+    #[test]
+    fn spawn_abortable_returns_handle_that_aborts() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (handle, abort) = spawn_abortable(futures::future::pending::<()>());
+            crate::task::yield_now().await;
+            abort.abort();
+            assert!(handle.await.unwrap_err().is_cancelled());
+        });
+    }
+
+    #[test]
+    fn spawn_with_builds_future_on_first_poll() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            // Rc is !Send and is only used while building the future
+            let built = Rc::new(Cell::new(false));
+            let flag = built.clone();
+            let handle = spawn_with(move || {
+                flag.set(true);
+                async { 7 }
+            });
+            assert!(!built.get());
+
+            assert_eq!(handle.await.unwrap(), 7);
+            assert!(built.get());
+        });
+    }
+
+    #[test]
+    fn self_referential_future_stays_pinned() {
+        use std::marker::PhantomPinned;
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Waker};
+
+        // Holds a pointer into its own buffer across polls, like an async fn
+        // holding a borrow of one of its locals across an await
+        struct SelfRef {
+            buf: [u8; 8],
+            ptr: *const u8,
+            addrs: Rc<RefCell<Vec<usize>>>,
+            waker: Rc<RefCell<Option<Waker>>>,
+            _pinned: PhantomPinned,
+        }
+
+        impl Future for SelfRef {
+            type Output = u8;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u8> {
+                // Safety: nothing is moved out of the future
+                let this = unsafe { self.get_unchecked_mut() };
+                let addr = this as *mut SelfRef as usize;
+                this.addrs.borrow_mut().push(addr);
+                if this.ptr.is_null() {
+                    this.buf[3] = 42;
+                    this.ptr = &this.buf[3];
+                    *this.waker.borrow_mut() = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                assert_eq!(this.ptr, &this.buf[3] as *const u8);
+                // Safety: the pointer is into the buffer, which hasn't moved
+                Poll::Ready(unsafe { *this.ptr })
+            }
+        }
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let addrs = Rc::new(RefCell::new(Vec::new()));
+            let waker = Rc::new(RefCell::new(None));
+            let handle = spawn(SelfRef {
+                buf: [0; 8],
+                ptr: std::ptr::null(),
+                addrs: addrs.clone(),
+                waker: waker.clone(),
+                _pinned: PhantomPinned,
+            });
+            crate::task::yield_now().await;
+            assert_eq!(addrs.borrow().len(), 1);
+
+            // The future lives in the task, so moving its handle leaves the
+            // future where it is
+            let handle = Box::new(handle);
+            waker.borrow_mut().take().unwrap().wake();
+            assert_eq!(handle.await.unwrap(), 42);
+
+            let addrs = addrs.borrow();
+            assert_eq!(addrs.len(), 2);
+            assert_eq!(addrs[0], addrs[1]);
+        });
+    }
+}
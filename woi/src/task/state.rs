@@ -1,3 +1,5 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use super::header::TaskId;
 
 // The task has been scheduled onto the executor
@@ -15,9 +17,18 @@ const JOIN_HANDLE: usize = 1 << 3;
 // The waker belonging to the join handle is registered
 const JOIN_WAKER: usize = 1 << 4;
 
+// The task has been aborted
+const CANCELLED: usize = 1 << 5;
+
+// The task was woken while it was being run. It's queued again once the
+// poll returns, rather than by the wake. Kept apart from SCHEDULED, which
+// is only set while the task is on the queue
+const NOTIFIED: usize = 1 << 6;
+
 // The idea of using a state mask and ref count mask and figuring
 // out how much to shift is from Tokio
-const STATE_MASK: usize = SCHEDULED | RUNNING | COMPLETE | JOIN_HANDLE | JOIN_WAKER;
+const STATE_MASK: usize =
+    SCHEDULED | RUNNING | COMPLETE | JOIN_HANDLE | JOIN_WAKER | CANCELLED | NOTIFIED;
 
 // The bits belonging to the ref count. These are the upper bits.
 // It is calculated by inverting the bits belonging to the
@@ -38,6 +49,11 @@ const REF_ONE: usize = 1 << REF_COUNT_SHIFT;
 // The task has an initial reference count of two
 //   * The JoinHandle
 //   * The internal Task
+// Spawning hands the Task straight to the scheduler without going
+// through `RawTask::schedule`, so both references are set up by this
+// single store and spawning needs no increments. The Task's reference
+// is taken over by the waker built in `RawTask::poll` and dropped at
+// the end of each poll; scheduling the task again adds a new one
 const INITIAL_STATE: usize = (REF_ONE * 2) | SCHEDULED | JOIN_HANDLE;
 
 pub(crate) struct State {
@@ -74,6 +90,7 @@ impl State {
     }
 
     pub fn ref_decr(&mut self) {
+        debug_assert!(self.ref_count() > 0, "ref count decremented past zero");
         self.state -= REF_ONE;
 
         if let Some(task_id) = self.task_id {
@@ -92,6 +109,10 @@ impl State {
         (self.state & REF_COUNT_MASK) >> REF_COUNT_SHIFT
     }
 
+    pub fn has_join_handle(&self) -> bool {
+        self.state & JOIN_HANDLE == JOIN_HANDLE
+    }
+
     pub fn unset_join_handle(&mut self) {
         self.state &= !JOIN_HANDLE;
     }
@@ -100,6 +121,10 @@ impl State {
         self.state |= JOIN_WAKER;
     }
 
+    pub fn unset_join_waker(&mut self) {
+        self.state &= !JOIN_WAKER;
+    }
+
     pub fn has_join_waker(&self) -> bool {
         self.state & JOIN_WAKER == JOIN_WAKER
     }
@@ -124,6 +149,26 @@ impl State {
         self.state &= !SCHEDULED;
     }
 
+    pub fn is_cancelled(&self) -> bool {
+        self.state & CANCELLED == CANCELLED
+    }
+
+    pub fn set_cancelled(&mut self) {
+        self.state |= CANCELLED;
+    }
+
+    pub fn is_notified(&self) -> bool {
+        self.state & NOTIFIED == NOTIFIED
+    }
+
+    pub fn set_notified(&mut self) {
+        self.state |= NOTIFIED;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state & RUNNING == RUNNING
+    }
+
     pub fn set_running(&mut self) {
         self.state |= RUNNING;
     }
@@ -135,6 +180,7 @@ impl State {
     pub fn transition_to_complete(&mut self) {
         self.set_complete();
         self.unset_running();
+        self.state &= !NOTIFIED;
         if let Some(task_id) = self.task_id {
             tracing::debug!(
                 "Task {}: Transitioned to complete. State: {}",
@@ -163,6 +209,7 @@ impl State {
     pub fn transition_to_scheduled(&mut self) {
         self.set_scheduled();
         self.unset_running();
+        self.state &= !NOTIFIED;
         if let Some(task_id) = self.task_id {
             tracing::debug!(
                 "Task {}: Transitioned to scheduled. State: {}",
@@ -173,23 +220,190 @@ impl State {
     }
 }
 
+/// The lifecycle bits of [`State`], for tasks that are woken and polled on
+/// several threads
+///
+/// Every transition is a compare-and-swap loop, so a task woken from two
+/// threads at once is only queued by one of them, and a task woken while
+/// it is polled on one thread is requeued by that thread rather than polled
+/// again on another. The reference count isn't kept here, since these tasks
+/// are counted by an `Arc`
+pub(crate) struct AtomicState(AtomicUsize);
+
+impl AtomicState {
+    /// A task that is about to be queued for the first time, with a join
+    /// handle
+    pub fn new() -> AtomicState {
+        AtomicState(AtomicUsize::new(SCHEDULED | JOIN_HANDLE))
+    }
+
+    // Applies `f` until the state is swapped in, or `f` returns `None`.
+    // Returns whether it was swapped in
+    fn update(&self, mut f: impl FnMut(usize) -> Option<usize>) -> bool {
+        let mut curr = self.0.load(Ordering::Acquire);
+        loop {
+            let next = match f(curr) {
+                Some(next) => next,
+                None => return false,
+            };
+            match self
+                .0
+                .compare_exchange_weak(curr, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return true,
+                Err(actual) => curr = actual,
+            }
+        }
+    }
+
+    /// Marks the task as woken. Returns true if the caller should queue it,
+    /// which is when it isn't already queued, complete or being polled. A
+    /// task woken while it's polled is only marked as notified, and is
+    /// requeued by [`transition_to_idle`](Self::transition_to_idle)
+    pub fn transition_to_notified(&self) -> bool {
+        let mut queue = false;
+        self.update(|curr| {
+            if curr & (SCHEDULED | COMPLETE | NOTIFIED) != 0 {
+                return None;
+            }
+            queue = curr & RUNNING == 0;
+            if queue {
+                Some(curr | SCHEDULED)
+            } else {
+                Some(curr | NOTIFIED)
+            }
+        });
+        queue
+    }
+
+    /// Takes the task off the queue to poll it. Returns false if it completed
+    /// while it was queued, e.g. because the runtime shut it down, in which
+    /// case it isn't polled
+    pub fn transition_to_running(&self) -> bool {
+        self.update(|curr| {
+            debug_assert!(curr & RUNNING == 0, "task polled on two threads");
+            if curr & COMPLETE == COMPLETE {
+                return None;
+            }
+            Some((curr | RUNNING) & !SCHEDULED)
+        })
+    }
+
+    /// Called once a poll returns pending. Returns true if the task was woken
+    /// while it was polled, in which case it's marked as scheduled and the
+    /// caller must queue it again
+    pub fn transition_to_idle(&self) -> bool {
+        let mut notified = false;
+        self.update(|curr| {
+            notified = curr & NOTIFIED == NOTIFIED;
+            let next = curr & !(RUNNING | NOTIFIED);
+            if notified {
+                Some(next | SCHEDULED)
+            } else {
+                Some(next)
+            }
+        });
+        notified
+    }
+
+    /// Called once the task has completed and its output is stored. Returns
+    /// whether the join handle still exists to take the output
+    pub fn transition_to_complete(&self) -> bool {
+        let mut join_handle = false;
+        self.update(|curr| {
+            join_handle = curr & JOIN_HANDLE == JOIN_HANDLE;
+            Some((curr | COMPLETE) & !(RUNNING | SCHEDULED | NOTIFIED))
+        });
+        join_handle
+    }
+
+    /// Marks the task as aborted. Returns true if the caller should queue
+    /// it, so its next poll drops the future. Aborting a complete or already
+    /// aborted task does nothing
+    pub fn transition_to_cancelled(&self) -> bool {
+        let mut queue = false;
+        self.update(|curr| {
+            if curr & (COMPLETE | CANCELLED) != 0 {
+                return None;
+            }
+            queue = curr & (SCHEDULED | RUNNING) == 0;
+            if curr & RUNNING == RUNNING {
+                Some(curr | CANCELLED | NOTIFIED)
+            } else {
+                Some(curr | CANCELLED | SCHEDULED)
+            }
+        });
+        queue
+    }
+
+    /// Called as the join handle is dropped. Returns whether the task had
+    /// completed, in which case the caller drops the output
+    pub fn unset_join_handle(&self) -> bool {
+        let prev = self.0.fetch_and(!JOIN_HANDLE, Ordering::AcqRel);
+        prev & COMPLETE == COMPLETE
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.0.load(Ordering::Acquire) & COMPLETE == COMPLETE
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire) & CANCELLED == CANCELLED
+    }
+}
+
 impl std::fmt::Display for State {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // scheduled | running | complete | join handle | join waker | ref count
+        // scheduled | running | notified | complete | join handle | join waker | cancelled | ref count
         let scheduled = self.is_scheduled();
-        let running = self.state & RUNNING == RUNNING;
+        let notified = self.is_notified();
+        let running = self.is_running();
         let complete = self.is_complete();
-        let join_handle = self.state & JOIN_HANDLE == JOIN_HANDLE;
+        let join_handle = self.has_join_handle();
         let join_waker = self.has_join_waker();
+        let cancelled = self.is_cancelled();
         let ref_count = self.ref_count();
         write!(
             f,
-            "State {{ scheduled={}, running={}, complete={}, has_join_handle={}, has_join_waker={}, ref_count={} }}",
-            scheduled, running, complete, join_handle, join_waker, ref_count
+            "State {{ scheduled={}, running={}, notified={}, complete={}, has_join_handle={}, has_join_waker={}, cancelled={}, ref_count={} }}",
+            scheduled, running, notified, complete, join_handle, join_waker, cancelled, ref_count
         )
     }
 }
 
+impl std::fmt::Debug for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Decodes the set flags into their names i.e
+        // State { SCHEDULED | JOIN_HANDLE, ref_count: 2 }
+        const FLAGS: [(usize, &str); 7] = [
+            (SCHEDULED, "SCHEDULED"),
+            (RUNNING, "RUNNING"),
+            (NOTIFIED, "NOTIFIED"),
+            (COMPLETE, "COMPLETE"),
+            (JOIN_HANDLE, "JOIN_HANDLE"),
+            (JOIN_WAKER, "JOIN_WAKER"),
+            (CANCELLED, "CANCELLED"),
+        ];
+
+        write!(f, "State {{ ")?;
+        let mut flags = FLAGS
+            .iter()
+            .filter(|(flag, _)| self.state & flag == *flag)
+            .map(|(_, name)| name)
+            .peekable();
+        if flags.peek().is_none() {
+            write!(f, "IDLE")?;
+        }
+        for (i, name) in flags.enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", name)?;
+        }
+        write!(f, ", ref_count: {} }}", self.ref_count())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -213,4 +427,118 @@ mod tests {
         state.ref_decr();
         assert_eq!(state.ref_count(), 1);
     }
+
+    #[test]
+    fn debug_decodes_flags() {
+        let mut state = State::new();
+        assert_eq!(
+            format!("{:?}", state),
+            "State { SCHEDULED | JOIN_HANDLE, ref_count: 2 }"
+        );
+
+        state.transition_to_running();
+        state.set_join_waker();
+        state.set_notified();
+        state.ref_incr();
+        assert_eq!(
+            format!("{:?}", state),
+            "State { RUNNING | NOTIFIED | JOIN_HANDLE | JOIN_WAKER, ref_count: 3 }"
+        );
+
+        state.transition_to_complete();
+        state.unset_join_handle();
+        assert_eq!(
+            format!("{:?}", state),
+            "State { COMPLETE | JOIN_WAKER, ref_count: 3 }"
+        );
+
+        state.state = REF_ONE;
+        assert_eq!(format!("{:?}", state), "State { IDLE, ref_count: 1 }");
+    }
+
+    #[test]
+    fn notified_bit_leaves_ref_count_alone() {
+        // Six flags were taken before NOTIFIED, so the ref count starts at
+        // the eighth bit and still has the rest of the word
+        assert_eq!(REF_COUNT_SHIFT, 7);
+        assert_eq!(REF_COUNT_MASK >> REF_COUNT_SHIFT, usize::MAX >> 7);
+
+        let mut state = State::new();
+        state.transition_to_running();
+        state.set_notified();
+        state.ref_incr();
+        assert!(state.is_notified());
+        assert_eq!(state.ref_count(), 3);
+        // Requeued once the poll returns
+        state.transition_to_scheduled();
+        assert!(!state.is_notified());
+        assert!(state.is_scheduled());
+    }
+
+    #[test]
+    fn atomic_wake_while_running_requeues_once() {
+        let state = AtomicState::new();
+        // Spawned tasks start out queued
+        assert!(!state.transition_to_notified());
+
+        assert!(state.transition_to_running());
+        // Woken twice while polled, from two threads say. Neither queues it
+        assert!(!state.transition_to_notified());
+        assert!(!state.transition_to_notified());
+        // The poll that returns pending does, once
+        assert!(state.transition_to_idle());
+
+        assert!(state.transition_to_running());
+        assert!(!state.transition_to_idle());
+        // Idle, so the first wake queues it and the second doesn't
+        assert!(state.transition_to_notified());
+        assert!(!state.transition_to_notified());
+    }
+
+    #[test]
+    fn atomic_complete_and_cancel() {
+        let state = AtomicState::new();
+        assert!(state.transition_to_running());
+        // Aborted while polled, so the poll that is running requeues it
+        assert!(!state.transition_to_cancelled());
+        assert!(state.is_cancelled());
+        assert!(state.transition_to_idle());
+
+        assert!(state.transition_to_running());
+        assert!(state.transition_to_complete());
+        assert!(state.is_complete());
+        // Nothing is queued or polled once complete
+        assert!(!state.transition_to_notified());
+        assert!(!state.transition_to_running());
+        assert!(!state.transition_to_cancelled());
+        assert!(state.unset_join_handle());
+    }
+
+    #[test]
+    fn atomic_wakes_from_many_threads_queue_once() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Arc;
+
+        let state = Arc::new(AtomicState::new());
+        assert!(state.transition_to_running());
+        assert!(!state.transition_to_idle());
+
+        let queued = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let (state, queued) = (state.clone(), queued.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        if state.transition_to_notified() {
+                            queued.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(queued.load(Ordering::Relaxed), 1);
+    }
 }
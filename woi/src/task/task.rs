@@ -1,3 +1,4 @@
+use std::mem;
 use std::ptr::NonNull;
 
 use super::header::{Header, TaskId};
@@ -7,15 +8,49 @@ pub(crate) struct Task {
 }
 
 impl Task {
+    /// Returns a task holding another reference to the task at `raw`
+    ///
+    /// # Safety
+    ///
+    /// `raw` must point to a task that hasn't been deallocated
+    pub unsafe fn from_raw(raw: NonNull<()>) -> Task {
+        let ptr = raw.as_ptr();
+        let header = ptr as *const Header;
+        ((*header).vtable.clone_reference)(ptr);
+        Task { raw }
+    }
+
     pub fn id(&self) -> TaskId {
         let ptr = self.raw.as_ptr();
         let header = ptr as *const Header;
         unsafe { (*header).id }
     }
 
+    /// Marks the task as aborted, so running it drops its future
+    pub fn abort(&self) {
+        let ptr = self.raw.as_ptr();
+        let header = ptr as *const Header;
+        unsafe { ((*header).vtable.abort)(ptr) }
+    }
+
     pub fn run(self) {
         let ptr = self.raw.as_ptr();
         let header = ptr as *const Header;
+        // Polling takes over the task's reference
+        mem::forget(self);
         unsafe { ((*header).vtable.poll)(ptr) }
     }
 }
+
+// A task dropped without being run, e.g. while shutting down, releases its
+// reference. The task is deallocated, dropping its future, if it was the last
+impl Drop for Task {
+    fn drop(&mut self) {
+        let ptr = self.raw.as_ptr();
+        let header = ptr as *const Header;
+        unsafe {
+            tracing::debug!("Task {}: Dropping unrun task", (*header).id);
+            ((*header).vtable.drop_reference)(ptr)
+        }
+    }
+}
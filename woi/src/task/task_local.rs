@@ -0,0 +1,213 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::ptr;
+
+thread_local! {
+    // Task-local values of the task currently being polled. Points into the
+    // header of the task, so it is only valid while the guard lives
+    static CURRENT: Cell<*const TaskLocals> = const { Cell::new(ptr::null()) };
+}
+
+/// Declares task-local keys
+///
+/// Each key holds a separate value for every task, set with
+/// [`LocalKey::set`]. Futures awaited by the task, however deeply nested,
+/// read the value of the task they're running in
+///
+/// ```
+/// woi::task_local! {
+///     static REQUEST_ID: u64;
+/// }
+///
+/// async fn handle() -> Option<u64> {
+///     REQUEST_ID.get()
+/// }
+///
+/// let rt = woi::Runtime::new();
+/// rt.block_on(async {
+///     let id = woi::spawn(async {
+///         REQUEST_ID.set(7);
+///         handle().await
+///     });
+///     assert_eq!(id.await.unwrap(), Some(7));
+/// });
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty;)+) => {$(
+        $(#[$attr])*
+        $vis static $name: $crate::task::LocalKey<$ty> = $crate::task::LocalKey::new();
+    )+};
+}
+
+/// The values of every task-local key for a task. Lives in the task's
+/// header, so the values are dropped when the task is deallocated
+#[derive(Default)]
+pub(crate) struct TaskLocals {
+    /// Values, by the address of their key. Tasks rarely have more than a
+    /// couple of keys set, so a search is cheaper than hashing
+    values: RefCell<Vec<(*const (), Box<dyn Any>)>>,
+}
+
+pub(crate) struct EnterGuard {
+    prev: *const TaskLocals,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.prev));
+    }
+}
+
+/// Makes `locals` the task-local values read by keys until the returned
+/// guard is dropped. The guard must be dropped before `locals` is
+pub(crate) fn enter(locals: &TaskLocals) -> EnterGuard {
+    let prev = CURRENT.with(|current| current.replace(locals));
+    EnterGuard { prev }
+}
+
+// Runs `f` with the values of the task being polled, if there is one
+fn with_current<R>(f: impl FnOnce(Option<&TaskLocals>) -> R) -> R {
+    CURRENT.with(|current| {
+        // Safety: the pointer is only set while the values it points to are
+        // alive, see `enter`
+        let locals = unsafe { current.get().as_ref() };
+        f(locals)
+    })
+}
+
+/// A key for task-local values. Declared with [`task_local!`]
+///
+/// [`task_local!`]: crate::task_local
+pub struct LocalKey<T: 'static> {
+    // Values are found by the address of their key. The key isn't zero-sized
+    // so that every key static has an address of its own
+    _unique: u8,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new() -> LocalKey<T> {
+        LocalKey {
+            _unique: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    fn id(&'static self) -> *const () {
+        self as *const LocalKey<T> as *const ()
+    }
+
+    /// Sets the value of the key for the current task, returning the value
+    /// it replaced
+    ///
+    /// Panics if called outside of a task, including from the future passed
+    /// to `block_on`, or from within [`with`](LocalKey::with)
+    pub fn set(&'static self, value: T) -> Option<T> {
+        with_current(|locals| {
+            let locals = locals.expect("task-local set outside of a task");
+            let mut values = locals.values.borrow_mut();
+            let slot = values.iter_mut().find(|(id, _)| *id == self.id());
+            match slot {
+                Some((_, prev)) => {
+                    let prev = std::mem::replace(prev, Box::new(value));
+                    prev.downcast().ok().map(|prev| *prev)
+                }
+                None => {
+                    values.push((self.id(), Box::new(value)));
+                    None
+                }
+            }
+        })
+    }
+
+    /// Runs `f` with the value of the key for the current task. The value is
+    /// `None` if it was never set, or when called outside of a task
+    pub fn with<R>(&'static self, f: impl FnOnce(Option<&T>) -> R) -> R {
+        with_current(|locals| {
+            let values = locals.map(|locals| locals.values.borrow());
+            let value = values.as_ref().and_then(|values| {
+                values
+                    .iter()
+                    .find(|(id, _)| *id == self.id())
+                    .and_then(|(_, value)| value.downcast_ref())
+            });
+            f(value)
+        })
+    }
+
+    /// Returns a clone of the value of the key for the current task
+    pub fn get(&'static self) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.with(|value| value.cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Runtime;
+
+    crate::task_local! {
+        static NAME: &'static str;
+    }
+
+    async fn nested() -> Option<&'static str> {
+        crate::task::yield_now().await;
+        NAME.get()
+    }
+
+    #[test]
+    fn concurrent_tasks_see_their_own_values() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            assert_eq!(NAME.get(), None);
+
+            let handles: Vec<_> = ["a", "b"]
+                .iter()
+                .map(|&name| {
+                    crate::spawn(async move {
+                        assert_eq!(NAME.get(), None);
+                        NAME.set(name);
+                        // Both tasks have set their value by the time either
+                        // reads it back
+                        let seen = nested().await;
+                        assert_eq!(NAME.set("replaced"), Some(name));
+                        seen
+                    })
+                })
+                .collect();
+
+            let mut seen = Vec::new();
+            for handle in handles {
+                seen.push(handle.await.unwrap());
+            }
+            assert_eq!(seen, vec![Some("a"), Some("b")]);
+            assert_eq!(NAME.get(), None);
+        });
+    }
+
+    #[test]
+    fn values_dropped_with_task() {
+        use std::rc::Rc;
+
+        crate::task_local! {
+            static SHARED: Rc<()>;
+        }
+
+        let value = Rc::new(());
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let shared = value.clone();
+            crate::spawn(async move {
+                SHARED.set(shared);
+            })
+            .await
+            .unwrap();
+        });
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
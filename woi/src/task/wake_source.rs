@@ -0,0 +1,104 @@
+#[cfg(debug_assertions)]
+use std::cell::Cell;
+use std::task::Waker;
+
+/// What triggered a task's most recent wake
+///
+/// In debug builds, each task records the source of its last wake and
+/// includes it in the tracing span of its polls, which helps to debug why a
+/// task woke up
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WakeSource {
+    /// A sleep or timeout firing
+    Timer,
+    /// An IO resource becoming ready
+    Io,
+    /// A channel or semaphore the task is waiting on
+    Channel,
+    /// Anything else, e.g. a waker woken by hand
+    #[default]
+    Manual,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    // Source of the wake in progress. Tasks read it when woken
+    static SOURCE: Cell<WakeSource> = const { Cell::new(WakeSource::Manual) };
+}
+
+/// Wakes `waker`, tagging `source` as what woke the task
+pub(crate) fn wake(waker: Waker, source: WakeSource) {
+    with_source(source, || waker.wake())
+}
+
+/// Wakes `waker` without consuming it, tagging `source` as what woke the task
+pub(crate) fn wake_by_ref(waker: &Waker, source: WakeSource) {
+    with_source(source, || waker.wake_by_ref())
+}
+
+/// The source of the wake in progress. Wakes that weren't tagged are manual
+#[cfg(debug_assertions)]
+pub(crate) fn current() -> WakeSource {
+    SOURCE.with(|current| current.get())
+}
+
+#[cfg(debug_assertions)]
+fn with_source(source: WakeSource, wake: impl FnOnce()) {
+    let prev = SOURCE.with(|current| current.replace(source));
+    wake();
+    SOURCE.with(|current| current.set(prev));
+}
+
+#[cfg(not(debug_assertions))]
+fn with_source(_: WakeSource, wake: impl FnOnce()) {
+    wake()
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use crate::task::header::Header;
+    use crate::task::JoinHandle;
+    use crate::Runtime;
+    use std::time::Duration;
+
+    fn last_wake_source<T>(handle: &JoinHandle<T>) -> WakeSource {
+        let header = handle.raw.as_ptr() as *const Header;
+        unsafe { (*header).wake_source }
+    }
+
+    #[test]
+    fn timer_woken_task_records_timer() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let mut handle = crate::spawn(crate::time::sleep(Duration::from_millis(10)));
+            (&mut handle).await.unwrap();
+            assert_eq!(last_wake_source(&handle), WakeSource::Timer);
+        });
+    }
+
+    #[test]
+    fn channel_and_manual_wakes_are_recorded() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = crate::channel::oneshot::channel();
+            let mut handle = crate::spawn(rx);
+            crate::spawn(async {}).await.unwrap();
+            tx.send(1).unwrap();
+            assert_eq!(last_wake_source(&handle), WakeSource::Channel);
+            (&mut handle).await.unwrap().unwrap();
+
+            let mut woken = false;
+            let mut handle = crate::spawn(futures::future::poll_fn(move |cx| {
+                if woken {
+                    return std::task::Poll::Ready(());
+                }
+                woken = true;
+                cx.waker().wake_by_ref();
+                std::task::Poll::Pending
+            }));
+            (&mut handle).await.unwrap();
+            assert_eq!(last_wake_source(&handle), WakeSource::Manual);
+        });
+    }
+}
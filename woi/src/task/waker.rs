@@ -0,0 +1,80 @@
+use std::cell::Cell;
+use std::ptr;
+use std::task::Waker;
+
+thread_local! {
+    // Waker of the task currently being polled. Points to the waker on the
+    // stack of `RawTask::poll`, so it is only valid while the guard lives
+    static CURRENT: Cell<*const Waker> = const { Cell::new(ptr::null()) };
+}
+
+pub(crate) struct EnterGuard {
+    prev: *const Waker,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.prev));
+    }
+}
+
+/// Sets `waker` as the waker of the task being polled until the returned
+/// guard is dropped. The guard must be dropped before `waker` is
+pub(crate) fn enter(waker: &Waker) -> EnterGuard {
+    let prev = CURRENT.with(|current| current.replace(waker));
+    EnterGuard { prev }
+}
+
+/// Returns a clone of the waker of the task currently being polled
+///
+/// Leaf futures can use this instead of threading the `Context` through
+/// deep call stacks. Returns `None` when called outside of a task's poll,
+/// including from the future passed to `block_on`
+pub fn waker() -> Option<Waker> {
+    CURRENT.with(|current| {
+        let waker = current.get();
+        // Safety: the pointer is only set while the waker it points to is
+        // alive, see `enter`
+        unsafe { waker.as_ref().cloned() }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    // Pending the first time it is polled. It wakes itself through the
+    // waker from `waker()` rather than the one from `cx`
+    struct WakeThroughCurrent(bool);
+
+    impl Future for WakeThroughCurrent {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+
+            let waker = waker().expect("no waker inside task poll");
+            assert!(waker.will_wake(cx.waker()));
+            waker.wake();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn waker_inside_task() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            assert!(waker().is_none());
+            crate::spawn(WakeThroughCurrent(false)).await.unwrap();
+            assert!(waker().is_none());
+        });
+        assert!(waker().is_none());
+    }
+}
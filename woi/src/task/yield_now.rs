@@ -0,0 +1,75 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Future returned by [`yield_now`]
+pub struct YieldNow {
+    yielded: bool,
+}
+
+/// Yields execution back to the runtime
+///
+/// The task is woken straight away, which puts it at the back of the run
+/// queue, so every task queued before it runs first. Tasks doing CPU-bound
+/// work in a loop can await this now and then to let others make progress
+///
+/// Yielding also refills the task's coop budget. Resources like channel
+/// receivers make a task yield once it has made progress on them too many
+/// times in a row, and a task that yields of its own accord starts over
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+
+        self.yielded = true;
+        // The task gives up its turn, so it gets a full budget for the next
+        crate::task::coop::reset();
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn yielding_tasks_interleave() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let handles: Vec<_> = ["a", "b"]
+                .iter()
+                .map(|&name| {
+                    let log = log.clone();
+                    crate::spawn(async move {
+                        for i in 0..3 {
+                            log.borrow_mut().push((name, i));
+                            yield_now().await;
+                        }
+                    })
+                })
+                .collect();
+
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+
+        assert_eq!(
+            *log.borrow(),
+            vec![("a", 0), ("b", 0), ("a", 1), ("b", 1), ("a", 2), ("b", 2)]
+        );
+    }
+}
@@ -1,4 +1,6 @@
 mod sleep;
+mod timeout;
 mod timer;
 
-pub use sleep::sleep;
+pub use sleep::{sleep, Sleep};
+pub use timeout::{timeout, Elapsed, Timeout};
@@ -1,24 +1,93 @@
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::ready;
 
 use super::timer::Timer;
+use crate::io::epoll::Interest;
+use crate::io::io_source::Direction;
 use crate::io::pollable::Pollable;
+use crate::io::reactor::Handle;
+use crate::task::WakeSource;
 
 // Future that is returned from a call to `sleep`
 pub struct Sleep {
     inner: Pollable<Timer>,
+    deadline: Instant,
+    /// Handle to the reactor the sleep's deadline is registered with
+    handle: Handle,
+    /// Key of the deadline in the reactor. Only set while the sleep is pending
+    key: Option<usize>,
 }
 
 impl Sleep {
     fn until(duration: Duration) -> Sleep {
         // TODO: Saner error handling
+        let handle = Handle::current_time();
+        let deadline = Instant::now() + duration;
         let timer = Timer::new(duration).unwrap();
-        let inner = Pollable::new(timer).unwrap();
-        Sleep { inner }
+        let inner = Pollable::new_with_handle(timer, Interest::READABLE, handle.clone()).unwrap();
+        inner.set_wake_source(WakeSource::Timer);
+        let key = Some(handle.inner.add_deadline(deadline));
+        Sleep {
+            inner,
+            deadline,
+            handle,
+            key,
+        }
+    }
+
+    /// The instant at which the sleep completes
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Reschedules the sleep to complete at `deadline`
+    ///
+    /// The underlying timer is re-armed in place, so no new timer is
+    /// created. If the sleep has already completed, it becomes pending
+    /// again until the new deadline
+    ///
+    /// In a loop, pin one sleep outside of the loop and reset it instead
+    /// of creating a new sleep every iteration:
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use futures::future::{select, Either};
+    ///
+    /// let rt = woi::Runtime::new();
+    /// rt.block_on(async {
+    ///     let (tx, rx) = woi::channel::unbounded::channel();
+    ///     tx.send("ping").unwrap();
+    ///
+    ///     let idle = woi::time::sleep(Duration::from_millis(10));
+    ///     woi::pin!(idle);
+    ///     loop {
+    ///         match select(idle.as_mut(), Box::pin(rx.recv())).await {
+    ///             Either::Left(_) => break,
+    ///             Either::Right(_) => idle.as_mut().reset(Instant::now() + Duration::from_millis(10)),
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn reset(self: Pin<&mut Self>, deadline: Instant) {
+        let this = self.get_mut();
+        this.deadline = deadline;
+
+        let duration = deadline.saturating_duration_since(Instant::now());
+        // TODO: Improve error handling
+        if let Err(e) = this.inner.get_ref().set(duration) {
+            panic!("timer error: {}", e)
+        }
+        // Forget that the previous expiry fired, if it did
+        this.inner.clear_readiness(Direction::Read);
+
+        match this.key {
+            Some(key) => this.handle.inner.deadlines.borrow_mut()[key] = deadline,
+            None => this.key = Some(this.handle.inner.add_deadline(deadline)),
+        }
     }
 }
 
@@ -26,10 +95,28 @@ impl Future for Sleep {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        // TODO: Improve error handling
-        match ready!(self.inner.poll_readable(cx)) {
-            Ok(()) => Poll::Ready(()),
-            Err(e) => panic!("timer error: {}", e),
+        let this = self.get_mut();
+        // A deadline that has already passed completes without waiting on the
+        // reactor to report the timer. This is what lets `sleep(Duration::ZERO)`
+        // complete on its first poll
+        if this.deadline > Instant::now() {
+            // TODO: Improve error handling
+            if let Err(e) = ready!(this.inner.poll_readable(cx)) {
+                panic!("timer error: {}", e)
+            }
+        }
+
+        if let Some(key) = this.key.take() {
+            this.handle.inner.remove_deadline(key);
+        }
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.handle.inner.remove_deadline(key);
         }
     }
 }
@@ -37,3 +124,152 @@ impl Future for Sleep {
 pub fn sleep(duration: Duration) -> Sleep {
     Sleep::until(duration)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn zero_sleep_is_ready_on_first_poll() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let zero = sleep(Duration::ZERO);
+            crate::pin!(zero);
+            assert!(futures::poll!(zero.as_mut()).is_ready());
+
+            // A past deadline is the same as a zero sleep
+            let past = sleep(Duration::from_secs(1));
+            crate::pin!(past);
+            past.as_mut()
+                .reset(Instant::now() - Duration::from_millis(1));
+            assert!(futures::poll!(past.as_mut()).is_ready());
+        });
+    }
+
+    #[test]
+    fn sleeps_fire_in_deadline_order() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let start = Instant::now();
+            let fired = Rc::new(RefCell::new(Vec::new()));
+
+            // Spawned out of order so the queue order can't decide the result.
+            // Sleeps that expire in the same turn of the reactor are woken in
+            // whatever order epoll reports them, so the deadlines are far
+            // enough apart for each to get its own turn on a busy machine
+            let handles: Vec<_> = [100, 50, 200, 150]
+                .iter()
+                .map(|&ms| {
+                    let fired = fired.clone();
+                    crate::spawn(async move {
+                        sleep(Duration::from_millis(ms)).await;
+                        fired.borrow_mut().push((ms, start.elapsed()));
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+
+            let fired = fired.borrow();
+            let order: Vec<_> = fired.iter().map(|(ms, _)| *ms).collect();
+            assert_eq!(order, vec![50, 100, 150, 200]);
+            // Sleeps can fire late on a busy machine, but never early
+            for (ms, elapsed) in fired.iter() {
+                let deadline = Duration::from_millis(*ms);
+                assert!(*elapsed >= deadline, "{}ms sleep took {:?}", ms, elapsed);
+            }
+        });
+    }
+
+    #[test]
+    fn reset_keeps_sleep_from_firing() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let start = Instant::now();
+            let timeout = Duration::from_millis(50);
+
+            let idle = sleep(timeout);
+            crate::pin!(idle);
+            for _ in 0..3 {
+                sleep(Duration::from_millis(20)).await;
+                assert!(futures::poll!(idle.as_mut()).is_pending());
+                idle.as_mut().reset(Instant::now() + timeout);
+            }
+
+            idle.await;
+            assert!(start.elapsed() >= Duration::from_millis(110));
+        });
+    }
+
+    #[test]
+    fn reset_after_firing() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let fired = sleep(Duration::from_millis(10));
+            crate::pin!(fired);
+            fired.as_mut().await;
+
+            let start = Instant::now();
+            fired.as_mut().reset(start + Duration::from_millis(30));
+            assert!(futures::poll!(fired.as_mut()).is_pending());
+
+            fired.await;
+            assert!(start.elapsed() >= Duration::from_millis(30));
+        });
+    }
+
+    #[test]
+    fn reset_in_loop_reuses_timer() {
+        use crate::channel::unbounded;
+        use futures::future::{select, Either};
+
+        // Timers registered with the current reactor, and pending deadlines
+        fn timer_counts() -> (usize, usize) {
+            let io = crate::runtime::context::io();
+            let sources = io.inner.sources.borrow().len();
+            let deadlines = io.inner.deadlines.borrow().len();
+            (sources, deadlines)
+        }
+
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let (tx, rx) = unbounded::channel();
+            for i in 0..500 {
+                tx.send(i).unwrap();
+            }
+
+            let idle = sleep(Duration::from_millis(20));
+            crate::pin!(idle);
+            let counts = timer_counts();
+
+            let mut received = 0;
+            loop {
+                match select(idle.as_mut(), Box::pin(rx.recv())).await {
+                    Either::Left(_) => break,
+                    Either::Right(_) => {
+                        received += 1;
+                        idle.as_mut()
+                            .reset(Instant::now() + Duration::from_millis(20));
+                    }
+                }
+                assert_eq!(timer_counts(), counts);
+            }
+            assert_eq!(received, 500);
+        });
+
+        // Dropped sleeps release their registration
+        rt.block_on(async {
+            let counts = timer_counts();
+            for _ in 0..100 {
+                let sleep = sleep(Duration::from_secs(1));
+                drop(sleep);
+            }
+            assert_eq!(timer_counts(), counts);
+        });
+    }
+}
@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use super::sleep::{sleep, Sleep};
+
+/// Future returned by [`timeout`]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+/// Error returned when a [`Timeout`] elapses before its future completes
+#[derive(Debug, PartialEq, Eq)]
+pub struct Elapsed(());
+
+/// Requires `future` to complete within `duration`
+///
+/// Resolves to the future's output, or to [`Elapsed`] if `duration` passes
+/// first, in which case the future is dropped along with the `Timeout`. A
+/// future that is ready on its first poll always completes, even with a
+/// zero duration
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the future is pinned along with `Timeout` and is never moved
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed(()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// ===== impl Elapsed =====
+
+impl Error for Elapsed {}
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn timeout_completes_or_elapses() {
+        let rt = Runtime::new();
+        rt.block_on(async {
+            let res = timeout(Duration::from_millis(100), async { 3 }).await;
+            assert_eq!(res, Ok(3));
+
+            let slow = sleep(Duration::from_secs(10));
+            let res = timeout(Duration::from_millis(10), slow).await;
+            assert_eq!(res, Err(Elapsed(())));
+        });
+    }
+}
@@ -14,10 +14,27 @@ impl Timer {
     /// duration has elapsed
     pub fn new(duration: Duration) -> io::Result<Timer> {
         let fd = timerfd::create()?;
+        let timer = Timer { fd };
+        timer.set(duration)?;
+
+        Ok(timer)
+    }
+
+    /// Re-arms the timer to fire after the length of duration has
+    /// elapsed, replacing the previous expiry. This also resets the
+    /// timer if it has already fired
+    pub fn set(&self, duration: Duration) -> io::Result<()> {
+        // A zero expiry disarms a timerfd, so the shortest timer we can
+        // set is a nanosecond
+        let duration = duration.max(Duration::from_nanos(1));
         let spec = IntervalTimerSpec::new(duration);
-        timerfd::set(fd, spec)?;
+        timerfd::set(self.fd, spec)
+    }
+}
 
-        Ok(Timer { fd })
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let _ = timerfd::close(self.fd);
     }
 }
 
@@ -81,7 +98,6 @@ mod timerfd {
         Ok(spec)
     }
 
-    #[allow(unused)]
     pub(super) fn close(fd: RawFd) -> io::Result<()> {
         cvt(unsafe { libc::close(fd) })?;
         Ok(())
@@ -106,7 +122,6 @@ mod tests {
         let duration = Duration::from_secs(3);
         let timer = Timer::new(duration);
         assert!(timer.is_ok());
-        let _ = timerfd::close(timer.unwrap().fd);
     }
 
     #[test]
@@ -125,7 +140,5 @@ mod tests {
         // test will evaluate within a time period that this is still 2 (instead of lower)
         assert_eq!(2, sec);
         assert!(remaining_duration < duration);
-
-        let _ = timerfd::close(timer.fd);
     }
 }
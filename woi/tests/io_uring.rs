@@ -0,0 +1,45 @@
+#![cfg(feature = "io-uring")]
+
+use std::net::SocketAddr;
+
+use woi::io::{AsyncReadExt, AsyncWriteExt};
+use woi::net::{TcpListener, TcpStream};
+use woi::runtime::Builder;
+
+#[test]
+fn socket_echo_through_io_uring() {
+    let rt = Builder::new().enable_all().io_uring().build().unwrap();
+    rt.block_on(async {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = woi::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            loop {
+                // The read is submitted before the client has written, so
+                // it's the kernel that waits for the data
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                stream.write_all(&buf[..n]).await.unwrap();
+            }
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        for msg in [&b"hello"[..], b"io_uring", &[7; 1000]] {
+            client.write_all(msg).await.unwrap();
+            let mut echoed = vec![0; msg.len()];
+            client.read_exact(&mut echoed).await.unwrap();
+            assert_eq!(echoed, msg);
+        }
+        client.close().await.unwrap();
+        server.await.unwrap();
+    });
+
+    // Every read and write of both ends completed through the ring
+    assert!(rt.metrics().io_uring_completions >= 12);
+}